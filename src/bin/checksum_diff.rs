@@ -0,0 +1,36 @@
+use std::process;
+
+use trading::engin::{read_checksums, first_divergence, StateChecksum};
+
+// 比较两次运行由 ChecksumFile 写出的摘要, 报告第一个状态不一致的输入序号
+fn usage() -> ! {
+    eprintln!("usage: checksum_diff FILE FILE");
+    process::exit(2);
+}
+
+fn read(path : &str) -> Vec<StateChecksum> {
+    read_checksums(path).unwrap_or_else(|e| {
+        eprintln!("read {} failed: {}", path, e);
+        process::exit(2);
+    })
+}
+
+fn main() {
+    let args : Vec<String> = std::env::args().skip(1).collect();
+    let (a, b) = match args.as_slice() {
+        [a, b] => (read(a), read(b)),
+        _ => usage(),
+    };
+    match first_divergence(&a, &b) {
+        Some((x, y)) => {
+            println!("diverged at seq_num {} ({})", x.seq_num, x.diverged_stages(&y).join(", "));
+            println!("  {}", x.to_line());
+            println!("  {}", y.to_line());
+            process::exit(1);
+        },
+        None => {
+            let common = a.iter().filter(|x| b.binary_search_by_key(&x.seq_num, |y| y.seq_num).is_ok()).count();
+            println!("no divergence in {} common checkpoints", common);
+        },
+    }
+}
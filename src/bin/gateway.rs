@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::process;
+use std::sync::Arc;
+use std::thread;
+
+use trading::engin::Engin;
+use trading::gateway::{Gateway, GatewayConfig};
+use trading::report_log::ReportLog;
+use trading::journal::SyncPolicy;
+use trading::types::*;
+
+fn usage() -> ! {
+    eprintln!("usage: gateway --listen ADDR --pbu PBUID=PASSWORD [--pbu PBUID=PASSWORD ...] [--report-log PATH] [--stream SECURITY=STREAM ...]");
+    process::exit(2);
+}
+
+fn parse_args() -> (String, GatewayConfig) {
+    let mut listen = None;
+    let mut credentials = HashMap::new();
+    let mut report_log = None;
+    let mut streams = HashMap::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--listen", Some(addr)) => listen = Some(addr),
+            ("--pbu", Some(credential)) => match credential.split_once('=') {
+                Some((pbu_id, password)) if !pbu_id.is_empty() && pbu_id.len() <= 6 => {
+                    credentials.insert(to_array::<6>(pbu_id), password.to_string());
+                },
+                _ => usage(),
+            },
+            ("--report-log", Some(path)) => report_log = Some(ReportLog::open(&path, SyncPolicy::PerBatch).unwrap_or_else(|e| {
+                eprintln!("open {} failed: {}", path, e);
+                process::exit(1);
+            })),
+            ("--stream", Some(stream)) => match stream.split_once('=').map(|(security_id, id)| (security_id, id.parse())) {
+                Some((security_id, Ok(id))) if !security_id.is_empty() && security_id.len() <= 8 => {
+                    streams.insert(to_array::<8>(security_id), id);
+                },
+                _ => usage(),
+            },
+            _ => usage(),
+        }
+    }
+    match listen {
+        Some(listen) if !credentials.is_empty() => (listen, GatewayConfig { credentials, report_log, streams }),
+        _ => usage(),
+    }
+}
+
+fn main() {
+    let (listen, config) = parse_args();
+    let gateway = Arc::new(Gateway::bind(&listen, config).unwrap_or_else(|e| {
+        eprintln!("bind {} failed: {}", listen, e);
+        process::exit(1);
+    }));
+    println!("listening on {}, enter \"quit\" to stop", gateway.local_addr().unwrap());
+
+    // 不等待读 stdin 的线程: 引擎出错时 run 直接返回, 进程退出
+    let stopper = gateway.clone();
+    thread::spawn(move || {
+        let stdin = std::io::stdin();
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(line) if line.trim() != "quit" => continue,
+                _ => break,
+            }
+        }
+        stopper.stop();
+    });
+
+    let mut engin = Engin::new(gateway.sink());
+    let result = gateway.run(&engin);
+    if let Err(e) = result.and(engin.close().map(|_| ())) {
+        eprintln!("engine stopped: {}", e);
+        process::exit(1);
+    }
+}
@@ -1,106 +1,458 @@
+mod sequencer;
 mod pre_processor;
 mod rc_processor;
 mod core_processor;
 mod trading_session;
 mod exe_processor;
+mod validator;
+mod error;
+mod supervisor;
+mod config;
+mod ingress;
+mod recovery;
+mod snapshot;
+mod checksum;
+mod md_processor;
 
-use std::sync::mpsc::{channel, Sender};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::iter;
 use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
+use crate::types::SeqNum;
 use crate::messages::*;
-use crate::types::ExeSender;
+use crate::report_sink::{ReportSink, CountingSink};
+use crate::recorder::InputRecorder;
+use crate::ring_buffer::{ring_buffer, QueueMonitor};
+use crate::pool::{pool, PoolStats};
+use crate::market_data::DepthSnapshots;
 
+pub use self::error::*;
+pub use self::config::{EnginConfig, BackpressurePolicy, ReportFormat};
+pub use self::ingress::Ingress;
+pub use self::recovery::Recovery;
+pub use crate::messages::{Snapshot, StateChecksum};
+pub use self::snapshot::{SnapshotStore, SNAPSHOT_VERSION};
+pub use self::checksum::{StateHasher, RollingHash, ChecksumLog, ChecksumFile, state_hash, read_checksums, first_divergence};
+use self::ingress::IngressTask;
+use self::supervisor::Supervisor;
 use self::exe_processor::{ExeProcessor};
+use self::sequencer::Sequencer;
 use self::pre_processor::PreProcessor;
 use self::rc_processor::RcProcessor;
 use self::core_processor::CoreProcessor;
+use self::md_processor::MdProcessor;
 
-pub struct Engin {
-    pub engin_tx : Sender<Option<PreProcessorTask>>,
+// 组提交一批最多包含的任务数, 入口队列一直不空时也按此数量 flush, 下游不会一直等待
+const MAX_GROUP_COMMIT : usize = 256;
+
+// 某个阶段输入队列的当前深度与历史最大深度
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueStats {
+    pub stage : Stage,
+    pub depth : usize,
+    pub high_watermark : usize,
+    pub capacity : usize,
+}
+
+pub struct Engin<S : ReportSink = CountingSink> {
+    ingress : Ingress,
+    supervisor : Supervisor,
+    monitors : Vec<(Stage, QueueMonitor)>,
+    seq : Option<JoinHandle<()>>,
     pre : Option<JoinHandle<()>>,
     rc : Option<JoinHandle<()>>,
     core : Option<JoinHandle<()>>,
-    exe : Option<JoinHandle<ExeSender>>,
+    exe : Option<JoinHandle<S>>,
+    md : Option<JoinHandle<()>>,
+    depth_snapshots : DepthSnapshots,
+    // 定时生成行情快照的线程
+    md_timer : Option<(Arc<AtomicBool>, JoinHandle<()>)>,
 }
 
-impl Engin {
-    pub fn new(mut sender : ExeSender) -> Engin {
-        let (engin_tx, pre_rx) = channel();
-        let (pre_tx, rc_rx) = channel();
-        let (rc_tx, core_rx) = channel();
-        let (core_tx, exe_rx) = channel();
+impl<S : ReportSink + 'static> Engin<S> {
+    pub fn new(sink : S) -> Engin<S> {
+        Engin::with_config(sink, EnginConfig::default())
+    }
+
+    // 配置不合法时 panic, 见 EnginConfig::validate
+    pub fn with_config(sink : S, config : EnginConfig) -> Engin<S> {
+        Engin::try_with_config(sink, config).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // 先检查配置, 不合法时返回 EngineError::InvalidConfig, 不启动任何阶段
+    pub fn try_with_config(mut sink : S, config : EnginConfig) -> Result<Engin<S>, EngineError> {
+        config.validate()?;
+        let (engin_tx, seq_rx) = ring_buffer(config.ingress_capacity, config.wait_strategy);
+        let (seq_tx, pre_rx) = ring_buffer(config.queue_capacity, config.wait_strategy);
+        let (pre_tx, rc_rx) = ring_buffer(config.queue_capacity, config.wait_strategy);
+        let (rc_tx, core_rx) = ring_buffer(config.queue_capacity, config.wait_strategy);
+        let (core_tx, exe_rx) = ring_buffer(config.queue_capacity, config.wait_strategy);
+        let (md_tx, md_rx) = match config.market_data {
+            Some(sink) => {
+                let (md_tx, md_rx) = ring_buffer(config.queue_capacity, config.wait_strategy);
+                let md_monitor = md_tx.monitor();
+                (Some(md_tx), Some((md_rx, md_monitor, sink)))
+            },
+            None => (None, None),
+        };
+        let (order_pool, order_recycler) = pool(config.pool_capacity);
+        let (cancel_pool, cancel_recycler) = pool(config.pool_capacity);
+
+        let supervisor = Supervisor::new();
+        let seq_supervisor = supervisor.clone();
+        let pre_supervisor = supervisor.clone();
+        let rc_supervisor = supervisor.clone();
+        let core_supervisor = supervisor.clone();
+        let exe_supervisor = supervisor.clone();
+        let md_supervisor = supervisor.clone();
+
+        let rc_price_limits = config.price_limits.clone();
+        let checksum_interval = match config.checksum_log {
+            Some(_) => config.checksum_interval,
+            None => 0,
+        };
+        let mut recorder = config.recorder;
+        let mut recovery = config.recovery;
+        // 序号不超过此值的输入不输出报告; 备用引擎接管时由定序阶段改为最后一条复制输入的序号
+        let suppress_until = match config.standby {
+            true => SeqNum::MAX,
+            false => recovery.as_ref().map(Recovery::suppress_until).unwrap_or(0),
+        };
+        let seq_suppress_until = Arc::new(AtomicU64::new(suppress_until));
+        let exe_suppress_until = seq_suppress_until.clone();
+        let md_suppress_until = seq_suppress_until.clone();
+        // 各阶段从快照中取出自己的部分
+        let (seq_snapshot, pre_snapshot, core_snapshot, exe_snapshot, md_seq_num) = match recovery.as_mut().and_then(|recovery| recovery.snapshot.take()) {
+            Some(snapshot) => (Some((snapshot.seq_num, snapshot.next_order_id)), Some(snapshot.pre), Some(snapshot.core), (snapshot.exec_id, snapshot.notional), snapshot.md_seq_num),
+            None => (None, None, None, (0, BTreeMap::new()), 0),
+        };
+
+        let mut monitors = vec![
+            (Stage::Seq, engin_tx.monitor()),
+            (Stage::Pre, seq_tx.monitor()),
+            (Stage::Rc, pre_tx.monitor()),
+            (Stage::Core, rc_tx.monitor()),
+            (Stage::Exe, core_tx.monitor()),
+        ];
+        let seq_monitor = engin_tx.monitor();
+        let exe_monitor = core_tx.monitor();
+        monitors.extend(md_tx.as_ref().map(|md_tx| (Stage::Md, md_tx.monitor())));
+        let ingress = Ingress::new(engin_tx, supervisor.clone(), config.backpressure, config.standby, order_pool, cancel_pool);
+        let depth_snapshots = DepthSnapshots::new();
+        let md_snapshots = depth_snapshots.clone();
+        let md_timer = match (&md_tx, config.market_data_snapshot_interval) {
+            (Some(_), Some(interval)) => Some(market_data_timer(ingress.clone(), interval)),
+            _ => None,
+        };
 
-        //let mut tmp_sender = sender;
+        Ok(Engin {
+            ingress,
+            supervisor,
+            monitors,
+            seq : Some(thread::spawn(move || {
+                let mut worker = Sequencer::new();
+                worker.set_checksum_interval(checksum_interval);
+                if let Some((seq_num, next_order_id)) = seq_snapshot {
+                    worker.restore(seq_num, next_order_id);
+                }
+                // 先重放崩溃前已定序的输入, 它们已在日志中, 不再记录
+                for task in recovery.into_iter().flat_map(Recovery::tasks) {
+                    worker.resume(&task);
+                    let checkpoint = worker.checkpoint(&task);
+                    if iter::once(task).chain(checkpoint).try_for_each(|task| seq_tx.send(Some(task))).is_err() {
+                        seq_supervisor.report(StageFault { stage : Stage::Seq, order_id : None, kind : FaultKind::Disconnected });
+                        return;
+                    }
+                }
+                // 组提交: 一批输入记录之后 flush 一次, flush 返回后才把这一批送往下游, 下游处理的输入一定已记录
+                let mut batch = Vec::with_capacity(MAX_GROUP_COMMIT);
+                let commit = |recorder : &mut Option<Box<dyn InputRecorder>>, batch : &mut Vec<PreProcessorTask>| {
+                    if let Some(recorder) = recorder {
+                        recorder.flush().map_err(|e| FaultKind::Io(e.to_string()))?;
+                    }
+                    batch.drain(..).try_for_each(|task| seq_tx.send(Some(task))).map_err(|_| FaultKind::Disconnected)
+                };
+                let closed = seq_supervisor.run(Stage::Seq, &seq_rx, |task| {
+                    let task = match task {
+                        IngressTask::Input(task) => Some(worker.process(task)),
+                        IngressTask::Replicated(task) => {
+                            worker.resume(&task);
+                            Some(task)
+                        },
+                        // 之后的任务经队列到达 exe 阶段时, 一定能看到新的值
+                        IngressTask::Promote => {
+                            seq_suppress_until.store(worker.last_seq_num(), Ordering::Release);
+                            None
+                        },
+                    };
+                    if let Some(task) = task {
+                        if let (Some(recorder), Some(input)) = (&mut recorder, task.inbound()) {
+                            recorder.record(&input).map_err(|e| FaultKind::Io(e.to_string()))?;
+                        }
+                        let checkpoint = worker.checkpoint(&task);
+                        batch.extend(iter::once(task).chain(checkpoint));
+                    }
+                    if recorder.is_none() || seq_monitor.is_empty() || batch.len() >= MAX_GROUP_COMMIT {
+                        commit(&mut recorder, &mut batch)?;
+                    }
+                    Ok(())
+                });
+                // 出错退出时不再送出未提交的任务, 只 flush 已记录的输入
+                if !closed {
+                    batch.clear();
+                }
+                if let Err(kind) = commit(&mut recorder, &mut batch) {
+                    seq_supervisor.report(StageFault { stage : Stage::Seq, order_id : None, kind });
+                    return;
+                }
+                if closed {
+                    let _ = seq_tx.send(None);
+                }
+            })),
 
-        Engin {
-            engin_tx : engin_tx,
             pre : Some(thread::spawn(move || {
-                let mut worker = PreProcessor::new();
-                loop {
-                    let task = pre_rx.recv().unwrap();
-                    if let None = task {
-                        pre_tx.send(None).unwrap();
-                        break;
+                let mut worker = match pre_snapshot.map(|snapshot| PreProcessor::restore(&snapshot)).transpose() {
+                    Ok(worker) => worker.unwrap_or_else(PreProcessor::new),
+                    Err(e) => {
+                        pre_supervisor.report(StageFault { stage : Stage::Pre, order_id : None, kind : FaultKind::Io(e.to_string()) });
+                        return;
                     }
-                    //println!("pre recieved one!");
-                    pre_tx.send(Some(worker.process(task.unwrap()))).unwrap();
+                };
+                let closed = pre_supervisor.run(Stage::Pre, &pre_rx, |task| {
+                    pre_tx.send(Some(worker.process(task))).map_err(|_| FaultKind::Disconnected)
+                });
+                if closed {
+                    let _ = pre_tx.send(None);
                 }
             })),
 
             rc : Some(thread::spawn(move || {
-                let mut worker = RcProcessor::new();
-                loop {
-                    let task = rc_rx.recv().unwrap();
-                    if let None = task {
-                        rc_tx.send(None).unwrap();
-                        break;
-                    }
-                    //println!("rc recieved one!");
-                    rc_tx.send(Some(worker.process(task.unwrap()))).unwrap();
+                let mut worker = RcProcessor::new(rc_price_limits);
+                let closed = rc_supervisor.run(Stage::Rc, &rc_rx, |task| {
+                    rc_tx.send(Some(worker.process(task))).map_err(|_| FaultKind::Disconnected)
+                });
+                if closed {
+                    let _ = rc_tx.send(None);
                 }
             })),
 
             core : Some(thread::spawn(move || {
-                let mut worker = CoreProcessor::new();
-                let exe_fn = |task : ExecutionTask| {core_tx.send(Some(task)).unwrap();};
-                loop {
-                    let task = core_rx.recv().unwrap();
-                    if let None = task {
-                        core_tx.send(None).unwrap();
-                        break;
+                let mut worker = CoreProcessor::new(config.price_limits, config.use_price_ladder);
+                if md_tx.is_some() {
+                    worker.enable_market_data();
+                }
+                if let Some(Err(e)) = core_snapshot.map(|snapshot| worker.restore(&snapshot, md_seq_num)) {
+                    core_supervisor.report(StageFault { stage : Stage::Core, order_id : None, kind : FaultKind::Io(e.to_string()) });
+                    return;
+                }
+                let closed = core_supervisor.run(Stage::Core, &core_rx, |task| {
+                    let mut connected = true;
+                    worker.process(task, |task : ExecutionTask| {
+                        connected = connected && core_tx.send(Some(task)).is_ok();
+                    });
+                    if let (Some(md_tx), Some(task)) = (&md_tx, worker.take_market_data()) {
+                        connected = connected && md_tx.send(Some(task)).is_ok();
+                    }
+                    if connected { Ok(()) } else { Err(FaultKind::Disconnected) }
+                });
+                if closed {
+                    let _ = core_tx.send(None);
+                    if let Some(md_tx) = &md_tx {
+                        let _ = md_tx.send(None);
                     }
-
-
-                    //println!("core recieved one!");
-                    worker.process(task.unwrap(), exe_fn);
-
                 }
             })),
 
             exe : Some(thread::spawn(move || {
-                let mut worker = ExeProcessor::new();
-                loop {
-                    let task = exe_rx.recv().unwrap();
-                    if let None = task {
-                        break;
+                let mut worker = ExeProcessor::new(order_recycler, cancel_recycler, config.report_format);
+                worker.restore(exe_snapshot.0, exe_snapshot.1);
+                if let Some(snapshots) = config.snapshots {
+                    worker.set_snapshot_store(snapshots);
+                }
+                if let Some(checksum_log) = config.checksum_log {
+                    worker.set_checksum_log(checksum_log);
+                }
+                exe_supervisor.run(Stage::Exe, &exe_rx, |task| {
+                    worker.suppress_until(exe_suppress_until.load(Ordering::Acquire));
+                    worker.process(task, &mut sink).map_err(|e| FaultKind::Io(e.to_string()))?;
+                    // 输入队列已处理空, 把缓冲的报告发出去
+                    if exe_monitor.is_empty() {
+                        sink.flush().map_err(|e| FaultKind::Io(e.to_string()))?;
                     }
-                    worker.process(task.unwrap(), &mut sender);
+                    Ok(())
+                });
+                if let Err(e) = sink.flush() {
+                    exe_supervisor.report(StageFault { stage : Stage::Exe, order_id : None, kind : FaultKind::Io(e.to_string()) });
                 }
-                sender
-            }))
+                sink
+            })),
+
+            md : md_rx.map(|(md_rx, md_monitor, mut sink)| thread::spawn(move || {
+                let mut worker = MdProcessor::new(md_snapshots);
+                worker.restore(md_seq_num);
+                md_supervisor.run(Stage::Md, &md_rx, |task| {
+                    worker.suppress_until(md_suppress_until.load(Ordering::Acquire));
+                    worker.process(task, &mut sink).map_err(|e| FaultKind::Io(e.to_string()))?;
+                    // 输入队列已处理空, 把缓冲的行情发出去
+                    if md_monitor.is_empty() {
+                        sink.flush().map_err(|e| FaultKind::Io(e.to_string()))?;
+                    }
+                    Ok(())
+                });
+                if let Err(e) = sink.flush() {
+                    md_supervisor.report(StageFault { stage : Stage::Md, order_id : None, kind : FaultKind::Io(e.to_string()) });
+                }
+            })),
+            depth_snapshots,
+            md_timer,
+        })
+    }
+
+    pub fn process(&self, task : PreProcessorTask) -> Result<(), EngineError> {
+        self.ingress.process(task)
+    }
+
+    // 各阶段处理完此前送入的委托后保存快照, 需要设置 EnginConfig::snapshots
+    pub fn snapshot(&self) -> Result<(), EngineError> {
+        self.ingress.snapshot()
+    }
+
+    // 行情阶段处理到时生成各证券的全部价位, 交给 MarketDataSink 并更新 depth_snapshots
+    pub fn market_data_snapshot(&self) -> Result<(), EngineError> {
+        self.ingress.market_data_snapshot()
+    }
+
+    // 各证券最新的行情快照, 晚加入的订阅方载入后从快照的 md_seq_num 之后接着应用增量
+    pub fn depth_snapshots(&self) -> DepthSnapshots {
+        self.depth_snapshots.clone()
+    }
+
+    // 备用引擎接管, 见 Ingress::promote
+    pub fn promote(&self) -> Result<(), EngineError> {
+        self.ingress.promote()
+    }
+
+    // 供其它线程送入委托, 与 Engin 共用对象池
+    pub fn ingress(&self) -> Ingress {
+        self.ingress.clone()
+    }
+
+    // 网关收到的委托从池中取出 Box 后送入引擎
+    pub fn submit(&self, inbound : Inbound) -> Result<(), EngineError> {
+        self.ingress.submit(inbound)
+    }
+
+    // 从池中取出空闲的 Box 填入消息, exe 阶段处理完后自动回收
+    pub fn alloc_new_order(&self, order : NewOrder) -> Box<NewOrder> {
+        self.ingress.alloc_new_order(order)
+    }
+
+    pub fn alloc_cancel_request(&self, cancel_request : CancelRequest) -> Box<CancelRequest> {
+        self.ingress.alloc_cancel_request(cancel_request)
+    }
+
+    pub fn new_order_pool_stats(&self) -> PoolStats {
+        self.ingress.pool_stats().0
+    }
+
+    pub fn cancel_request_pool_stats(&self) -> PoolStats {
+        self.ingress.pool_stats().1
+    }
+
+    // 可交给监控线程定期读取
+    pub fn queue_monitors(&self) -> Vec<(Stage, QueueMonitor)> {
+        self.monitors.clone()
+    }
+
+    pub fn queue_stats(&self) -> Vec<QueueStats> {
+        self.monitors.iter().map(|(stage, monitor)| QueueStats {
+            stage : *stage,
+            depth : monitor.len(),
+            high_watermark : monitor.high_watermark(),
+            capacity : monitor.capacity(),
+        }).collect()
+    }
+
+    // 所有阶段登记的故障, 第一个为根因
+    pub fn faults(&self) -> Vec<StageFault> {
+        self.supervisor.faults()
+    }
+
+    // 停止所有阶段并取回报告输出
+    pub fn close(&mut self) -> Result<S, EngineError> {
+        let (seq, pre, rc, core, exe) = match (self.seq.take(), self.pre.take(), self.rc.take(), self.core.take(), self.exe.take()) {
+            (Some(seq), Some(pre), Some(rc), Some(core), Some(exe)) => (seq, pre, rc, core, exe),
+            _ => return Err(EngineError::Closed),
+        };
+        self.stop_md_timer();
+        self.ingress.close();
+
+        self.join(Stage::Seq, seq);
+        self.join(Stage::Pre, pre);
+        self.join(Stage::Rc, rc);
+        self.join(Stage::Core, core);
+        if let Some(md) = self.md.take() {
+            self.join(Stage::Md, md);
+        }
+        let sink = self.join(Stage::Exe, exe);
+
+        if self.supervisor.faulted() {
+            return Err(self.supervisor.error());
         }
+        sink.ok_or(EngineError::Closed)
     }
-    
-    pub fn process(&self, task : PreProcessorTask) {
-        self.engin_tx.send(Some(task)).unwrap();
+
+    fn join<T>(&self, stage : Stage, handle : JoinHandle<T>) -> Option<T> {
+        match handle.join() {
+            Ok(result) => Some(result),
+            Err(_) => {
+                self.supervisor.report(StageFault {
+                    stage,
+                    order_id : None,
+                    kind : FaultKind::Panicked(String::from("stage thread panicked"))
+                });
+                None
+            }
+        }
     }
 
-    pub fn close(&mut self) -> ExeSender{
-        self.engin_tx.send(None).unwrap();
-        self.pre.take().unwrap().join().unwrap();
-        self.rc.take().unwrap().join().unwrap();
-        self.core.take().unwrap().join().unwrap();
-        self.exe.take().unwrap().join().unwrap()
+}
+
+// 定时线程持有入口, 未调用 close 就丢弃 Engin 时也要停下, 否则定序阶段等不到入口关闭
+impl<S : ReportSink> Engin<S> {
+    fn stop_md_timer(&mut self) {
+        if let Some((running, handle)) = self.md_timer.take() {
+            running.store(false, Ordering::Release);
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
     }
+}
 
-}
\ No newline at end of file
+impl<S : ReportSink> Drop for Engin<S> {
+    fn drop(&mut self) {
+        self.stop_md_timer();
+    }
+}
+
+// 入口队列满时跳过这一次, 引擎关闭或出错后退出
+fn market_data_timer(ingress : Ingress, interval : Duration) -> (Arc<AtomicBool>, JoinHandle<()>) {
+    let running = Arc::new(AtomicBool::new(true));
+    let flag = running.clone();
+    let handle = thread::spawn(move || {
+        while flag.load(Ordering::Acquire) {
+            thread::park_timeout(interval);
+            if !flag.load(Ordering::Acquire) {
+                break;
+            }
+            match ingress.market_data_snapshot() {
+                Ok(()) | Err(EngineError::QueueFull) => {},
+                Err(_) => break,
+            }
+        }
+    });
+    (running, handle)
+}
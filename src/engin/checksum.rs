@@ -0,0 +1,183 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::mpsc::Sender;
+
+use serde::Serialize;
+
+use crate::messages::StateChecksum;
+
+const FNV_OFFSET_BASIS : u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME : u64 = 0x0000_0100_0000_01B3;
+
+// 64 位 FNV-1a, 结果与平台和 Rust 版本无关; 状态经 bincode 编码后直接写入, 不额外分配
+pub struct StateHasher {
+    state : u64,
+}
+
+impl StateHasher {
+    pub fn new() -> StateHasher {
+        StateHasher { state : FNV_OFFSET_BASIS }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.state
+    }
+}
+
+impl Default for StateHasher {
+    fn default() -> StateHasher {
+        StateHasher::new()
+    }
+}
+
+impl Write for StateHasher {
+    fn write(&mut self, bytes : &[u8]) -> io::Result<usize> {
+        self.state = bytes.iter().fold(self.state, |state, &b| (state ^ b as u64).wrapping_mul(FNV_PRIME));
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// 各阶段的状态都保存在有序容器中, 编码结果只取决于状态本身
+pub fn state_hash<T : Serialize + ?Sized>(state : &T) -> u64 {
+    let mut hasher = StateHasher::new();
+    bincode::serialize_into(&mut hasher, state).expect("hash stage state");
+    hasher.finish()
+}
+
+// 状态中元素集合的摘要: 各元素摘要的和, 与插入顺序无关, 插入与删除元素时增量更新,
+// 检查点上直接取出, 不必遍历整个状态
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RollingHash {
+    state : u64,
+}
+
+impl RollingHash {
+    pub fn insert<T : Serialize + ?Sized>(&mut self, element : &T) {
+        self.state = self.state.wrapping_add(element_hash(element));
+    }
+
+    pub fn remove<T : Serialize + ?Sized>(&mut self, element : &T) {
+        self.state = self.state.wrapping_sub(element_hash(element));
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.state
+    }
+}
+
+// FNV-1a 的结果再经 splitmix64 的混合, 相加时低位不会互相抵消
+fn element_hash<T : Serialize + ?Sized>(element : &T) -> u64 {
+    let mut hash = state_hash(element);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    hash ^ (hash >> 31)
+}
+
+// exe 阶段按序号顺序交出摘要
+pub trait ChecksumLog : Send {
+    fn record(&mut self, checksum : &StateChecksum) -> io::Result<()>;
+}
+
+impl<L : ChecksumLog + ?Sized> ChecksumLog for Box<L> {
+    fn record(&mut self, checksum : &StateChecksum) -> io::Result<()> {
+        (**self).record(checksum)
+    }
+}
+
+// 交给其它线程, 例如在线比较主备引擎
+impl ChecksumLog for Sender<StateChecksum> {
+    fn record(&mut self, checksum : &StateChecksum) -> io::Result<()> {
+        self.send(*checksum).map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+// 每个摘要一行写入文本文件, 摘要间隔很大, 每行写完即交给操作系统
+pub struct ChecksumFile {
+    file : File,
+}
+
+impl ChecksumFile {
+    pub fn create<P : AsRef<Path>>(path : P) -> io::Result<ChecksumFile> {
+        Ok(ChecksumFile { file : File::create(path)? })
+    }
+}
+
+impl ChecksumLog for ChecksumFile {
+    fn record(&mut self, checksum : &StateChecksum) -> io::Result<()> {
+        writeln!(self.file, "{}", checksum.to_line())
+    }
+}
+
+pub fn read_checksums<P : AsRef<Path>>(path : P) -> io::Result<Vec<StateChecksum>> {
+    let mut checksums = Vec::new();
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let checksum = StateChecksum::parse_line(&line)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("malformed checksum at line {}", i + 1)))?;
+        checksums.push(checksum);
+    }
+    Ok(checksums)
+}
+
+// 两次运行中序号相同的摘要里第一对不一致的; 两边都按序号递增, 只有一边有的序号被跳过
+pub fn first_divergence(a : &[StateChecksum], b : &[StateChecksum]) -> Option<(StateChecksum, StateChecksum)> {
+    let (mut a, mut b) = (a.iter().peekable(), b.iter().peekable());
+    while let (Some(x), Some(y)) = (a.peek(), b.peek()) {
+        if x.seq_num < y.seq_num {
+            a.next();
+        }
+        else if x.seq_num > y.seq_num {
+            b.next();
+        }
+        else if x != y {
+            return Some((**x, **y));
+        }
+        else {
+            a.next();
+            b.next();
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn divergence_on_common_seq_nums() {
+        let checksum = |seq_num, core| StateChecksum { seq_num, pre : 1, core, exe : 2 };
+        let a = vec![checksum(10, 5), checksum(20, 6), checksum(30, 7), checksum(40, 8)];
+        let b = vec![checksum(20, 6), checksum(30, 9), checksum(40, 8)];
+        assert_eq!(first_divergence(&a, &b), Some((checksum(30, 7), checksum(30, 9))));
+        assert_eq!(a[2].diverged_stages(&b[1]), vec!["core"]);
+        assert_eq!(first_divergence(&a, &a[1..]), None);
+
+        assert_eq!(StateChecksum::parse_line(&a[3].to_line()), Some(a[3]));
+        assert_eq!(StateChecksum::parse_line("40 1 2"), None);
+        // FNV-1a 的标准测试值
+        let mut hasher = StateHasher::new();
+        hasher.write_all(b"a").unwrap();
+        assert_eq!(hasher.finish(), 0xAF63_DC4C_8601_EC8C);
+    }
+
+    #[test]
+    fn rolling_hash_independent_of_order() {
+        let (mut a, mut b) = (RollingHash::default(), RollingHash::default());
+        (1..=5u64).for_each(|i| a.insert(&i));
+        (1..=6u64).rev().for_each(|i| b.insert(&i));
+        assert_ne!(a, b);
+        b.remove(&6u64);
+        assert_eq!(a, b);
+        (1..=5u64).for_each(|i| a.remove(&i));
+        assert_eq!(a, RollingHash::default());
+    }
+}
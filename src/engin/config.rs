@@ -0,0 +1,98 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::ring_buffer::WaitStrategy;
+use crate::types::{SecurityID, PriceLimit, SeqNum};
+use crate::fix::FixConfig;
+use crate::recorder::InputRecorder;
+use crate::market_data::MarketDataSink;
+use crate::engin::recovery::Recovery;
+use crate::engin::snapshot::SnapshotStore;
+use crate::engin::checksum::ChecksumLog;
+use crate::engin::error::EngineError;
+
+// 入口队列满时 Engin::process 的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackpressurePolicy {
+    // 阻塞调用方直到队列有空位
+    Block,
+    // 立即返回 EngineError::QueueFull
+    Reject,
+}
+
+// exe 阶段输出报告的编码格式
+#[derive(Debug, Clone)]
+pub enum ReportFormat {
+    // reports::encode_into, 可由 reports::decode 解码
+    Bincode,
+    Fix(FixConfig),
+}
+
+pub struct EnginConfig {
+    // 入口队列的容量, 向上取整为 2 的幂
+    pub ingress_capacity : usize,
+    // 各阶段之间队列的容量, 向上取整为 2 的幂
+    pub queue_capacity : usize,
+    pub wait_strategy : WaitStrategy,
+    pub backpressure : BackpressurePolicy,
+    // 预先分配的 NewOrder 和 CancelRequest 个数, 由 Engin::alloc_* 复用
+    pub pool_capacity : usize,
+    // 有涨跌停限制的证券, rc 阶段拒绝超出范围或不在价位上的委托
+    pub price_limits : BTreeMap<SecurityID, PriceLimit>,
+    // 为有涨跌停限制的证券使用数组价位簿, 其余证券使用有序树
+    pub use_price_ladder : bool,
+    pub report_format : ReportFormat,
+    // 定序阶段按全局顺序记录每条输入, 用于重放; journal::Journal 作为预写日志
+    pub recorder : Option<Box<dyn InputRecorder>>,
+    // 启动时先重放崩溃前日志中的输入, 再开始处理新的输入
+    pub recovery : Option<Recovery>,
+    // Engin::snapshot 产生的快照保存在此目录, 未设置时快照标记经过各阶段后被丢弃
+    pub snapshots : Option<SnapshotStore>,
+    // 作为备用引擎启动: 只处理 replication::Replica 送入的主引擎输入且不输出报告, 直到 Engin::promote
+    pub standby : bool,
+    // 每隔 checksum_interval 条输入计算一次各阶段状态的摘要交给 checksum_log, 用于比较两次运行或主备引擎
+    pub checksum_interval : SeqNum,
+    pub checksum_log : Option<Box<dyn ChecksumLog>>,
+    // core 阶段由簿的变化生成逐笔成交与价位变化, 经行情阶段编号后交给此 sink; 未设置时不生成行情
+    pub market_data : Option<Box<dyn MarketDataSink>>,
+    // 按此间隔自动生成行情快照, 只在设置了 market_data 时有效; 也可随时调用 Engin::market_data_snapshot
+    pub market_data_snapshot_interval : Option<Duration>,
+}
+
+impl EnginConfig {
+    // 涨跌停价位须有正的价位间隔且下限不高于上限, 否则 rc 阶段的价位检查与价位簿都无法工作; FIX 输出的价格小数位数见 FixConfig::validate
+    pub fn validate(&self) -> Result<(), EngineError> {
+        for (security_id, limit) in &self.price_limits {
+            if limit.tick <= 0 || limit.min_price > limit.max_price {
+                return Err(EngineError::InvalidConfig(format!("price limit of {} {:?}", String::from_utf8_lossy(security_id), limit)));
+            }
+        }
+        if let ReportFormat::Fix(config) = &self.report_format {
+            config.validate().map_err(|e| EngineError::InvalidConfig(e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for EnginConfig {
+    fn default() -> EnginConfig {
+        EnginConfig {
+            ingress_capacity : 1 << 16,
+            queue_capacity : 1 << 16,
+            wait_strategy : WaitStrategy::Yield,
+            backpressure : BackpressurePolicy::Block,
+            pool_capacity : 1 << 12,
+            price_limits : BTreeMap::new(),
+            use_price_ladder : false,
+            report_format : ReportFormat::Bincode,
+            recorder : None,
+            recovery : None,
+            snapshots : None,
+            standby : false,
+            checksum_interval : 0,
+            checksum_log : None,
+            market_data : None,
+            market_data_snapshot_interval : None,
+        }
+    }
+}
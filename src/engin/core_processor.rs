@@ -1,41 +1,364 @@
+use std::collections::BTreeMap;
+
 use crate::messages::*;
-use std::sync::Arc;
+use crate::types::*;
+use crate::order_book::{BookBackend, BookLevel};
 use crate::engin::trading_session::TradingSession;
+use crate::engin::checksum::state_hash;
+use crate::market_data::{MarketDataEvent, LevelUpdate, LevelAction, Trade, DepthSnapshot};
+
+use serde::{Serialize, Deserialize};
+
+// 快照中一只证券的两边簿
+#[derive(Serialize, Deserialize)]
+struct SessionSnapshot {
+    security_id : SecurityID,
+    buy : Vec<BookLevel<NewOrder>>,
+    sell : Vec<BookLevel<NewOrder>>,
+}
 
 pub struct CoreProcessor {
-    session : TradingSession,
+    // 每只证券一个交易时段, 收到该证券第一笔委托时创建
+    sessions : BTreeMap<SecurityID, TradingSession>,
+    price_limits : BTreeMap<SecurityID, PriceLimit>,
+    use_price_ladder : bool,
+    // 启用行情时, 每条输入引起的成交与价位变化暂存在这里, 由 take_market_data 取走
+    market_data : Option<MarketDataTask>,
+    // 已送出的行情条数, 与行情阶段的编号一致, 写入快照
+    md_seq_num : u64,
+}
+
+// 一条输入在某个价位上增加与减少的数量
+struct LevelChange {
+    side : Side,
+    price : Price,
+    added : Qty,
+    removed : Qty,
+}
+
+fn add_change(changes : &mut Vec<LevelChange>, side : Side, price : Price, added : Qty, removed : Qty) {
+    match changes.iter_mut().find(|change| change.side == side && change.price == price) {
+        Some(change) => {
+            change.added += added;
+            change.removed += removed;
+        },
+        None => changes.push(LevelChange { side, price, added, removed }),
+    }
 }
 
 impl CoreProcessor {
-    pub fn new() -> CoreProcessor {
+    pub fn new(price_limits : BTreeMap<SecurityID, PriceLimit>, use_price_ladder : bool) -> CoreProcessor {
         CoreProcessor {
-            session : TradingSession::new(),
+            sessions : BTreeMap::new(),
+            price_limits,
+            use_price_ladder,
+            market_data : None,
+            md_seq_num : 0,
+        }
+    }
+
+    pub fn enable_market_data(&mut self) {
+        self.market_data = Some(MarketDataTask { order_id : 0, seq_num : 0, events : Vec::new(), snapshots : Vec::new() });
+    }
+
+    // 上一条输入引起的行情或行情快照标记生成的快照, 都没有时为 None
+    pub fn take_market_data(&mut self) -> Option<MarketDataTask> {
+        match &mut self.market_data {
+            Some(task) if !task.events.is_empty() || !task.snapshots.is_empty() => {
+                let events = std::mem::take(&mut task.events);
+                let snapshots = std::mem::take(&mut task.snapshots);
+                self.md_seq_num += events.len() as u64;
+                Some(MarketDataTask { order_id : task.order_id, seq_num : task.seq_num, events, snapshots })
+            },
+            _ => None,
+        }
+    }
+
+    // 各证券两边的全部价位, 与此前送出的增量行情一致
+    fn depth_snapshot(&mut self, seq_num : SeqNum) {
+        let task = match &mut self.market_data {
+            Some(task) => task,
+            None => return,
+        };
+        task.order_id = 0;
+        task.seq_num = seq_num;
+        task.snapshots = self.sessions.iter().map(|(security_id, session)| DepthSnapshot {
+            security_id : *security_id,
+            md_seq_num : 0,
+            seq_num,
+            bids : session.depth(K_BUY),
+            asks : session.depth(K_SELL),
+        }).collect();
+    }
+
+    // 由处理后的簿与各价位的增减得到处理前的总量, 从而区分新增、变化与删除
+    fn publish(&mut self, order_id : OrderID, seq_num : SeqNum, security_id : SecurityID, trades : Vec<Trade>, changes : Vec<LevelChange>) {
+        let (task, session) = match (&mut self.market_data, self.sessions.get(&security_id)) {
+            (Some(task), Some(session)) => (task, session),
+            _ => return,
+        };
+        task.order_id = order_id;
+        task.seq_num = seq_num;
+        task.events.extend(trades.into_iter().map(MarketDataEvent::Trade));
+        for LevelChange { side, price, added, removed } in changes {
+            let (qty, order_count) = session.level(side, price).unwrap_or((0, 0));
+            let before = qty + removed - added;
+            let action = match (before, qty) {
+                (before, qty) if before == qty => continue,
+                (0, _) => LevelAction::New,
+                (_, 0) => LevelAction::Delete,
+                _ => LevelAction::Change,
+            };
+            task.events.push(MarketDataEvent::Level(LevelUpdate { security_id, side, price, qty, order_count : order_count as u32, action }));
+        }
+    }
+
+    fn backend(&self, security_id : &SecurityID) -> BookBackend {
+        match self.price_limits.get(security_id) {
+            Some(limit) if self.use_price_ladder => BookBackend::Ladder {
+                min_price : limit.min_price,
+                max_price : limit.max_price,
+                tick : limit.tick
+            },
+            _ => BookBackend::Tree,
         }
     }
 
     pub fn process<F>(&mut self, task : CoreProcessorTask, mut exe_gen : F ) 
           where F : FnMut(ExecutionTask) {
+        if let Some(market_data) = &mut self.market_data {
+            market_data.events.clear();
+            market_data.snapshots.clear();
+        }
         match task {
             CoreProcessorTask::NewOrder(order, rc_info) => self.process_new_order(order, rc_info, exe_gen),
             CoreProcessorTask::NewOrderRejected(info) => exe_gen(ExecutionTask::NewOrderRejected(info)),
             CoreProcessorTask::CancelRequest(info, cancle_request) => self.process_cancel_request(info, cancle_request, exe_gen),
             CoreProcessorTask::CancelRequestRejected(info) => exe_gen(ExecutionTask::CancelRequestRejected(info.0, info.1)),
+            CoreProcessorTask::Snapshot(mut snapshot) => {
+                snapshot.core = self.snapshot();
+                snapshot.md_seq_num = self.md_seq_num;
+                exe_gen(ExecutionTask::Snapshot(snapshot))
+            },
+            CoreProcessorTask::Checksum(mut checksum) => {
+                // 按快照的内容计算, 价位、排队顺序与剩余数量都直接取自簿本身
+                checksum.core = state_hash(&self.export());
+                exe_gen(ExecutionTask::Checksum(checksum))
+            },
+            // 只交给行情阶段, 不经过 exe 阶段
+            CoreProcessorTask::MarketDataSnapshot(seq_num) => self.depth_snapshot(seq_num),
+        }
+    }
+
+    // 与价位的存储方式无关, 两种簿导出的结果相同
+    fn export(&self) -> Vec<SessionSnapshot> {
+        self.sessions.iter().map(|(security_id, session)| SessionSnapshot {
+            security_id : *security_id,
+            buy : session.export(K_BUY),
+            sell : session.export(K_SELL),
+        }).collect()
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        bincode::serialize(&self.export()).expect("serialize order books")
+    }
+
+    // 由快照重建各证券的簿, 价位的存储方式仍由当前配置决定
+    pub fn restore(&mut self, snapshot : &[u8], md_seq_num : u64) -> bincode::Result<()> {
+        self.md_seq_num = md_seq_num;
+        let sessions : Vec<SessionSnapshot> = bincode::deserialize(snapshot)?;
+        for SessionSnapshot { security_id, buy, sell } in sessions {
+            let mut session = TradingSession::with_backend(self.backend(&security_id));
+            session.import(K_BUY, buy);
+            session.import(K_SELL, sell);
+            self.sessions.insert(security_id, session);
         }
+        Ok(())
     }
 
     fn process_new_order<F>(&mut self, order : Box<NewOrder>, rc_info : Box<RcResult>, mut exe_gen : F)
          where F : FnMut(ExecutionTask) {
 
-        let order = Arc::from(order);
-        exe_gen(ExecutionTask::NewOrderAccepted(Arc::clone(&order)));
+        let backend = self.backend(&order.security_id);
+        let session = self.sessions.entry(order.security_id).or_insert_with(|| TradingSession::with_backend(backend));
+        let tasks = session.process_new_order(*order, rc_info);
+        if self.market_data.is_some() {
+            let contra_side = if order.side == K_BUY { K_SELL } else { K_BUY };
+            let mut trades = Vec::new();
+            let mut changes = Vec::new();
+            let mut leaves_qty = order.qty;
+            for task in &tasks {
+                if let ExecutionTask::NewoOrderMatched(info) = task {
+                    let (buy_order_id, sell_order_id) = if order.side == K_BUY {
+                        (info.order1.order_id, info.order2.order_id)
+                    }
+                    else {
+                        (info.order2.order_id, info.order1.order_id)
+                    };
+                    trades.push(Trade { security_id : order.security_id, price : info.last_px, qty : info.last_qty, buy_order_id, sell_order_id, aggressor_side : order.side });
+                    add_change(&mut changes, contra_side, info.last_px, 0, info.last_qty);
+                    leaves_qty = info.leaves_qty1;
+                }
+            }
+            // 未成交的部分留在簿中
+            add_change(&mut changes, order.side, order.price, leaves_qty, 0);
+            self.publish(order.order_id, order.seq_num, order.security_id, trades, changes);
+        }
+        exe_gen(ExecutionTask::NewOrderAccepted(order));
 
-        self.session.process_new_order(order, rc_info).into_iter().for_each(|task| {
+        tasks.into_iter().for_each(|task| {
             exe_gen(task);
          });
     }
 
     fn process_cancel_request<F>(&mut self, orig_info : OrigOrderInfoForCancel, cancel_request : Box<CancelRequest>, mut exe_gen : F) 
          where F : FnMut(ExecutionTask) {
-        exe_gen(self.session.process_cancel_request(&orig_info, cancel_request));
+        let task = match self.sessions.get_mut(&orig_info.security_id) {
+            Some(session) => session.process_cancel_request(&orig_info, cancel_request),
+            None => ExecutionTask::CancelRequestRejected(CancelReasonCode::OrderNotExisted, cancel_request),
+        };
+        if let ExecutionTask::CancelRequestAccepted(leaves_qty, cancel_request, order) = &task {
+            if self.market_data.is_some() {
+                let changes = vec![LevelChange { side : order.side, price : order.price, added : 0, removed : *leaves_qty }];
+                self.publish(cancel_request.order_id, cancel_request.seq_num, order.security_id, Vec::new(), changes);
+            }
+        }
+        exe_gen(task);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(order_id : OrderID, side : Side, price : Price, qty : Qty) -> Box<NewOrder> {
+        Box::new(NewOrder { order_id, seq_num : order_id as SeqNum, pbu_id : to_array("PBU001"), cl_ord_id : to_array(&order_id.to_string()),
+            security_id : to_array("SEC001"), side, price, qty })
+    }
+
+    fn levels(processor : &mut CoreProcessor) -> Vec<(Side, Price, Qty, u32, LevelAction)> {
+        processor.take_market_data().into_iter().flat_map(|task| task.events).filter_map(|event| match event {
+            MarketDataEvent::Level(level) => Some((level.side, level.price, level.qty, level.order_count, level.action)),
+            MarketDataEvent::Trade(_) => None,
+        }).collect()
+    }
+
+    fn matched(processor : &mut CoreProcessor, order : Box<NewOrder>) -> usize {
+        let mut count = 0;
+        processor.process(CoreProcessorTask::NewOrder(order, Box::new(RcResult {})), |task| {
+            if let ExecutionTask::NewoOrderMatched(_) = task { count += 1; }
+        });
+        count
+    }
+
+    #[test]
+    fn securities_do_not_cross() {
+        let mut processor = CoreProcessor::new(BTreeMap::new(), false);
+        let other = |order : Box<NewOrder>| Box::new(NewOrder { security_id : to_array("SEC002"), ..*order });
+
+        // 价格可以成交但证券不同, 两笔都应当挂在各自的订单簿上
+        assert_eq!(matched(&mut processor, order(1, K_BUY, 100, 10)), 0);
+        assert_eq!(matched(&mut processor, other(order(2, K_SELL, 100, 10))), 0);
+
+        assert_eq!(matched(&mut processor, order(3, K_SELL, 100, 10)), 1);
+        assert_eq!(matched(&mut processor, other(order(4, K_BUY, 100, 10))), 1);
+    }
+
+    #[test]
+    fn level_updates_from_book_changes() {
+        let mut processor = CoreProcessor::new(BTreeMap::new(), false);
+        processor.enable_market_data();
+        let new_order = |processor : &mut CoreProcessor, order| processor.process(CoreProcessorTask::NewOrder(order, Box::new(RcResult {})), |_| {});
+
+        new_order(&mut processor, order(1, K_BUY, 100, 10));
+        assert_eq!(levels(&mut processor), vec![(K_BUY, 100, 10, 1, LevelAction::New)]);
+        new_order(&mut processor, order(2, K_BUY, 100, 5));
+        assert_eq!(levels(&mut processor), vec![(K_BUY, 100, 15, 2, LevelAction::Change)]);
+        new_order(&mut processor, order(3, K_BUY, 98, 5));
+        assert_eq!(levels(&mut processor), vec![(K_BUY, 98, 5, 1, LevelAction::New)]);
+
+        // 吃掉 100 的第一笔与第二笔的一部分, 再吃掉 98 整个价位, 剩余部分挂在 97
+        new_order(&mut processor, order(4, K_SELL, 97, 22));
+        let task = processor.take_market_data().unwrap();
+        assert_eq!((task.order_id, task.seq_num), (4, 4));
+        let trades : Vec<(Price, Qty, OrderID, OrderID)> = task.events.iter().filter_map(|event| match event {
+            MarketDataEvent::Trade(trade) => Some((trade.price, trade.qty, trade.buy_order_id, trade.sell_order_id)),
+            MarketDataEvent::Level(_) => None,
+        }).collect();
+        assert_eq!(trades, vec![(100, 10, 1, 4), (100, 5, 2, 4), (98, 5, 3, 4)]);
+        let actions : Vec<(Side, Price, LevelAction)> = task.events.iter().filter_map(|event| match event {
+            MarketDataEvent::Level(level) => Some((level.side, level.price, level.action)),
+            MarketDataEvent::Trade(_) => None,
+        }).collect();
+        assert_eq!(actions, vec![(K_BUY, 100, LevelAction::Delete), (K_BUY, 98, LevelAction::Delete), (K_SELL, 97, LevelAction::New)]);
+        assert!(processor.take_market_data().is_none());
+
+        new_order(&mut processor, order(5, K_BUY, 96, 1));
+        new_order(&mut processor, order(6, K_SELL, 96, 1));
+        assert_eq!(levels(&mut processor), vec![(K_BUY, 96, 0, 0, LevelAction::Delete)]);
+
+        let cancel_request = Box::new(CancelRequest { order_id : 7, seq_num : 7, ..CancelRequest::default() });
+        let info = order(4, K_SELL, 97, 22).get_info_for_cancel();
+        processor.process(CoreProcessorTask::CancelRequest(info, cancel_request), |_| {});
+        assert_eq!(levels(&mut processor), vec![(K_SELL, 97, 0, 0, LevelAction::Delete)]);
+    }
+
+    #[test]
+    fn depth_snapshot_from_books() {
+        let mut processor = CoreProcessor::new(BTreeMap::new(), false);
+        processor.enable_market_data();
+        for order in [order(1, K_BUY, 100, 10), order(2, K_BUY, 101, 5), order(3, K_BUY, 100, 2), order(4, K_SELL, 103, 7)] {
+            processor.process(CoreProcessorTask::NewOrder(order, Box::new(RcResult {})), |_| {});
+        }
+        processor.take_market_data();
+
+        processor.process(CoreProcessorTask::MarketDataSnapshot(4), |_| panic!("snapshot marker reached exe"));
+        let task = processor.take_market_data().unwrap();
+        assert!(task.events.is_empty());
+        assert_eq!(task.snapshots.len(), 1);
+        let snapshot = &task.snapshots[0];
+        assert_eq!((snapshot.security_id, snapshot.seq_num), (to_array("SEC001"), 4));
+        assert_eq!(snapshot.bids, vec![(101, 5, 1), (100, 12, 2)]);
+        assert_eq!(snapshot.asks, vec![(103, 7, 1)]);
+        assert!(processor.take_market_data().is_none());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn checksum_from_books() {
+        let mut processor = CoreProcessor::new(BTreeMap::new(), false);
+        let checksum = |processor : &mut CoreProcessor| {
+            let mut core = 0;
+            processor.process(CoreProcessorTask::Checksum(Box::default()), |task| if let ExecutionTask::Checksum(checksum) = task { core = checksum.core });
+            core
+        };
+        let empty = checksum(&mut processor);
+        for order in [order(1, K_BUY, 100, 10), order(2, K_BUY, 101, 5), order(3, K_SELL, 100, 8), order(4, K_SELL, 102, 7)] {
+            processor.process(CoreProcessorTask::NewOrder(order, Box::new(RcResult {})), |_| {});
+        }
+        let cancel_request = Box::new(CancelRequest { order_id : 5, seq_num : 5, ..CancelRequest::default() });
+        processor.process(CoreProcessorTask::CancelRequest(order(4, K_SELL, 102, 7).get_info_for_cancel(), cancel_request), |_| {});
+
+        // 由快照重建的簿得到相同的结果
+        let mut restored = CoreProcessor::new(BTreeMap::new(), true);
+        restored.restore(&processor.snapshot(), 0).unwrap();
+        let hash = checksum(&mut processor);
+        assert_ne!(hash, empty);
+        assert_eq!(checksum(&mut restored), hash);
+
+        // 同一价位的两笔委托交换排队顺序后结果不同
+        processor.process(CoreProcessorTask::NewOrder(order(6, K_SELL, 102, 3), Box::new(RcResult {})), |_| {});
+        processor.process(CoreProcessorTask::NewOrder(order(7, K_SELL, 102, 3), Box::new(RcResult {})), |_| {});
+        let mut sessions = processor.export();
+        sessions[0].sell[0].orders.swap(0, 1);
+        let mut swapped = CoreProcessor::new(BTreeMap::new(), false);
+        swapped.restore(&bincode::serialize(&sessions).unwrap(), 0).unwrap();
+        assert_eq!(swapped.export()[0].sell[0].orders[0].0.order_id, 7);
+        assert_ne!(checksum(&mut swapped), checksum(&mut processor));
+
+        // 吃掉全部委托后与由空快照重建的结果相同
+        processor.process(CoreProcessorTask::NewOrder(order(8, K_SELL, 100, 7), Box::new(RcResult {})), |_| {});
+        processor.process(CoreProcessorTask::NewOrder(order(9, K_BUY, 102, 6), Box::new(RcResult {})), |_| {});
+        let mut emptied = CoreProcessor::new(BTreeMap::new(), false);
+        emptied.restore(&processor.snapshot(), 0).unwrap();
+        assert_eq!(checksum(&mut processor), checksum(&mut emptied));
+    }
+}
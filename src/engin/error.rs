@@ -0,0 +1,90 @@
+use std::fmt;
+
+use crate::types::OrderID;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Seq,
+    Pre,
+    Rc,
+    Core,
+    Exe,
+    // 行情阶段, 只在设置了 EnginConfig::market_data 时启动
+    Md,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FaultKind {
+    // 处理消息时发生 panic, 附带 panic 信息
+    Panicked(String),
+    // 下游阶段已经退出, 消息无法继续传递
+    Disconnected,
+    // 报告输出失败
+    Io(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StageFault {
+    pub stage : Stage,
+    pub order_id : Option<OrderID>,
+    pub kind : FaultKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EngineError {
+    StageFailed(StageFault),
+    // 入口队列已满, 且背压策略为 Reject
+    QueueFull,
+    Closed,
+    // 备用引擎接管前不接受新的输入
+    Standby,
+    // EnginConfig 不合法, 引擎未启动
+    InvalidConfig(String),
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Stage::Seq => "seq",
+            Stage::Pre => "pre",
+            Stage::Rc => "rc",
+            Stage::Core => "core",
+            Stage::Exe => "exe",
+            Stage::Md => "md",
+        };
+        f.write_str(name)
+    }
+}
+
+impl fmt::Display for FaultKind {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FaultKind::Panicked(msg) => write!(f, "panicked: {}", msg),
+            FaultKind::Disconnected => f.write_str("downstream stage disconnected"),
+            FaultKind::Io(msg) => write!(f, "report sink error: {}", msg),
+        }
+    }
+}
+
+impl fmt::Display for StageFault {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.order_id {
+            Some(order_id) => write!(f, "{} stage failed on order {}: {}", self.stage, order_id, self.kind),
+            None => write!(f, "{} stage failed: {}", self.stage, self.kind),
+        }
+    }
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::StageFailed(fault) => fault.fmt(f),
+            EngineError::QueueFull => f.write_str("ingress queue is full"),
+            EngineError::Closed => f.write_str("engine is closed"),
+            EngineError::Standby => f.write_str("engine is in standby"),
+            EngineError::InvalidConfig(msg) => write!(f, "invalid engine config: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
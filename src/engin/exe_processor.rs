@@ -1,62 +1,184 @@
 use crate::messages::*;
 use crate::types::*;
+use crate::pool::Recycler;
+use crate::report_sink::ReportSink;
+use crate::reports::*;
+use crate::fix::{FixEncoder, FixReport};
+use crate::engin::config::ReportFormat;
+use crate::engin::snapshot::{SnapshotStore, SnapshotWriter};
+use crate::engin::checksum::{state_hash, ChecksumLog, RollingHash};
 
-use serde::Serialize;
-use bincode;
+use std::collections::BTreeMap;
+use std::io;
 
 
 pub struct ExeProcessor {
     exec_id : ExecID,
+    // 部分成交且未结束的委托已成交的金额, 用于计算平均价格
+    notional : BTreeMap<OrderID, i128>,
+    hash : RollingHash,
+    // 报告生成后把消息送回入口线程复用
+    order_recycler : Recycler<NewOrder>,
+    cancel_recycler : Recycler<CancelRequest>,
+    // 复用的编码缓冲区, 每条报告直接编码到这里, 不再单独分配 Vec
+    buffer : Vec<u8>,
+    // 输出 FIX 格式时使用, 否则输出 bincode
+    fix : Option<FixEncoder>,
+    // 恢复时重放的输入中, 序号不超过此值的报告已经送达, 只更新 exec_id 不再输出
+    suppress_until : SeqNum,
+    suppressed : bool,
+    // 快照标记最后到达本阶段, 写入 exec_id 后交给后台线程保存
+    snapshots : Option<SnapshotWriter>,
+    checksum_log : Option<Box<dyn ChecksumLog>>,
 }
 
 impl ExeProcessor {
-    pub fn new() -> ExeProcessor {
-        ExeProcessor { exec_id: 0 }
+    pub fn new(order_recycler : Recycler<NewOrder>, cancel_recycler : Recycler<CancelRequest>, format : ReportFormat) -> ExeProcessor {
+        let fix = match format {
+            ReportFormat::Bincode => None,
+            ReportFormat::Fix(config) => Some(FixEncoder::new(config).expect("FIX config checked by EnginConfig::validate")),
+        };
+        ExeProcessor { exec_id: 0, notional : BTreeMap::new(), hash : RollingHash::default(), order_recycler, cancel_recycler, buffer : Vec::with_capacity(256), fix, suppress_until : 0, suppressed : false, snapshots : None, checksum_log : None }
     }
 
-    pub fn process(&mut self, task : ExecutionTask, sender : &mut ExeSender) {
+    pub fn restore(&mut self, exec_id : ExecID, notional : BTreeMap<OrderID, i128>) {
+        self.exec_id = exec_id;
+        self.hash = RollingHash::default();
+        for entry in notional.iter() {
+            self.hash.insert(&entry);
+        }
+        self.notional = notional;
+    }
+
+    pub fn set_snapshot_store(&mut self, snapshots : SnapshotStore) {
+        self.snapshots = Some(SnapshotWriter::spawn(snapshots));
+    }
+
+    pub fn set_checksum_log(&mut self, checksum_log : Box<dyn ChecksumLog>) {
+        self.checksum_log = Some(checksum_log);
+    }
+
+    pub fn suppress_until(&mut self, seq_num : SeqNum) {
+        self.suppress_until = seq_num;
+    }
+
+    pub fn process<S : ReportSink>(&mut self, task : ExecutionTask, sink : &mut S) -> io::Result<()> {
+        self.suppressed = seq_num(&task) <= self.suppress_until;
         match task {
             ExecutionTask::NewOrderAccepted(order) => {
                 self.exec_id += 1;
                 let mut report = new_order_accepted(order.as_ref());
                 report.exec_id = self.exec_id;
-                sender.send(bincode::serialize(&report).unwrap());
+                self.send(sink, &report)?;
+                self.order_recycler.recycle(order);
             },
             ExecutionTask::NewOrderRejected((reason, order)) => {
                 self.exec_id += 1;
                 let mut report = new_order_rejected(reason, order.as_ref());
                 report.exec_id = self.exec_id;
-                sender.send(bincode::serialize(&report).unwrap());
+                self.send(sink, &report)?;
+                self.order_recycler.recycle(order);
             },
             ExecutionTask::NewoOrderMatched(info) => {
-                let mut tcr = gen_tcr(info.last_px, info.last_qty, info.order1.as_ref(), &info.order2);
+                let mut tcr = gen_tcr(info.last_px, info.last_qty, &info.order1, &info.order2);
                 self.exec_id += 1;
                 let mut report = new_order_matched(info.last_px, info.last_qty, info.leaves_qty1, &info.order1);
+                report.avg_px = self.fill(report.order_id, info.last_px, info.last_qty, report.cum_qty, info.leaves_qty1);
                 report.exec_id = self.exec_id;
                 tcr.exec_id = self.exec_id;
-                sender.send(bincode::serialize(&report).unwrap());
+                self.send(sink, &report)?;
                 let mut report = new_order_matched(info.last_px, info.last_qty, info.leaves_qty2, &info.order2);
+                report.avg_px = self.fill(report.order_id, info.last_px, info.last_qty, report.cum_qty, info.leaves_qty2);
                 report.exec_id = self.exec_id;
+                // 成交由 order1 的输入触发, 对手方的报告也带 order1 的 seq_num
+                report.seq_num = info.order1.seq_num;
                 tcr.counterparty_exec_id = self.exec_id;
-                sender.send(bincode::serialize(&report).unwrap());
-                sender.send(bincode::serialize(&tcr).unwrap());
+                self.send(sink, &report)?;
+                self.send(sink, &tcr)?;
             },
             ExecutionTask::CancelRequestAccepted(leaves_qty,cancel_request , order) => {
                 self.exec_id += 1;
-                let mut report = new_order_cancelled(leaves_qty, cancel_request.as_ref(), order.as_ref());
+                let mut report = new_order_cancelled(leaves_qty, cancel_request.as_ref(), &order);
+                if let Some(notional) = self.notional.remove(&order.order_id) {
+                    self.hash.remove(&(&order.order_id, &notional));
+                    report.avg_px = avg_px(notional, report.cum_qty);
+                }
                 report.exec_id = self.exec_id;
-                sender.send(bincode::serialize(&report).unwrap());
+                self.send(sink, &report)?;
+                self.cancel_recycler.recycle(cancel_request);
             },
             ExecutionTask::CancelRequestRejected(reason, cancel_request) => {
                 let report = cancel_rejected(reason, cancel_request.as_ref());
-                sender.send(bincode::serialize(&report).unwrap());
-            }
+                self.send(sink, &report)?;
+                self.cancel_recycler.recycle(cancel_request);
+            },
+            ExecutionTask::Snapshot(mut snapshot) => {
+                snapshot.exec_id = self.exec_id;
+                snapshot.notional = self.notional.clone();
+                if let Some(snapshots) = &self.snapshots {
+                    snapshots.save(&snapshot)?;
+                }
+            },
+            ExecutionTask::Checksum(mut checksum) => {
+                checksum.exe = state_hash(&(self.exec_id, self.hash.finish()));
+                if let Some(checksum_log) = &mut self.checksum_log {
+                    checksum_log.record(&checksum)?;
+                }
+            },
         }
+        Ok(())
+    }
 
+    // 累计一次成交的金额, 返回成交后的平均价格; 委托全部成交后不再保留
+    fn fill(&mut self, order_id : OrderID, last_px : Price, last_qty : Qty, cum_qty : Qty, leaves_qty : Qty) -> Price {
+        let mut notional = last_px as i128 * last_qty as i128;
+        if let Some(previous) = self.notional.remove(&order_id) {
+            self.hash.remove(&(&order_id, &previous));
+            notional += previous;
+        }
+        if leaves_qty > 0 {
+            self.notional.insert(order_id, notional);
+            self.hash.insert(&(&order_id, &notional));
+        }
+        avg_px(notional, cum_qty)
+    }
+
+    fn send<T : Report + FixReport, S : ReportSink>(&mut self, sink : &mut S, report : &T) -> io::Result<()> {
+        if self.suppressed {
+            return Ok(());
+        }
+        self.buffer.clear();
+        match &mut self.fix {
+            Some(fix) => fix.encode_into(&mut self.buffer, report),
+            None => encode_into(&mut self.buffer, report).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        }
+        sink.send(&self.buffer)
     }
 
 }
 
+// 触发该任务的输入的序号
+fn seq_num(task : &ExecutionTask) -> SeqNum {
+    match task {
+        ExecutionTask::NewOrderAccepted(order) => order.seq_num,
+        ExecutionTask::NewOrderRejected((_, order)) => order.seq_num,
+        ExecutionTask::CancelRequestAccepted(_, cancel_request, _) => cancel_request.seq_num,
+        ExecutionTask::CancelRequestRejected(_, cancel_request) => cancel_request.seq_num,
+        ExecutionTask::NewoOrderMatched(info) => info.order1.seq_num,
+        ExecutionTask::Snapshot(snapshot) => snapshot.seq_num,
+        ExecutionTask::Checksum(checksum) => checksum.seq_num,
+    }
+}
+
+// 四舍五入到价位单位
+fn avg_px(notional : i128, cum_qty : Qty) -> Price {
+    if cum_qty == 0 {
+        return 0;
+    }
+    let cum_qty = cum_qty as i128;
+    ((notional * 2 + cum_qty) / (cum_qty * 2)) as Price
+}
+
 fn new_order_accepted(order : &NewOrder) -> ExecutionReport {
     let mut report = ExecutionReport::new(order);
     report.leaves_qty = order.qty;
@@ -93,8 +215,10 @@ fn gen_tcr(last_px : Price, last_qty : Qty, order : &NewOrder, couterparty_order
     TradeCaptureReport {
         security_id : order.security_id.clone(),
         order_id : order.order_id.clone(),
+        seq_num : order.seq_num,
         pbu_id : order.pbu_id.clone(),
         cl_ord_id : order.cl_ord_id.clone(),
+        side : order.side,
         exec_id : 0,
         counterparty_order_id : couterparty_order.order_id,
         counterparty_pbu_id : couterparty_order.pbu_id,
@@ -107,6 +231,7 @@ fn gen_tcr(last_px : Price, last_qty : Qty, order : &NewOrder, couterparty_order
 
 fn new_order_cancelled(leaves_qty : Qty, cancel_request : &CancelRequest, order : &NewOrder) -> ExecutionReport {
     let mut report = ExecutionReport::new(order);
+    report.seq_num = cancel_request.seq_num;
     report.cl_ord_id = cancel_request.cl_ord_id;
     report.orig_cl_ord_id = order.cl_ord_id;
     report.exec_type = K_EXEC_TYPE_CANCELLED;
@@ -118,6 +243,7 @@ fn new_order_cancelled(leaves_qty : Qty, cancel_request : &CancelRequest, order
 fn cancel_rejected(reason : CancelReasonCode, cancel_request : &CancelRequest) -> CancelReject {
     CancelReject { 
         order_id: cancel_request.order_id.clone(),
+        seq_num : cancel_request.seq_num,
         pbu_id : cancel_request.pbu_id.clone(),
         cl_ord_id : cancel_request.cl_ord_id.clone(),
         orig_cl_ord_id : cancel_request.orig_cl_ord_id.clone(),
@@ -126,71 +252,79 @@ fn cancel_rejected(reason : CancelReasonCode, cancel_request : &CancelRequest) -
     }
 }
 
-#[derive(Serialize)]
-struct CancelReject {
-    order_id : OrderID,
-    pbu_id : PBUID,
-    cl_ord_id : ClOrdID,
-    orig_cl_ord_id : ClOrdID,
-    security_id : SecurityID,
-    rejected_reason : CancelReasonCode,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool::pool;
+    use crate::report_sink::MemorySink;
 
+    #[test]
+    fn decode_reports() {
+        let (_, order_recycler) = pool(1);
+        let (_, cancel_recycler) = pool(1);
+        let mut processor = ExeProcessor::new(order_recycler, cancel_recycler, ReportFormat::Bincode);
+        let mut sink = MemorySink::new();
 
-#[derive(Serialize)]
-struct TradeCaptureReport {
-    security_id : SecurityID,
-    order_id : OrderID,
-    pbu_id : PBUID,
-    cl_ord_id : ClOrdID,
-    exec_id : ExecID,
-    counterparty_order_id : OrderID,
-    counterparty_pbu_id : PBUID,
-    counterparty_cl_ord_id : ClOrdID,
-    counterparty_exec_id : ExecID,
-    last_px : Price,
-    last_qty : Qty,
-}
+        let order = NewOrder { order_id : 1, seq_num : 1, pbu_id : to_array("PBU001"), cl_ord_id : to_array("1"),
+            security_id : to_array("SEC001"), side : K_BUY, price : 100, qty : 10 };
+        processor.process(ExecutionTask::NewOrderAccepted(Box::new(order)), &mut sink).unwrap();
+        let mut report = new_order_accepted(&order);
+        report.exec_id = 1;
+        assert_eq!(decode(&sink.reports[0]).unwrap(), OutputMessage::ExecutionReport(report));
 
-#[derive(Serialize)]
-struct ExecutionReport {
-    order_id : OrderID,
-    pbu_id : PBUID,
-    cl_ord_id : ClOrdID,
-    orig_cl_ord_id : ClOrdID,
-    security_id : SecurityID,
-    side : Side,
-    price : Price,
-    qty : Qty,
-    cum_qty : Qty,
-    leaves_qty : Qty,
-    rejected_reason : CancelReasonCode,
-    exec_type : char,
-    ord_status : char,
-    last_px : Price,
-    last_qty : Qty,
-    exec_id : ExecID,
-}
+        let cancel_request = CancelRequest { order_id : 2, seq_num : 2, pbu_id : to_array("PBU001"), cl_ord_id : to_array("2"),
+            orig_cl_ord_id : to_array("1"), security_id : to_array("SEC001") };
+        processor.process(ExecutionTask::CancelRequestRejected(CancelReasonCode::OrderNotExisted, Box::new(cancel_request)), &mut sink).unwrap();
+        let report = cancel_rejected(CancelReasonCode::OrderNotExisted, &cancel_request);
+        assert_eq!(decode(&sink.reports[1]).unwrap(), OutputMessage::CancelReject(report));
+        assert_eq!(sink.reports.len(), 2);
 
-impl ExecutionReport {
-    pub fn new(order : &NewOrder) -> ExecutionReport{
-        ExecutionReport {
-            order_id : order.order_id.clone(),
-            pbu_id : order.pbu_id.clone(),
-            cl_ord_id : order.cl_ord_id.clone(),
-            orig_cl_ord_id : to_array(""),
-            security_id : order.security_id.clone(),
-            side : order.side.clone(),
-            price : order.price.clone(),
-            qty : order.qty.clone(),
-            cum_qty : 0,
-            leaves_qty : 0,
-            rejected_reason : CancelReasonCode::Passed,
-            exec_type : ' ',
-            ord_status : ' ' ,
-            last_px : 0,
-            last_qty : 0,
-            exec_id : 0
+        // 成交的三条报告都带触发成交的输入序号
+        let aggressor = NewOrder { order_id : 3, seq_num : 3, cl_ord_id : to_array("3"), side : K_SELL, qty : 4, ..order };
+        let info = OrderMatchedInfo { order1 : aggressor, leaves_qty1 : 0, order2 : order, leaves_qty2 : 6, last_px : 100, last_qty : 4 };
+        processor.process(ExecutionTask::NewoOrderMatched(info), &mut sink).unwrap();
+        for report in &sink.reports[2..] {
+            let seq_num = match decode(report).unwrap() {
+                OutputMessage::ExecutionReport(report) => report.seq_num,
+                OutputMessage::TradeCaptureReport(report) => report.seq_num,
+                other => panic!("unexpected {:?}", other),
+            };
+            assert_eq!(seq_num, 3);
         }
+        assert_eq!(sink.reports.len(), 5);
+    }
+
+    #[test]
+    fn average_price() {
+        let (_, order_recycler) = pool(1);
+        let (_, cancel_recycler) = pool(1);
+        let mut processor = ExeProcessor::new(order_recycler, cancel_recycler, ReportFormat::Bincode);
+        let mut sink = MemorySink::new();
+        let avg_px = |report : &Vec<u8>| match decode(report).unwrap() {
+            OutputMessage::ExecutionReport(report) => report.avg_px,
+            other => panic!("unexpected {:?}", other),
+        };
+
+        let order = NewOrder { order_id : 1, seq_num : 1, pbu_id : to_array("PBU001"), cl_ord_id : to_array("1"),
+            security_id : to_array("SEC001"), side : K_BUY, price : 102, qty : 10 };
+        let seller1 = NewOrder { order_id : 2, seq_num : 2, cl_ord_id : to_array("2"), side : K_SELL, price : 100, qty : 3, ..order };
+        let seller2 = NewOrder { order_id : 3, seq_num : 3, cl_ord_id : to_array("3"), side : K_SELL, price : 101, qty : 4, ..order };
+        let info = OrderMatchedInfo { order1 : order, leaves_qty1 : 7, order2 : seller1, leaves_qty2 : 0, last_px : 100, last_qty : 3 };
+        processor.process(ExecutionTask::NewoOrderMatched(info), &mut sink).unwrap();
+        assert_eq!(avg_px(&sink.reports[0]), 100);
+        let info = OrderMatchedInfo { order1 : order, leaves_qty1 : 3, order2 : seller2, leaves_qty2 : 0, last_px : 101, last_qty : 4 };
+        processor.process(ExecutionTask::NewoOrderMatched(info), &mut sink).unwrap();
+        // (100 * 3 + 101 * 4) / 7 = 100.57
+        assert_eq!(avg_px(&sink.reports[3]), 101);
+        assert_eq!(avg_px(&sink.reports[4]), 101);
+        assert_eq!(processor.notional.len(), 1);
+
+        // 撤单报告带已成交部分的平均价格, 之后不再保留
+        let cancel_request = CancelRequest { order_id : 4, seq_num : 4, pbu_id : to_array("PBU001"), cl_ord_id : to_array("4"),
+            orig_cl_ord_id : to_array("1"), security_id : to_array("SEC001") };
+        processor.process(ExecutionTask::CancelRequestAccepted(3, Box::new(cancel_request), order), &mut sink).unwrap();
+        assert_eq!(avg_px(&sink.reports[6]), 101);
+        assert!(processor.notional.is_empty());
+        assert_eq!(processor.hash.finish(), RollingHash::default().finish());
     }
-}
\ No newline at end of file
+}
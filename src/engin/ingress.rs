@@ -0,0 +1,158 @@
+use std::sync::mpsc::TrySendError;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::types::OrderID;
+use crate::messages::*;
+use crate::ring_buffer::Producer;
+use crate::pool::{Pool, PoolStats};
+use crate::engin::error::*;
+use crate::engin::config::BackpressurePolicy;
+use crate::engin::supervisor::Supervisor;
+
+// 入口队列中的任务
+pub(crate) enum IngressTask {
+    // 新的输入, 由定序阶段编号
+    Input(PreProcessorTask),
+    // 主引擎已定序的输入, 保留原有编号
+    Replicated(PreProcessorTask),
+    // 备用引擎接管, 之后的输入照常编号并输出报告
+    Promote,
+}
+
+impl Traceable for IngressTask {
+    fn order_id(&self) -> OrderID {
+        match self {
+            IngressTask::Input(task) | IngressTask::Replicated(task) => task.order_id(),
+            IngressTask::Promote => 0,
+        }
+    }
+}
+
+struct IngressQueue {
+    tx : Option<Producer<Option<IngressTask>>>,
+    // 备用引擎只接受复制来的输入, 在同一把锁下切换, 接管标记之后不会再有复制的输入
+    standby : bool,
+    // 池只能由一个线程取用, 放在队列的锁下由所有入口共用
+    order_pool : Pool<NewOrder>,
+    cancel_pool : Pool<CancelRequest>,
+}
+
+impl IngressQueue {
+    // 从池中取出空闲的 Box 填入输入, exe 阶段处理完后回收
+    fn alloc(&self, inbound : Inbound) -> PreProcessorTask {
+        match inbound {
+            Inbound::NewOrder(order) => PreProcessorTask::NewOrder(self.order_pool.alloc(order)),
+            Inbound::CancelRequest(cancel_request) => PreProcessorTask::CancelRequest(self.cancel_pool.alloc(cancel_request)),
+        }
+    }
+}
+
+// 引擎入口, 可克隆后交给多个线程; 所有入口共用一个队列, 进入队列的先后即为定序阶段看到的全局顺序
+#[derive(Clone)]
+pub struct Ingress {
+    queue : Arc<Mutex<IngressQueue>>,
+    supervisor : Supervisor,
+    backpressure : BackpressurePolicy,
+}
+
+impl Ingress {
+    pub(crate) fn new(tx : Producer<Option<IngressTask>>, supervisor : Supervisor, backpressure : BackpressurePolicy, standby : bool,
+                      order_pool : Pool<NewOrder>, cancel_pool : Pool<CancelRequest>) -> Ingress {
+        let queue = IngressQueue { tx : Some(tx), standby, order_pool, cancel_pool };
+        Ingress { queue : Arc::new(Mutex::new(queue)), supervisor, backpressure }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, IngressQueue> {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn send(&self, queue : &IngressQueue, task : IngressTask) -> Result<(), EngineError> {
+        if self.supervisor.faulted() {
+            return Err(self.supervisor.error());
+        }
+        let tx = queue.tx.as_ref().ok_or(EngineError::Closed)?;
+        match self.backpressure {
+            BackpressurePolicy::Block => tx.send(Some(task)).map_err(|_| self.supervisor.error()),
+            BackpressurePolicy::Reject => tx.try_send(Some(task)).map_err(|e| match e {
+                TrySendError::Full(_) => EngineError::QueueFull,
+                TrySendError::Disconnected(_) => self.supervisor.error(),
+            }),
+        }
+    }
+
+    // 备用引擎接管前返回 EngineError::Standby
+    pub fn process(&self, task : PreProcessorTask) -> Result<(), EngineError> {
+        let queue = self.lock();
+        if queue.standby {
+            return Err(EngineError::Standby);
+        }
+        self.send(&queue, IngressTask::Input(task))
+    }
+
+    // 使用 Engin 的对象池, 与 Engin::submit 相同
+    pub fn submit(&self, inbound : Inbound) -> Result<(), EngineError> {
+        let queue = self.lock();
+        if queue.standby {
+            return Err(EngineError::Standby);
+        }
+        let task = queue.alloc(inbound);
+        self.send(&queue, IngressTask::Input(task))
+    }
+
+    pub(crate) fn alloc_new_order(&self, order : NewOrder) -> Box<NewOrder> {
+        self.lock().order_pool.alloc(order)
+    }
+
+    pub(crate) fn alloc_cancel_request(&self, cancel_request : CancelRequest) -> Box<CancelRequest> {
+        self.lock().cancel_pool.alloc(cancel_request)
+    }
+
+    pub(crate) fn pool_stats(&self) -> (PoolStats, PoolStats) {
+        let queue = self.lock();
+        (queue.order_pool.stats(), queue.cancel_pool.stats())
+    }
+
+    // 在当前位置插入快照标记, 标记之前进入队列的输入都包含在快照中; 备用引擎也可以保存快照
+    pub fn snapshot(&self) -> Result<(), EngineError> {
+        let queue = self.lock();
+        self.send(&queue, IngressTask::Input(PreProcessorTask::Snapshot(Box::default())))
+    }
+
+    // 在当前位置插入行情快照标记, 快照包含标记之前进入队列的输入引起的所有价位变化; 未启用行情时被丢弃
+    pub fn market_data_snapshot(&self) -> Result<(), EngineError> {
+        let queue = self.lock();
+        self.send(&queue, IngressTask::Input(PreProcessorTask::MarketDataSnapshot(0)))
+    }
+
+    // 送入主引擎已定序的输入, 只在备用引擎接管前可用, 之后返回 EngineError::Closed
+    pub(crate) fn replicate(&self, inbound : Inbound) -> Result<(), EngineError> {
+        let queue = self.lock();
+        if !queue.standby {
+            return Err(EngineError::Closed);
+        }
+        let task = queue.alloc(inbound);
+        self.send(&queue, IngressTask::Replicated(task))
+    }
+
+    pub fn is_standby(&self) -> bool {
+        self.lock().standby
+    }
+
+    // 备用引擎接管: 已送入的复制输入照常处理且不输出报告, 之后的输入接着编号并输出报告; 非备用引擎上无作用
+    pub fn promote(&self) -> Result<(), EngineError> {
+        let mut queue = self.lock();
+        if !queue.standby {
+            return Ok(());
+        }
+        self.send(&queue, IngressTask::Promote)?;
+        queue.standby = false;
+        Ok(())
+    }
+
+    // 通知定序阶段结束, 之后所有入口都返回 EngineError::Closed
+    pub(crate) fn close(&self) {
+        if let Some(tx) = self.lock().tx.take() {
+            let _ = tx.send(None);
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use std::io;
+
+use crate::types::SeqNum;
+use crate::messages::MarketDataTask;
+use crate::market_data::{MarketDataMessage, MarketDataSink, DepthSnapshots};
+
+// 行情阶段: 按 core 阶段的输出顺序给每条行情编号后交给 MarketDataSink
+pub struct MdProcessor {
+    md_seq_num : u64,
+    // 与 exe 阶段相同, 重放与备用期间的行情只编号不输出
+    suppress_until : SeqNum,
+    // 各证券最新的快照
+    snapshots : DepthSnapshots,
+}
+
+impl MdProcessor {
+    pub fn new(snapshots : DepthSnapshots) -> MdProcessor {
+        MdProcessor { md_seq_num : 0, suppress_until : 0, snapshots }
+    }
+
+    // 从快照恢复时接着快照中的编号
+    pub fn restore(&mut self, md_seq_num : u64) {
+        self.md_seq_num = md_seq_num;
+    }
+
+    pub fn suppress_until(&mut self, seq_num : SeqNum) {
+        self.suppress_until = seq_num;
+    }
+
+    pub fn process<S : MarketDataSink + ?Sized>(&mut self, task : MarketDataTask, sink : &mut S) -> io::Result<()> {
+        let suppressed = task.seq_num <= self.suppress_until;
+        for event in task.events {
+            self.md_seq_num += 1;
+            if !suppressed {
+                sink.publish(&MarketDataMessage { md_seq_num : self.md_seq_num, seq_num : task.seq_num, event })?;
+            }
+        }
+        // 快照不占用编号, 对应此前最后一条增量
+        for mut snapshot in task.snapshots {
+            snapshot.md_seq_num = self.md_seq_num;
+            if !suppressed {
+                sink.publish_snapshot(&snapshot)?;
+                self.snapshots.update(snapshot);
+            }
+        }
+        Ok(())
+    }
+}
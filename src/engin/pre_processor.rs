@@ -3,37 +3,72 @@ use std::collections::BTreeSet;
 use crate::types::CancelReasonCode;
 use crate::types::*;
 use crate::messages::*;
+use crate::engin::validator;
+use crate::engin::checksum::RollingHash;
 
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize)]
 pub struct PreProcessor {
     new_orders : BTreeMap<(PBUID, ClOrdID), OrigOrderInfoForCancel>,
-    cancel_requests : BTreeSet<(PBUID, ClOrdID)>
+    cancel_requests : BTreeSet<(PBUID, ClOrdID)>,
+    // 两个集合中元素的摘要, 随插入更新; 不写入快照, 恢复时重新计算
+    #[serde(skip)]
+    hash : RollingHash,
 }
 impl PreProcessor {
     pub fn new() -> PreProcessor {
         PreProcessor {
             new_orders : BTreeMap::new(),
-            cancel_requests : BTreeSet::new()
+            cancel_requests : BTreeSet::new(),
+            hash : RollingHash::default(),
         }
     }
 
     pub fn process(&mut self, task: PreProcessorTask) -> RcProcessorTask{
         match task {
             PreProcessorTask::NewOrder(new_order) => { self.process_new_order(new_order) },
-            PreProcessorTask::CancelRequest(cancel_request) => { self.process_cancel_request(cancel_request) }
+            PreProcessorTask::CancelRequest(cancel_request) => { self.process_cancel_request(cancel_request) },
+            PreProcessorTask::Snapshot(mut snapshot) => {
+                snapshot.pre = bincode::serialize(self).expect("serialize pre processor");
+                RcProcessorTask::Snapshot(snapshot)
+            },
+            PreProcessorTask::Checksum(mut checksum) => {
+                checksum.pre = self.hash.finish();
+                RcProcessorTask::Checksum(checksum)
+            },
+            PreProcessorTask::MarketDataSnapshot(seq_num) => RcProcessorTask::MarketDataSnapshot(seq_num),
         }
     }
 
+    pub fn restore(snapshot : &[u8]) -> bincode::Result<PreProcessor> {
+        let mut processor : PreProcessor = bincode::deserialize(snapshot)?;
+        let mut hash = RollingHash::default();
+        processor.new_orders.iter().for_each(|entry| hash.insert(&entry));
+        processor.cancel_requests.iter().for_each(|key| hash.insert(key));
+        processor.hash = hash;
+        Ok(processor)
+    }
+
     fn process_new_order(&mut self, new_order : Box<NewOrder>) -> RcProcessorTask {
+        if let Err(code) = validator::check_new_order(&new_order) {
+            return RcProcessorTask::NewOrderRejected((code, new_order));
+        }
+
         if self.cancel_requests.contains(&(new_order.pbu_id.clone(), new_order.cl_ord_id.clone())) {
             return RcProcessorTask::NewOrderRejected((CancelReasonCode::Duplicated, new_order));
         }
 
         let mut duplicated = false;
-        self.new_orders.entry((new_order.pbu_id.clone(), new_order.cl_ord_id.clone()))
+        let key = (new_order.pbu_id.clone(), new_order.cl_ord_id.clone());
+        let info = self.new_orders.entry(key)
            .and_modify(|_v| {
                 duplicated = true;
             })
             .or_insert(new_order.get_info_for_cancel());
+        if !duplicated {
+            self.hash.insert(&(&key, &*info));
+        }
         
         if duplicated {
             RcProcessorTask::NewOrderRejected((CancelReasonCode::Duplicated, new_order))
@@ -44,13 +79,19 @@ impl PreProcessor {
     }
 
     fn process_cancel_request(&mut self, cancel_request : Box<CancelRequest>) -> RcProcessorTask {
+        if let Err(code) = validator::check_cancel_request(&cancel_request) {
+            return RcProcessorTask::CancelRequestRejected((code, cancel_request));
+        }
+
         if self.new_orders.contains_key(&(cancel_request.pbu_id.clone(), cancel_request.cl_ord_id.clone())) {
             return RcProcessorTask::CancelRequestRejected((CancelReasonCode::Duplicated, cancel_request));
         }
         
-        if !self.cancel_requests.insert((cancel_request.pbu_id.clone(), cancel_request.cl_ord_id.clone())) {
+        let key = (cancel_request.pbu_id.clone(), cancel_request.cl_ord_id.clone());
+        if !self.cancel_requests.insert(key) {
             return RcProcessorTask::CancelRequestRejected((CancelReasonCode::Duplicated, cancel_request));
         }
+        self.hash.insert(&key);
 
         if let Some(info) = self.new_orders.get(&(cancel_request.pbu_id.clone(), cancel_request.orig_cl_ord_id.clone())) {
             if cancel_request.security_id != info.security_id {
@@ -103,6 +144,7 @@ mod tests {
                 pbu_id: to_array("000100"), 
                 cl_ord_id:to_array("123"),
                 order_id : 0,
+                seq_num : 0,
                 security_id : to_array("SEC001"),
                 price : 100,
                 qty : 100,
@@ -121,6 +163,7 @@ mod tests {
                 pbu_id: to_array("000100"), 
                 cl_ord_id: to_array("124"),
                 order_id : 1,
+                seq_num : 0,
                 security_id : to_array("SEC001"),
                 price : 110,
                 qty : 100,
@@ -139,6 +182,7 @@ mod tests {
                 pbu_id: to_array("000100"), 
                 cl_ord_id:to_array("123"),
                 order_id : 0,
+                seq_num : 0,
                 security_id : to_array("SEC001"),
                 price : 100,
                 qty : 100,
@@ -153,6 +197,7 @@ mod tests {
                 pbu_id: to_array("000100"), 
                 cl_ord_id: to_array("123"),
                 order_id : 0,
+                seq_num : 0,
                 security_id : to_array("SEC001"),
                 orig_cl_ord_id : to_array("124")
             }
@@ -166,6 +211,7 @@ mod tests {
                 pbu_id: to_array("000100"), 
                 cl_ord_id: to_array("125"),
                 order_id : 0,
+                seq_num : 0,
                 security_id : to_array("SEC002"),
                 orig_cl_ord_id : to_array("124")
             }
@@ -179,6 +225,7 @@ mod tests {
                 pbu_id: to_array("000100"), 
                 cl_ord_id: to_array("125"),
                 order_id : 0,
+                seq_num : 0,
                 security_id : to_array("SEC001"),
                 orig_cl_ord_id : to_array("124")
             }
@@ -192,6 +239,7 @@ mod tests {
                 pbu_id: to_array("000100"), 
                 cl_ord_id: to_array("126"),
                 order_id : 0,
+                seq_num : 0,
                 security_id : to_array("SEC001"),
                 orig_cl_ord_id : to_array("124")
             }
@@ -213,6 +261,7 @@ mod tests {
                 pbu_id: to_array("000100"), 
                 cl_ord_id: to_array("126"),
                 order_id : 0,
+                seq_num : 0,
                 security_id : to_array("SEC001"),
                 price : 100,
                 qty : 100,
@@ -225,4 +274,65 @@ mod tests {
 
 
     }
+
+    #[test]
+    fn test_invalid_new_order() {
+        let mut p = PreProcessor::new();
+
+        let order = Box::new(
+            NewOrder {
+                pbu_id: to_array("000100"), 
+                cl_ord_id:to_array("123"),
+                order_id : 0,
+                seq_num : 0,
+                security_id : to_array("SEC001"),
+                price : 100,
+                qty : 0,
+                side : 'B'
+        });
+        let task = p.process(PreProcessorTask::NewOrder(order));
+        assert_cancel_reason(&task, CancelReasonCode::InvalidQty);
+
+        // 被拒绝的非法委托不占用 ClOrdID
+        let order = Box::new(
+            NewOrder {
+                pbu_id: to_array("000100"), 
+                cl_ord_id:to_array("123"),
+                order_id : 1,
+                seq_num : 0,
+                security_id : to_array("SEC001"),
+                price : 100,
+                qty : 100,
+                side : 'X'
+        });
+        let task = p.process(PreProcessorTask::NewOrder(order));
+        assert_cancel_reason(&task, CancelReasonCode::InvalidSide);
+
+        let order = Box::new(
+            NewOrder {
+                pbu_id: to_array("000100"), 
+                cl_ord_id:to_array("123"),
+                order_id : 2,
+                seq_num : 0,
+                security_id : to_array("SEC001"),
+                price : 100,
+                qty : 100,
+                side : 'B'
+        });
+        let task = p.process(PreProcessorTask::NewOrder(order));
+        assert!(matches!(task, RcProcessorTask::NewOrder(_)));
+
+        let cancel = Box::new(
+            CancelRequest {
+                pbu_id: to_array("000100"), 
+                cl_ord_id: to_array(""),
+                order_id : 3,
+                seq_num : 0,
+                security_id : to_array("SEC001"),
+                orig_cl_ord_id : to_array("123")
+            }
+        );
+        let task = p.process(PreProcessorTask::CancelRequest(cancel));
+        assert_cancel_reason(&task, CancelReasonCode::InvalidClOrdID);
+    }
 }
@@ -1,12 +1,15 @@
+use std::collections::BTreeMap;
+
 use crate::messages::*;
+use crate::types::*;
 
 pub struct RcProcessor {
-
+    price_limits : BTreeMap<SecurityID, PriceLimit>,
 }
 
 impl RcProcessor {
-    pub fn new() -> RcProcessor {
-        RcProcessor{}
+    pub fn new(price_limits : BTreeMap<SecurityID, PriceLimit>) -> RcProcessor {
+        RcProcessor{ price_limits }
     }
 
     pub fn process(&mut self, task : RcProcessorTask) -> CoreProcessorTask {
@@ -15,10 +18,59 @@ impl RcProcessor {
             RcProcessorTask::NewOrderRejected(info) => CoreProcessorTask::NewOrderRejected(info),
             RcProcessorTask::CancelRequest(info, cancel_request) => CoreProcessorTask::CancelRequest(info, cancel_request),
             RcProcessorTask::CancelRequestRejected(info) => CoreProcessorTask::CancelRequestRejected(info),
+            // 本阶段只有配置, 没有需要写入快照的状态
+            RcProcessorTask::Snapshot(snapshot) => CoreProcessorTask::Snapshot(snapshot),
+            RcProcessorTask::Checksum(checksum) => CoreProcessorTask::Checksum(checksum),
+            RcProcessorTask::MarketDataSnapshot(seq_num) => CoreProcessorTask::MarketDataSnapshot(seq_num),
         }
     }
 
     pub fn process_new_order(&mut self, order : Box<NewOrder>) -> CoreProcessorTask {
+        if let Some(limit) = self.price_limits.get(&order.security_id) {
+            if order.price < limit.min_price || order.price > limit.max_price {
+                return CoreProcessorTask::NewOrderRejected((CancelReasonCode::PriceOutOfRange, order));
+            }
+            if (order.price - limit.min_price) % limit.tick != 0 {
+                return CoreProcessorTask::NewOrderRejected((CancelReasonCode::InvalidPriceTick, order));
+            }
+        }
         CoreProcessorTask::NewOrder( order, Box::new(RcResult{}))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_rejected(task : CoreProcessorTask, code : CancelReasonCode) {
+        match task {
+            CoreProcessorTask::NewOrderRejected((tmp_code, _)) => assert_eq!(tmp_code, code),
+            _ => panic!("order should be rejected"),
+        }
+    }
+
+    #[test]
+    fn test_price_limit() {
+        let mut limits = BTreeMap::new();
+        limits.insert(to_array("SEC001"), PriceLimit { min_price : 900, max_price : 1100, tick : 5 });
+        let mut p = RcProcessor::new(limits);
+
+        let order = NewOrder {
+            order_id : 1,
+            seq_num : 0,
+            pbu_id : to_array("000100"),
+            cl_ord_id : to_array("1"),
+            security_id : to_array("SEC001"),
+            side : K_BUY,
+            price : 1000,
+            qty : 100
+        };
+        assert!(matches!(p.process_new_order(Box::new(order)), CoreProcessorTask::NewOrder(..)));
+        assert_rejected(p.process_new_order(Box::new(NewOrder { price : 1105, ..order })), CancelReasonCode::PriceOutOfRange);
+        assert_rejected(p.process_new_order(Box::new(NewOrder { price : 895, ..order })), CancelReasonCode::PriceOutOfRange);
+        assert_rejected(p.process_new_order(Box::new(NewOrder { price : 1001, ..order })), CancelReasonCode::InvalidPriceTick);
+
+        let order = NewOrder { security_id : to_array("SEC002"), price : 1001, ..order };
+        assert!(matches!(p.process_new_order(Box::new(order)), CoreProcessorTask::NewOrder(..)));
+    }
+}
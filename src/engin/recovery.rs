@@ -0,0 +1,67 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use crate::types::*;
+use crate::messages::*;
+use crate::journal::{corrupted, JournalReader};
+use crate::engin::snapshot::SnapshotStore;
+
+// 崩溃前已定序的输入; Engin 启动时由定序阶段保留原有编号按顺序送入流水线,
+// 重建订单簿、重复委托检查的状态与 exec_id, 之后才处理入口队列中新的输入
+pub struct Recovery {
+    // 各阶段先从快照恢复, inputs 只包含快照之后的输入
+    pub snapshot : Option<Snapshot>,
+    pub inputs : Vec<Inbound>,
+    // 序号不超过此值的输入所产生的报告在崩溃前已经送达, 重放时不再输出
+    pub delivered_seq_num : SeqNum,
+}
+
+impl Recovery {
+    // delivered_seq_num 由调用方根据下游确认的报告给出, 例如网关报告日志中最后一条报告对应的输入;
+    // 无法确定时传 0, 重放的输入的报告全部重新输出
+    pub fn new(inputs : Vec<Inbound>, delivered_seq_num : SeqNum) -> Recovery {
+        Recovery { snapshot : None, inputs, delivered_seq_num }
+    }
+
+    pub fn from_journal<P : AsRef<Path>>(path : P, delivered_seq_num : SeqNum) -> io::Result<Recovery> {
+        Ok(Recovery::new(read_journal(path, 0)?, delivered_seq_num))
+    }
+
+    // 从不晚于 delivered_seq_num 的最新快照恢复, 只重放日志中快照之后的输入; 快照之后未送达的报告在重放时重新输出
+    pub fn from_snapshot<P : AsRef<Path>>(snapshots : &SnapshotStore, journal : P, delivered_seq_num : SeqNum) -> io::Result<Recovery> {
+        let snapshot = snapshots.latest_until(delivered_seq_num)?;
+        let seq_num = snapshot.as_ref().map(|snapshot| snapshot.seq_num).unwrap_or(0);
+        let inputs = read_journal(journal, seq_num)?;
+        Ok(Recovery { snapshot, inputs, delivered_seq_num })
+    }
+
+    // 实际被抑制的报告不超过最后一条重放的输入
+    pub(crate) fn suppress_until(&self) -> SeqNum {
+        self.inputs.last().map(Inbound::seq_num).unwrap_or(0).min(self.delivered_seq_num)
+    }
+
+    pub(crate) fn tasks(self) -> impl Iterator<Item = PreProcessorTask> {
+        self.inputs.into_iter().map(|input| match input {
+            Inbound::NewOrder(order) => PreProcessorTask::NewOrder(Box::new(order)),
+            Inbound::CancelRequest(cancel_request) => PreProcessorTask::CancelRequest(Box::new(cancel_request)),
+        })
+    }
+}
+
+// 读出日志中序号大于 after 的输入, 只取完整且校验通过的部分, 与 Journal::open 截断后保留的内容一致
+fn read_journal<P : AsRef<Path>>(path : P, after : SeqNum) -> io::Result<Vec<Inbound>> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut inputs = Vec::new();
+    let mut reader = JournalReader::new(BufReader::new(file));
+    while let Some(entry) = reader.next() {
+        match entry {
+            Ok(input) if input.seq_num() > after => inputs.push(input),
+            Ok(_) => {},
+            Err(e) if reader.torn_tail(&e, len) => break,
+            Err(e) => return Err(corrupted(e)),
+        }
+    }
+    Ok(inputs)
+}
@@ -0,0 +1,108 @@
+use crate::types::*;
+use crate::messages::{PreProcessorTask, StateChecksum};
+
+// 入口定序: 按到达顺序为每条输入分配唯一的 order_id 和连续的全局输入序号, 均从 1 开始
+pub struct Sequencer {
+    next_order_id : OrderID,
+    next_seq_num : SeqNum,
+    // 每隔多少条输入插入一个摘要标记, 0 为不插入
+    checksum_interval : SeqNum,
+}
+
+impl Sequencer {
+    pub fn new() -> Sequencer {
+        Sequencer { next_order_id : 1, next_seq_num : 1, checksum_interval : 0 }
+    }
+
+    pub fn set_checksum_interval(&mut self, interval : SeqNum) {
+        self.checksum_interval = interval;
+    }
+
+    // 输入的序号是间隔的整数倍时, 紧随其后送出摘要标记; 重放与复制的输入也一样, 各次运行在相同的序号上计算摘要
+    pub fn checkpoint(&self, task : &PreProcessorTask) -> Option<PreProcessorTask> {
+        let seq_num = task.inbound()?.seq_num();
+        if self.checksum_interval == 0 || seq_num % self.checksum_interval != 0 {
+            return None;
+        }
+        Some(PreProcessorTask::Checksum(Box::new(StateChecksum::new(seq_num))))
+    }
+
+    pub fn process(&mut self, mut task : PreProcessorTask) -> PreProcessorTask {
+        let (order_id, seq_num) = match &mut task {
+            PreProcessorTask::NewOrder(order) => (&mut order.order_id, &mut order.seq_num),
+            PreProcessorTask::CancelRequest(cancel_request) => (&mut cancel_request.order_id, &mut cancel_request.seq_num),
+            // 快照包含序号不超过 seq_num 的所有输入
+            PreProcessorTask::Snapshot(snapshot) => {
+                snapshot.seq_num = self.next_seq_num - 1;
+                snapshot.next_order_id = self.next_order_id;
+                return task;
+            },
+            PreProcessorTask::Checksum(checksum) => {
+                checksum.seq_num = self.next_seq_num - 1;
+                return task;
+            },
+            PreProcessorTask::MarketDataSnapshot(seq_num) => {
+                *seq_num = self.next_seq_num - 1;
+                return task;
+            },
+        };
+        *order_id = self.next_order_id;
+        *seq_num = self.next_seq_num;
+        self.next_order_id += 1;
+        self.next_seq_num += 1;
+        task
+    }
+
+    // 从快照恢复, seq_num 为快照包含的最后一条输入的序号
+    pub fn restore(&mut self, seq_num : SeqNum, next_order_id : OrderID) {
+        self.next_order_id = next_order_id;
+        self.next_seq_num = seq_num + 1;
+    }
+
+    // 最后一条已编号输入的序号
+    pub fn last_seq_num(&self) -> SeqNum {
+        self.next_seq_num - 1
+    }
+
+    // 重放已定序的输入时保留原有编号, 之后的输入接着分配
+    pub fn resume(&mut self, task : &PreProcessorTask) {
+        let (order_id, seq_num) = match task {
+            PreProcessorTask::NewOrder(order) => (order.order_id, order.seq_num),
+            PreProcessorTask::CancelRequest(cancel_request) => (cancel_request.order_id, cancel_request.seq_num),
+            PreProcessorTask::Snapshot(_) | PreProcessorTask::Checksum(_) | PreProcessorTask::MarketDataSnapshot(_) => return,
+        };
+        self.next_order_id = self.next_order_id.max(order_id + 1);
+        self.next_seq_num = self.next_seq_num.max(seq_num + 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::NewOrder;
+
+    #[test]
+    fn stamp_in_arrival_order() {
+        let mut sequencer = Sequencer::new();
+        let order = NewOrder { order_id : 100, seq_num : 100, ..NewOrder::default() };
+        match sequencer.process(PreProcessorTask::NewOrder(Box::new(order))) {
+            PreProcessorTask::NewOrder(order) => assert_eq!((order.order_id, order.seq_num), (1, 1)),
+            other => panic!("unexpected {:?}", other),
+        }
+        match sequencer.process(PreProcessorTask::CancelRequest(Box::default())) {
+            PreProcessorTask::CancelRequest(cancel_request) => assert_eq!((cancel_request.order_id, cancel_request.seq_num), (2, 2)),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn resume_after_replay() {
+        let mut sequencer = Sequencer::new();
+        let order = NewOrder { order_id : 7, seq_num : 5, ..NewOrder::default() };
+        sequencer.resume(&PreProcessorTask::NewOrder(Box::new(order)));
+        match sequencer.process(PreProcessorTask::NewOrder(Box::default())) {
+            PreProcessorTask::NewOrder(order) => assert_eq!((order.order_id, order.seq_num), (8, 6)),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+use serde::{Serialize, Deserialize};
+
+use crate::types::*;
+use crate::messages::Snapshot;
+use crate::journal::crc32;
+
+// 快照格式的版本, 任一阶段状态的编码变化时递增
+// 2: 簿按价位导出
+// 3: 行情编号
+// 4: 未完成委托的成交金额
+pub const SNAPSHOT_VERSION : u16 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct SnapshotHeader {
+    version : u16,
+}
+
+// 快照目录: 每个快照一个文件, 以 seq_num 命名, 先写临时文件再改名, 不会读到写了一半的快照
+pub struct SnapshotStore {
+    dir : PathBuf,
+}
+
+impl SnapshotStore {
+    pub fn open<P : AsRef<Path>>(dir : P) -> io::Result<SnapshotStore> {
+        fs::create_dir_all(&dir)?;
+        Ok(SnapshotStore { dir : dir.as_ref().to_path_buf() })
+    }
+
+    pub fn save(&self, snapshot : &Snapshot) -> io::Result<PathBuf> {
+        self.write(snapshot.seq_num, &encode(snapshot)?)
+    }
+
+    // 写入已编码的快照
+    fn write(&self, seq_num : SeqNum, bytes : &[u8]) -> io::Result<PathBuf> {
+        let path = self.dir.join(format!("snapshot-{:020}.bin", seq_num));
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &path)?;
+        Ok(path)
+    }
+
+    // 目录中已有快照的序号, 从小到大
+    pub fn seq_nums(&self) -> io::Result<Vec<SeqNum>> {
+        let mut seq_nums = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let seq_num : Option<SeqNum> = name.to_str()
+                .and_then(|name| name.strip_prefix("snapshot-"))
+                .and_then(|name| name.strip_suffix(".bin"))
+                .and_then(|seq_num| seq_num.parse().ok());
+            seq_nums.extend(seq_num);
+        }
+        seq_nums.sort_unstable();
+        Ok(seq_nums)
+    }
+
+    pub fn load(&self, seq_num : SeqNum) -> io::Result<Snapshot> {
+        let bytes = fs::read(self.dir.join(format!("snapshot-{:020}.bin", seq_num)))?;
+        if bytes.len() < 4 || crc32(&bytes[4..]) != u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("snapshot {} checksum mismatch", seq_num)));
+        }
+        let mut reader = &bytes[4..];
+        let header : SnapshotHeader = bincode::deserialize_from(&mut reader).map_err(invalid_data)?;
+        if header.version != SNAPSHOT_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported snapshot version {}", header.version)));
+        }
+        bincode::deserialize_from(&mut reader).map_err(invalid_data)
+    }
+
+    // 最新的可用快照; 损坏或版本不符的快照被跳过
+    pub fn latest(&self) -> io::Result<Option<Snapshot>> {
+        self.latest_until(SeqNum::MAX)
+    }
+
+    // 序号不超过 seq_num 的最新可用快照
+    pub fn latest_until(&self, seq_num : SeqNum) -> io::Result<Option<Snapshot>> {
+        for seq_num in self.seq_nums()?.into_iter().rev().filter(|n| *n <= seq_num) {
+            match self.load(seq_num) {
+                Ok(snapshot) => return Ok(Some(snapshot)),
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+}
+
+// exe 阶段的后台写入线程: exe 线程只编码快照, 文件写入与 fsync 在这里完成, 不阻塞报告输出.
+// 写入失败时记下错误, 下一次保存时返回; 丢弃时写完已交出的快照再停止
+pub(crate) struct SnapshotWriter {
+    tx : Option<Sender<(SeqNum, Vec<u8>)>>,
+    error : Arc<Mutex<Option<io::Error>>>,
+    handle : Option<JoinHandle<()>>,
+}
+
+impl SnapshotWriter {
+    pub(crate) fn spawn(store : SnapshotStore) -> SnapshotWriter {
+        let (tx, rx) = mpsc::channel::<(SeqNum, Vec<u8>)>();
+        let error = Arc::new(Mutex::new(None));
+        let failed = error.clone();
+        let handle = thread::spawn(move || {
+            for (seq_num, bytes) in rx {
+                if let Err(e) = store.write(seq_num, &bytes) {
+                    *failed.lock().unwrap_or_else(|e| e.into_inner()) = Some(e);
+                }
+            }
+        });
+        SnapshotWriter { tx : Some(tx), error, handle : Some(handle) }
+    }
+
+    pub(crate) fn save(&self, snapshot : &Snapshot) -> io::Result<()> {
+        if let Some(e) = self.error.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            return Err(e);
+        }
+        let bytes = encode(snapshot)?;
+        match &self.tx {
+            Some(tx) => tx.send((snapshot.seq_num, bytes)).map_err(|_| io::ErrorKind::BrokenPipe.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for SnapshotWriter {
+    fn drop(&mut self) {
+        self.tx = None;
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+// 文件内容: 版本头与快照的 crc32, 之后是版本头与快照
+fn encode(snapshot : &Snapshot) -> io::Result<Vec<u8>> {
+    let mut body = bincode::serialize(&SnapshotHeader { version : SNAPSHOT_VERSION }).map_err(invalid_data)?;
+    bincode::serialize_into(&mut body, snapshot).map_err(invalid_data)?;
+    let mut bytes = Vec::with_capacity(body.len() + 4);
+    bytes.extend_from_slice(&crc32(&body).to_le_bytes());
+    bytes.extend_from_slice(&body);
+    Ok(bytes)
+}
+
+fn invalid_data(e : bincode::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn latest_skips_corrupted() {
+        let dir = std::env::temp_dir().join(format!("snapshots_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let store = SnapshotStore::open(&dir).unwrap();
+        assert_eq!(store.latest().unwrap(), None);
+
+        let snapshot = Snapshot { seq_num : 10, next_order_id : 11, pre : vec![1, 2], core : vec![3], exec_id : 7, notional : BTreeMap::from([(3, 1500)]), md_seq_num : 12 };
+        store.save(&snapshot).unwrap();
+        let path = store.save(&Snapshot { seq_num : 20, ..snapshot.clone() }).unwrap();
+        assert_eq!(store.seq_nums().unwrap(), vec![10, 20]);
+        assert_eq!(store.latest().unwrap().unwrap().seq_num, 20);
+
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(store.latest().unwrap(), Some(snapshot));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn background_writer() {
+        let dir = std::env::temp_dir().join(format!("snapshot_writer_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let writer = SnapshotWriter::spawn(SnapshotStore::open(&dir).unwrap());
+        let snapshot = Snapshot { seq_num : 10, next_order_id : 11, ..Snapshot::default() };
+        writer.save(&snapshot).unwrap();
+        writer.save(&Snapshot { seq_num : 20, ..snapshot.clone() }).unwrap();
+        // 丢弃时写完已交出的快照
+        drop(writer);
+        let store = SnapshotStore::open(&dir).unwrap();
+        assert_eq!(store.seq_nums().unwrap(), vec![10, 20]);
+        assert_eq!(store.load(10).unwrap(), snapshot);
+
+        // 写入失败在下一次保存时返回
+        fs::remove_dir_all(&dir).unwrap();
+        let writer = SnapshotWriter::spawn(store);
+        writer.save(&snapshot).unwrap();
+        while writer.error.lock().unwrap().is_none() {
+            thread::yield_now();
+        }
+        assert!(writer.save(&snapshot).is_err());
+    }
+}
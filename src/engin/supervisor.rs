@@ -0,0 +1,151 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::messages::Traceable;
+use crate::ring_buffer::Consumer;
+use crate::engin::error::*;
+
+#[derive(Default)]
+struct Faults {
+    faulted : AtomicBool,
+    faults : Mutex<Vec<StageFault>>,
+}
+
+// 各阶段线程共享同一个 Supervisor, 出错时登记故障而不是直接 panic
+#[derive(Clone, Default)]
+pub struct Supervisor {
+    inner : Arc<Faults>,
+}
+
+impl Supervisor {
+    pub fn new() -> Supervisor {
+        Supervisor::default()
+    }
+
+    pub fn report(&self, fault : StageFault) {
+        if let Ok(mut faults) = self.inner.faults.lock() {
+            faults.push(fault);
+        }
+        self.inner.faulted.store(true, Ordering::Release);
+    }
+
+    pub fn faulted(&self) -> bool {
+        self.inner.faulted.load(Ordering::Acquire)
+    }
+
+    // 最先登记的故障即为根因, 其余多为上游阶段随之出现的 Disconnected
+    pub fn first_fault(&self) -> Option<StageFault> {
+        self.inner.faults.lock().ok().and_then(|faults| faults.first().cloned())
+    }
+
+    pub fn faults(&self) -> Vec<StageFault> {
+        self.inner.faults.lock().map(|faults| faults.clone()).unwrap_or_default()
+    }
+
+    pub fn error(&self) -> EngineError {
+        match self.first_fault() {
+            Some(fault) => EngineError::StageFailed(fault),
+            None => EngineError::Closed,
+        }
+    }
+
+    // 阶段主循环: 收到 None 正常结束返回 true; 上游断开或本阶段出错返回 false
+    pub fn run<T, F>(&self, stage : Stage, rx : &Consumer<Option<T>>, mut handle : F) -> bool
+        where T : Traceable, F : FnMut(T) -> Result<(), FaultKind> {
+        loop {
+            let task = match rx.recv() {
+                Ok(Some(task)) => task,
+                Ok(None) => return true,
+                Err(_) => return false,
+            };
+            let order_id = task.order_id();
+            let kind = match panic::catch_unwind(AssertUnwindSafe(|| handle(task))) {
+                Ok(Ok(())) => continue,
+                Ok(Err(kind)) => kind,
+                Err(payload) => FaultKind::Panicked(panic_message(payload)),
+            };
+            self.report(StageFault { stage, order_id : Some(order_id), kind });
+            return false;
+        }
+    }
+}
+
+fn panic_message(payload : Box<dyn Any + Send>) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    }
+    else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    }
+    else {
+        String::from("unknown panic")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ring_buffer::{ring_buffer, WaitStrategy};
+    use crate::types::OrderID;
+
+    struct Task(OrderID);
+
+    impl Traceable for Task {
+        fn order_id(&self) -> OrderID {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_run_until_closed() {
+        let supervisor = Supervisor::new();
+        let (tx, rx) = ring_buffer(8, WaitStrategy::Yield);
+        tx.send(Some(Task(1))).unwrap();
+        tx.send(Some(Task(2))).unwrap();
+        tx.send(None).unwrap();
+
+        let mut count = 0;
+        assert!(supervisor.run(Stage::Pre, &rx, |_task| { count += 1; Ok(()) }));
+        assert_eq!(count, 2);
+        assert!(!supervisor.faulted());
+    }
+
+    #[test]
+    fn test_report_panic() {
+        let supervisor = Supervisor::new();
+        let (tx, rx) = ring_buffer(8, WaitStrategy::Yield);
+        tx.send(Some(Task(1))).unwrap();
+        tx.send(Some(Task(2))).unwrap();
+        tx.send(None).unwrap();
+
+        let closed = supervisor.run(Stage::Core, &rx, |task| {
+            if task.0 == 2 {
+                panic!("bad order {}", task.0);
+            }
+            Ok(())
+        });
+        assert!(!closed);
+        assert!(supervisor.faulted());
+        assert_eq!(supervisor.error(), EngineError::StageFailed(StageFault {
+            stage : Stage::Core,
+            order_id : Some(2),
+            kind : FaultKind::Panicked(String::from("bad order 2"))
+        }));
+    }
+
+    #[test]
+    fn test_report_disconnected() {
+        let supervisor = Supervisor::new();
+        let (tx, rx) = ring_buffer(8, WaitStrategy::Yield);
+        tx.send(Some(Task(7))).unwrap();
+        drop(tx);
+
+        assert!(!supervisor.run(Stage::Rc, &rx, |_task| Err(FaultKind::Disconnected)));
+        let fault = supervisor.first_fault().unwrap();
+        assert_eq!(fault.stage, Stage::Rc);
+        assert_eq!(fault.order_id, Some(7));
+        assert_eq!(fault.kind, FaultKind::Disconnected);
+    }
+}
@@ -5,11 +5,10 @@ use crate::types::*;
 use crate::auction::continuos::Continuos;
 use crate::auction::continuos::TradingSessionData;
 
-use std::sync::Arc;
 use std::rc::Rc;
 
 pub struct NewOrderForBook {
-    order : Arc<NewOrder>,
+    order : NewOrder,
     rc_info : Box<RcResult>
 } 
 impl AuctionOrder for NewOrderForBook {
@@ -39,16 +38,16 @@ impl TradingSessionData<NewOrderForBook> for TradingSession {
 }
 
 impl TradingSession {
-    pub fn new() -> TradingSession {
+    pub fn with_backend(backend : BookBackend) -> TradingSession {
         TradingSession {  
-            buy_order_book : PriceOrderBook::create_high_price_priority_order_book(),
-            sell_order_book : PriceOrderBook::create_low_price_priority_order_book(),
+            buy_order_book : PriceOrderBook::create_high_price_priority_order_book_with_backend(backend),
+            sell_order_book : PriceOrderBook::create_low_price_priority_order_book_with_backend(backend),
         }
     }
-    pub fn process_new_order(&mut self, order : Arc<NewOrder>, rc_info : Box<RcResult>) -> Vec<ExecutionTask> {
+    pub fn process_new_order(&mut self, order : NewOrder, rc_info : Box<RcResult>) -> Vec<ExecutionTask> {
         let mut c = Continuos::<NewOrderForBook> { session : self};
         let side = order.side;
-        let tmp = Rc::new(NewOrderForBook {order:order.clone(), rc_info : rc_info});
+        let tmp = Rc::new(NewOrderForBook {order, rc_info : rc_info});
         let consumed_orders = c.process_new_order(side, tmp);
 
         let mut tasks = Vec::new();
@@ -57,9 +56,9 @@ impl TradingSession {
             leaves_qty -= contra.consumed_qty;
             tasks.push(ExecutionTask::NewoOrderMatched(
                 OrderMatchedInfo {
-                    order1 : order.clone(),
+                    order1 : order,
                     leaves_qty1 : leaves_qty,
-                    order2 : contra.orig_order.order.clone(),
+                    order2 : contra.orig_order.order,
                     leaves_qty2 : contra.leaves_qty,
                     last_px : contra.orig_order.price(),
                     last_qty : contra.consumed_qty
@@ -68,10 +67,39 @@ impl TradingSession {
         });
         tasks
     }
+    // 按价格优先、时间优先导出一边的簿
+    pub fn export(&self, side : Side) -> Vec<BookLevel<NewOrder>> {
+        let book = if side == K_BUY { &self.buy_order_book } else { &self.sell_order_book };
+        book.export(|order| order.order)
+    }
+
+    // 一边按价格优先排列的各价位 (price, qty, order_count)
+    pub fn depth(&self, side : Side) -> Vec<(Price, Qty, u32)> {
+        let book = if side == K_BUY { &self.buy_order_book } else { &self.sell_order_book };
+        let mut iter = book.price_iter();
+        let mut levels = Vec::new();
+        while let Some((price, qty)) = iter.next() {
+            let order_count = book.level(price).map(|(_, order_count)| order_count).unwrap_or(0);
+            levels.push((price, qty, order_count as u32));
+        }
+        levels
+    }
+
+    pub fn level(&self, side : Side, price : Price) -> Option<(Qty, usize)> {
+        let book = if side == K_BUY { &self.buy_order_book } else { &self.sell_order_book };
+        book.level(price)
+    }
+
+    // 导入 export 的结果, 恢复原来的排队顺序
+    pub fn import(&mut self, side : Side, levels : Vec<BookLevel<NewOrder>>) {
+        let book = if side == K_BUY { &mut self.buy_order_book } else { &mut self.sell_order_book };
+        book.import(levels, |order| NewOrderForBook { order, rc_info : Box::new(RcResult{}) });
+    }
+
     pub fn process_cancel_request(&mut self, orig_info : &OrigOrderInfoForCancel, cancel_request : Box<CancelRequest>) -> ExecutionTask {
         let mut c = Continuos::<NewOrderForBook> { session : self};
         if let Some(orig) = c.process_cancel_request(orig_info) {
-            ExecutionTask::CancelRequestAccepted(orig.consumed_qty, cancel_request, orig.orig_order.order.clone())
+            ExecutionTask::CancelRequestAccepted(orig.consumed_qty, cancel_request, orig.orig_order.order)
         }
         else {
             ExecutionTask::CancelRequestRejected(CancelReasonCode::OrderNotExisted, cancel_request)
@@ -81,11 +109,10 @@ impl TradingSession {
 
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
-
     use crate::{types::*, messages::NewOrder};
     use crate::messages::{RcResult, ExecutionTask};
 
+    use crate::order_book::BookBackend;
     use super::TradingSession;
 
 
@@ -97,18 +124,19 @@ mod tests {
         fn new() -> OrderGen {
             OrderGen { order_id: 0 }
         }
-        fn gen_order(&mut self, side : Side, price : Price, qty : Qty) -> Arc<NewOrder> {
+        fn gen_order(&mut self, side : Side, price : Price, qty : Qty) -> NewOrder {
             self.order_id += 1;
 
-            Arc::new(NewOrder { 
+            NewOrder { 
                 order_id: self.order_id,
+                seq_num: 0,
                 pbu_id: to_array("PBU001"), 
                 cl_ord_id: to_array(""),
                 security_id: to_array(""), 
                 side: side, 
                 price: price, 
                 qty: qty
-            })
+            }
         }
     }
 
@@ -127,7 +155,7 @@ mod tests {
     #[test]
     fn test_new_order() {
         let mut gen = OrderGen::new();
-        let mut session = TradingSession::new();
+        let mut session = TradingSession::with_backend(BookBackend::Tree);
 
         let order = gen.gen_order(K_BUY, 20, 50);
         session.process_new_order(order, Box::new(RcResult{}));
@@ -144,4 +172,34 @@ mod tests {
 
     }
 
-}
\ No newline at end of file
+    fn matched(tasks : &[ExecutionTask]) -> Vec<(OrderID, Qty, Qty)> {
+        tasks.iter().map(|task| match task {
+            ExecutionTask::NewoOrderMatched(info) => (info.order2.order_id, info.last_qty, info.leaves_qty2),
+            _ => panic!("unexpected task"),
+        }).collect()
+    }
+
+    #[test]
+    fn test_export_import() {
+        let mut gen = OrderGen::new();
+        let mut session = TradingSession::with_backend(BookBackend::Tree);
+        session.process_new_order(gen.gen_order(K_BUY, 30, 50), Box::new(RcResult{}));
+        session.process_new_order(gen.gen_order(K_BUY, 20, 50), Box::new(RcResult{}));
+        session.process_new_order(gen.gen_order(K_BUY, 30, 40), Box::new(RcResult{}));
+        session.process_new_order(gen.gen_order(K_SELL, 30, 20), Box::new(RcResult{}));
+
+        // 导入后排队顺序与剩余数量不变
+        let levels = session.export(K_BUY);
+        assert_eq!(levels.iter().map(|level| level.price).collect::<Vec<Price>>(), vec![30, 20]);
+        assert_eq!(levels[0].orders.iter().map(|(order, leaves_qty)| (order.order_id, *leaves_qty)).collect::<Vec<_>>(), vec![(1, 30), (3, 40)]);
+        let mut imported = TradingSession::with_backend(BookBackend::Ladder { min_price : 10, max_price : 40, tick : 1 });
+        imported.import(K_BUY, levels.clone());
+        assert_eq!(imported.export(K_BUY), levels);
+        assert!(imported.export(K_SELL).is_empty());
+
+        let order = gen.gen_order(K_SELL, 20, 100);
+        let expected = matched(&session.process_new_order(order, Box::new(RcResult{})));
+        assert_eq!(matched(&imported.process_new_order(order, Box::new(RcResult{}))), expected);
+        assert_eq!(expected, vec![(1, 30, 0), (3, 40, 0), (2, 30, 20)]);
+    }
+}
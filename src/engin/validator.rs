@@ -0,0 +1,124 @@
+use crate::messages::*;
+use crate::types::*;
+
+// 字段格式: 左对齐的 ASCII 字母数字, 右侧以空格补齐, 不能全为空格
+fn is_valid_id<const N : usize>(id : &[u8;N]) -> bool {
+    let len = id.iter().position(|c| *c == b' ').unwrap_or(N);
+    len > 0
+        && id[..len].iter().all(|c| c.is_ascii_alphanumeric())
+        && id[len..].iter().all(|c| *c == b' ')
+}
+
+pub fn check_new_order(order : &NewOrder) -> Result<(), CancelReasonCode> {
+    if !is_valid_id(&order.pbu_id) {
+        return Err(CancelReasonCode::InvalidPBUID);
+    }
+    if !is_valid_id(&order.cl_ord_id) {
+        return Err(CancelReasonCode::InvalidClOrdID);
+    }
+    if !is_valid_id(&order.security_id) {
+        return Err(CancelReasonCode::InvalidSecurityID);
+    }
+    if order.side != K_BUY && order.side != K_SELL {
+        return Err(CancelReasonCode::InvalidSide);
+    }
+    if order.price <= 0 {
+        return Err(CancelReasonCode::InvalidPrice);
+    }
+    if order.qty == 0 {
+        return Err(CancelReasonCode::InvalidQty);
+    }
+    Ok(())
+}
+
+pub fn check_cancel_request(cancel_request : &CancelRequest) -> Result<(), CancelReasonCode> {
+    if !is_valid_id(&cancel_request.pbu_id) {
+        return Err(CancelReasonCode::InvalidPBUID);
+    }
+    if !is_valid_id(&cancel_request.cl_ord_id) || !is_valid_id(&cancel_request.orig_cl_ord_id) {
+        return Err(CancelReasonCode::InvalidClOrdID);
+    }
+    if !is_valid_id(&cancel_request.security_id) {
+        return Err(CancelReasonCode::InvalidSecurityID);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_order() -> NewOrder {
+        NewOrder {
+            order_id : 1,
+            seq_num : 0,
+            pbu_id : to_array("000100"),
+            cl_ord_id : to_array("123"),
+            security_id : to_array("SEC001"),
+            side : K_BUY,
+            price : 100,
+            qty : 100
+        }
+    }
+
+    #[test]
+    fn test_check_new_order() {
+        assert_eq!(check_new_order(&new_order()), Ok(()));
+
+        let mut order = new_order();
+        order.side = 'X';
+        assert_eq!(check_new_order(&order), Err(CancelReasonCode::InvalidSide));
+
+        let mut order = new_order();
+        order.price = 0;
+        assert_eq!(check_new_order(&order), Err(CancelReasonCode::InvalidPrice));
+
+        let mut order = new_order();
+        order.price = -10;
+        assert_eq!(check_new_order(&order), Err(CancelReasonCode::InvalidPrice));
+
+        let mut order = new_order();
+        order.qty = 0;
+        assert_eq!(check_new_order(&order), Err(CancelReasonCode::InvalidQty));
+
+        let mut order = new_order();
+        order.security_id = to_array("");
+        assert_eq!(check_new_order(&order), Err(CancelReasonCode::InvalidSecurityID));
+
+        let mut order = new_order();
+        order.security_id = to_array("SE C001");
+        assert_eq!(check_new_order(&order), Err(CancelReasonCode::InvalidSecurityID));
+
+        let mut order = new_order();
+        order.security_id[0] = 0;
+        assert_eq!(check_new_order(&order), Err(CancelReasonCode::InvalidSecurityID));
+
+        let mut order = new_order();
+        order.pbu_id = to_array("");
+        assert_eq!(check_new_order(&order), Err(CancelReasonCode::InvalidPBUID));
+
+        let mut order = new_order();
+        order.cl_ord_id = to_array("12-3");
+        assert_eq!(check_new_order(&order), Err(CancelReasonCode::InvalidClOrdID));
+    }
+
+    #[test]
+    fn test_check_cancel_request() {
+        let cancel_request = CancelRequest {
+            order_id : 2,
+            seq_num : 0,
+            pbu_id : to_array("000100"),
+            cl_ord_id : to_array("124"),
+            orig_cl_ord_id : to_array("123"),
+            security_id : to_array("SEC001")
+        };
+        assert_eq!(check_cancel_request(&cancel_request), Ok(()));
+
+        let mut tmp = CancelRequest { orig_cl_ord_id : to_array(""), ..cancel_request };
+        assert_eq!(check_cancel_request(&tmp), Err(CancelReasonCode::InvalidClOrdID));
+
+        tmp.orig_cl_ord_id = to_array("123");
+        tmp.security_id = to_array("");
+        assert_eq!(check_cancel_request(&tmp), Err(CancelReasonCode::InvalidSecurityID));
+    }
+}
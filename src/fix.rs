@@ -0,0 +1,499 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::types::*;
+use crate::reports::*;
+use crate::fix::message::FixError;
+
+pub mod message;
+pub mod session;
+pub mod acceptor;
+
+pub const SOH : u8 = 0x01;
+// 价格的小数位数上限, 10 的更高次幂超出 Price 的范围
+pub const MAX_PRICE_SCALE : u32 = 18;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixVersion {
+    Fix44,
+    // FIXT.1.1 会话层, ApplVerID(1128) = 7
+    Fix50,
+}
+
+impl FixVersion {
+    pub fn begin_string(&self) -> &'static str {
+        match self {
+            FixVersion::Fix44 => "FIX.4.4",
+            FixVersion::Fix50 => "FIXT.1.1",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FixConfig {
+    pub version : FixVersion,
+    pub sender_comp_id : String,
+    // 价格的小数位数, 引擎内价格为整数, 例如 2 表示 10050 输出为 100.50
+    pub price_scales : BTreeMap<SecurityID, u32>,
+    pub default_price_scale : u32,
+}
+
+impl FixConfig {
+    pub fn validate(&self) -> Result<(), FixError> {
+        if self.default_price_scale > MAX_PRICE_SCALE {
+            return Err(FixError::InvalidConfig(format!("default price scale {} exceeds {}", self.default_price_scale, MAX_PRICE_SCALE)));
+        }
+        for (security_id, scale) in self.price_scales.iter() {
+            if *scale > MAX_PRICE_SCALE {
+                return Err(FixError::InvalidConfig(format!("price scale {} of {} exceeds {}",
+                    scale, String::from_utf8_lossy(security_id).trim_end(), MAX_PRICE_SCALE)));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for FixConfig {
+    fn default() -> FixConfig {
+        FixConfig {
+            version : FixVersion::Fix44,
+            sender_comp_id : String::from("ENGINE"),
+            price_scales : BTreeMap::new(),
+            default_price_scale : 0,
+        }
+    }
+}
+
+// 消息体的写入器, 负责 tag=value 格式与价格的小数位
+pub struct FixBody<'a> {
+    buffer : &'a mut Vec<u8>,
+    price_scale : u32,
+}
+
+impl<'a> FixBody<'a> {
+    pub fn field<V : std::fmt::Display>(&mut self, tag : u32, value : V) {
+        let _ = write!(self.buffer, "{}={}", tag, value);
+        self.buffer.push(SOH);
+    }
+    // 定长的 ID 字段去掉右侧补齐的空格
+    pub fn id_field(&mut self, tag : u32, value : &[u8]) {
+        let _ = write!(self.buffer, "{}=", tag);
+        self.buffer.extend_from_slice(trim(value));
+        self.buffer.push(SOH);
+    }
+    pub fn price_field(&mut self, tag : u32, price : Price) {
+        let _ = write!(self.buffer, "{}=", tag);
+        write_price(self.buffer, price, self.price_scale);
+        self.buffer.push(SOH);
+    }
+}
+
+pub fn trim(value : &[u8]) -> &[u8] {
+    let len = value.iter().rposition(|c| *c != b' ').map_or(0, |i| i + 1);
+    &value[..len]
+}
+
+fn write_price(buffer : &mut Vec<u8>, price : Price, scale : u32) {
+    if scale == 0 {
+        let _ = write!(buffer, "{}", price);
+        return;
+    }
+    let unit = 10u64.pow(scale);
+    let sign = if price < 0 { "-" } else { "" };
+    let abs = price.unsigned_abs();
+    let _ = write!(buffer, "{}{}.{:0width$}", sign, abs / unit, abs % unit, width = scale as usize);
+}
+
+// write_price 的逆过程, 小数位超过 scale 时返回 None
+pub fn parse_price(value : &[u8], scale : u32) -> Option<Price> {
+    let (negative, value) = match value.strip_prefix(b"-") {
+        Some(value) => (true, value),
+        None => (false, value),
+    };
+    let (int, frac) = match value.iter().position(|c| *c == b'.') {
+        Some(pos) => (&value[..pos], &value[pos + 1..]),
+        None => (value, &b""[..]),
+    };
+    if int.is_empty() || frac.len() > scale as usize || !int.iter().chain(frac).all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let mut price : Price = 0;
+    for c in int.iter().chain(frac) {
+        price = price.checked_mul(10)?.checked_add((c - b'0') as Price)?;
+    }
+    price = price.checked_mul(10i64.checked_pow(scale - frac.len() as u32)?)?;
+    Some(if negative { -price } else { price })
+}
+
+fn fix_side(side : Side) -> char {
+    match side {
+        K_BUY => '1',
+        K_SELL => '2',
+        _ => side,
+    }
+}
+
+fn opposite_side(side : Side) -> Side {
+    if side == K_BUY { K_SELL } else { K_BUY }
+}
+
+pub trait FixReport {
+    const FIX_MSG_TYPE : &'static str;
+    // 接收方, 即报告所属的交易单元
+    fn target(&self) -> &PBUID;
+    fn security_id(&self) -> &SecurityID;
+    fn write_body(&self, body : &mut FixBody);
+}
+
+impl FixReport for ExecutionReport {
+    const FIX_MSG_TYPE : &'static str = "8";
+    fn target(&self) -> &PBUID {
+        &self.pbu_id
+    }
+    fn security_id(&self) -> &SecurityID {
+        &self.security_id
+    }
+    fn write_body(&self, body : &mut FixBody) {
+        body.field(37, self.order_id);
+        body.id_field(11, &self.cl_ord_id);
+        if !trim(&self.orig_cl_ord_id).is_empty() {
+            body.id_field(41, &self.orig_cl_ord_id);
+        }
+        body.field(17, self.exec_id);
+        body.field(150, self.exec_type);
+        body.field(39, self.ord_status);
+        if self.rejected_reason != CancelReasonCode::Passed {
+            body.field(103, self.rejected_reason as u32);
+            body.field(58, format_args!("{:?}", self.rejected_reason));
+        }
+        body.id_field(55, &self.security_id);
+        body.field(54, fix_side(self.side));
+        body.price_field(44, self.price);
+        body.field(38, self.qty);
+        body.field(14, self.cum_qty);
+        body.price_field(6, self.avg_px);
+        body.field(151, self.leaves_qty);
+        body.price_field(31, self.last_px);
+        body.field(32, self.last_qty);
+    }
+}
+
+impl FixReport for CancelReject {
+    const FIX_MSG_TYPE : &'static str = "9";
+    fn target(&self) -> &PBUID {
+        &self.pbu_id
+    }
+    fn security_id(&self) -> &SecurityID {
+        &self.security_id
+    }
+    fn write_body(&self, body : &mut FixBody) {
+        body.field(37, self.order_id);
+        body.id_field(11, &self.cl_ord_id);
+        body.id_field(41, &self.orig_cl_ord_id);
+        body.field(39, K_ORD_STATUS_REJECT);
+        // 1: 撤单请求
+        body.field(434, 1);
+        // 1: Unknown order, 99: Other
+        body.field(102, if self.rejected_reason == CancelReasonCode::OrderNotExisted { 1 } else { 99 });
+        body.field(58, format_args!("{:?}", self.rejected_reason));
+        body.id_field(55, &self.security_id);
+    }
+}
+
+impl FixReport for TradeCaptureReport {
+    const FIX_MSG_TYPE : &'static str = "AE";
+    fn target(&self) -> &PBUID {
+        &self.pbu_id
+    }
+    fn security_id(&self) -> &SecurityID {
+        &self.security_id
+    }
+    fn write_body(&self, body : &mut FixBody) {
+        body.field(571, self.exec_id);
+        body.field(487, 0);
+        body.field(570, 'N');
+        body.id_field(55, &self.security_id);
+        body.field(32, self.last_qty);
+        body.price_field(31, self.last_px);
+        body.field(17, self.exec_id);
+        body.field(552, 2);
+        let sides = [
+            (self.side, self.order_id, &self.cl_ord_id, &self.pbu_id),
+            (opposite_side(self.side), self.counterparty_order_id, &self.counterparty_cl_ord_id, &self.counterparty_pbu_id),
+        ];
+        for (side, order_id, cl_ord_id, pbu_id) in sides {
+            body.field(54, fix_side(side));
+            body.field(37, order_id);
+            body.id_field(11, cl_ord_id);
+            body.field(453, 1);
+            body.id_field(448, pbu_id);
+            body.field(447, 'D');
+            body.field(452, 1);
+        }
+    }
+}
+
+// 标准消息头中随消息变化的部分
+pub struct FixHeader<'a> {
+    pub msg_type : &'a str,
+    pub target : &'a [u8],
+    pub seq_num : u64,
+    pub sending_time : &'a [u8],
+    // 重发时为原消息的 SendingTime, 同时带上 PossDupFlag(43)=Y
+    pub orig_sending_time : Option<&'a [u8]>,
+}
+
+// 把报告编码为 FIX tag=value 消息, 每个接收方单独维护 MsgSeqNum
+pub struct FixEncoder {
+    config : FixConfig,
+    seq_nums : HashMap<PBUID, u64>,
+    body : Vec<u8>,
+}
+
+impl FixEncoder {
+    pub fn new(config : FixConfig) -> Result<FixEncoder, FixError> {
+        config.validate()?;
+        Ok(FixEncoder { config, seq_nums : HashMap::new(), body : Vec::with_capacity(512) })
+    }
+
+    pub fn config(&self) -> &FixConfig {
+        &self.config
+    }
+
+    pub fn price_scale(&self, security_id : &SecurityID) -> u32 {
+        self.config.price_scales.get(security_id).copied().unwrap_or(self.config.default_price_scale)
+    }
+
+    // 编码到 buffer 末尾
+    pub fn encode_into<T : FixReport>(&mut self, buffer : &mut Vec<u8>, report : &T) {
+        let seq_num = self.seq_nums.entry(*report.target()).or_insert(0);
+        *seq_num += 1;
+        let seq_num = *seq_num;
+
+        let mut body = std::mem::take(&mut self.body);
+        body.clear();
+        self.write_report_body(&mut body, report);
+        let sending_time = format_sending_time(SystemTime::now());
+        let header = FixHeader { msg_type : T::FIX_MSG_TYPE, target : report.target(), seq_num, sending_time : &sending_time, orig_sending_time : None };
+        self.write_message(buffer, &header, &body);
+        self.body = body;
+    }
+
+    // 报告的应用层字段, 不含标准消息头和消息尾
+    pub fn write_report_body<T : FixReport>(&self, body : &mut Vec<u8>, report : &T) {
+        let price_scale = self.price_scale(report.security_id());
+        report.write_body(&mut FixBody { buffer : body, price_scale });
+    }
+
+    // 在应用层字段前后加上标准消息头和消息尾, 写到 buffer 末尾
+    pub fn write_message(&self, buffer : &mut Vec<u8>, header : &FixHeader, body : &[u8]) {
+        let start = buffer.len();
+        let mut fields = FixBody { buffer, price_scale : 0 };
+        fields.field(35, header.msg_type);
+        if self.config.version == FixVersion::Fix50 {
+            fields.field(1128, 7);
+        }
+        fields.field(49, &self.config.sender_comp_id);
+        fields.id_field(56, header.target);
+        fields.field(34, header.seq_num);
+        if header.orig_sending_time.is_some() {
+            fields.field(43, 'Y');
+        }
+        fields.id_field(52, header.sending_time);
+        if let Some(orig_sending_time) = header.orig_sending_time {
+            fields.id_field(122, orig_sending_time);
+        }
+        buffer.extend_from_slice(body);
+
+        let mut prefix = Vec::with_capacity(32);
+        let _ = write!(prefix, "8={}\x019={}\x01", self.config.version.begin_string(), buffer.len() - start);
+        buffer.splice(start..start, prefix);
+        let _ = write!(buffer, "10={:03}", checksum(&buffer[start..]));
+        buffer.push(SOH);
+    }
+}
+
+pub fn checksum(message : &[u8]) -> u8 {
+    message.iter().fold(0u8, |sum, c| sum.wrapping_add(*c))
+}
+
+// UTC 时间, 格式为 YYYYMMDD-HH:MM:SS.sss
+pub fn format_sending_time(time : SystemTime) -> [u8;21] {
+    let mut result = [b'0';21];
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs_of_day = secs % 86400;
+    let _ = write!(&mut result[..], "{:04}{:02}{:02}-{:02}:{:02}:{:02}.{:03}", year, month, day,
+        secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60, since_epoch.subsec_millis());
+    result
+}
+
+// 1970-01-01 起的天数转换为公历日期
+pub(crate) fn civil_from_days(days : i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::messages::NewOrder;
+
+    fn parse(message : &[u8]) -> Vec<(String, String)> {
+        message.split(|c| *c == SOH).filter(|f| !f.is_empty()).map(|f| {
+            let f = String::from_utf8_lossy(f);
+            let (tag, value) = f.split_once('=').unwrap();
+            (tag.to_string(), value.to_string())
+        }).collect()
+    }
+
+    fn value<'a>(fields : &'a [(String, String)], tag : &str) -> &'a str {
+        &fields.iter().find(|(t, _)| t == tag).unwrap().1
+    }
+
+    // 校验 BodyLength 和 CheckSum
+    fn check_framing(message : &[u8]) {
+        let fields = parse(message);
+        let body_start = message.windows(2).position(|w| w == [SOH, b'3']).unwrap() + 1;
+        let trailer_start = message.len() - 7;
+        assert_eq!(&message[trailer_start..trailer_start + 3], b"10=");
+        assert_eq!(value(&fields, "9").parse::<usize>().unwrap(), trailer_start - body_start);
+        assert_eq!(value(&fields, "10").parse::<u8>().unwrap(), checksum(&message[..trailer_start]));
+    }
+
+    fn order() -> NewOrder {
+        NewOrder { order_id : 5, seq_num : 0, pbu_id : to_array("PBU001"), cl_ord_id : to_array("C1"),
+            security_id : to_array("SEC001"), side : K_SELL, price : 10050, qty : 300 }
+    }
+
+    #[test]
+    fn execution_report() {
+        let mut config = FixConfig::default();
+        config.price_scales.insert(to_array("SEC001"), 2);
+        let mut encoder = FixEncoder::new(config).unwrap();
+
+        let mut report = ExecutionReport::new(&order());
+        report.exec_type = K_EXEC_TYPE_TRADE;
+        report.ord_status = K_ORD_STATUS_PARTIALLY_FILLED;
+        report.cum_qty = 100;
+        report.leaves_qty = 200;
+        report.last_px = 10005;
+        report.last_qty = 100;
+        report.avg_px = 10005;
+        report.exec_id = 9;
+
+        let mut buffer = Vec::new();
+        encoder.encode_into(&mut buffer, &report);
+        check_framing(&buffer);
+        let fields = parse(&buffer);
+        assert_eq!(fields[0], ("8".to_string(), "FIX.4.4".to_string()));
+        for (tag, expected) in [("35", "8"), ("56", "PBU001"), ("34", "1"), ("37", "5"), ("11", "C1"), ("17", "9"),
+            ("150", "F"), ("39", "1"), ("55", "SEC001"), ("54", "2"), ("44", "100.50"), ("38", "300"),
+            ("14", "100"), ("6", "100.05"), ("151", "200"), ("31", "100.05"), ("32", "100")] {
+            assert_eq!(value(&fields, tag), expected, "tag {}", tag);
+        }
+        assert!(fields.iter().all(|(tag, _)| tag != "41" && tag != "103"));
+
+        // 同一接收方的 MsgSeqNum 递增
+        buffer.clear();
+        encoder.encode_into(&mut buffer, &report);
+        assert_eq!(value(&parse(&buffer), "34"), "2");
+
+        // 没有成交时 AvgPx 为 0
+        buffer.clear();
+        encoder.encode_into(&mut buffer, &ExecutionReport::new(&order()));
+        assert_eq!(value(&parse(&buffer), "6"), "0.00");
+    }
+
+    #[test]
+    fn cancel_reject_and_trade_capture() {
+        let mut encoder = FixEncoder::new(FixConfig { version : FixVersion::Fix50, ..FixConfig::default() }).unwrap();
+        let reject = CancelReject {
+            order_id : 6,
+            seq_num : 0,
+            pbu_id : to_array("PBU002"),
+            cl_ord_id : to_array("C2"),
+            orig_cl_ord_id : to_array("C1"),
+            security_id : to_array("SEC001"),
+            rejected_reason : CancelReasonCode::OrderNotExisted,
+        };
+        let mut buffer = Vec::new();
+        encoder.encode_into(&mut buffer, &reject);
+        check_framing(&buffer);
+        let fields = parse(&buffer);
+        for (tag, expected) in [("8", "FIXT.1.1"), ("35", "9"), ("1128", "7"), ("41", "C1"), ("434", "1"), ("102", "1")] {
+            assert_eq!(value(&fields, tag), expected, "tag {}", tag);
+        }
+
+        let tcr = TradeCaptureReport {
+            security_id : to_array("SEC001"),
+            order_id : 5,
+            seq_num : 0,
+            pbu_id : to_array("PBU001"),
+            cl_ord_id : to_array("C1"),
+            side : K_SELL,
+            exec_id : 3,
+            counterparty_order_id : 4,
+            counterparty_pbu_id : to_array("PBU002"),
+            counterparty_cl_ord_id : to_array("C0"),
+            counterparty_exec_id : 3,
+            last_px : 100,
+            last_qty : 10,
+        };
+        buffer.clear();
+        encoder.encode_into(&mut buffer, &tcr);
+        check_framing(&buffer);
+        let fields = parse(&buffer);
+        assert_eq!(value(&fields, "35"), "AE");
+        let sides : Vec<&str> = fields.iter().filter(|(tag, _)| tag == "54").map(|(_, v)| v.as_str()).collect();
+        assert_eq!(sides, vec!["2", "1"]);
+        let parties : Vec<&str> = fields.iter().filter(|(tag, _)| tag == "448").map(|(_, v)| v.as_str()).collect();
+        assert_eq!(parties, vec!["PBU001", "PBU002"]);
+    }
+
+    #[test]
+    fn price_scale_limit() {
+        let mut config = FixConfig::default();
+        config.price_scales.insert(to_array("SEC001"), 20);
+        assert!(matches!(FixEncoder::new(config.clone()), Err(FixError::InvalidConfig(_))));
+        assert_eq!(acceptor::FixAcceptor::bind("127.0.0.1:0", config.clone()).err().map(|e| e.kind()), Some(std::io::ErrorKind::InvalidInput));
+        config.price_scales.insert(to_array("SEC001"), MAX_PRICE_SCALE);
+        assert!(FixEncoder::new(config.clone()).is_ok());
+        config.default_price_scale = MAX_PRICE_SCALE + 1;
+        assert!(matches!(FixEncoder::new(config), Err(FixError::InvalidConfig(_))));
+
+        let mut buffer = Vec::new();
+        write_price(&mut buffer, Price::MAX, MAX_PRICE_SCALE);
+        assert_eq!(parse_price(&buffer, MAX_PRICE_SCALE), Some(Price::MAX));
+    }
+
+    #[test]
+    fn price_and_time_format() {
+        let mut buffer = Vec::new();
+        write_price(&mut buffer, -5, 2);
+        buffer.push(b' ');
+        write_price(&mut buffer, 123456, 3);
+        buffer.push(b' ');
+        write_price(&mut buffer, 42, 0);
+        assert_eq!(buffer, b"-0.05 123.456 42");
+        assert_eq!(parse_price(b"-0.05", 2), Some(-5));
+        assert_eq!(parse_price(b"123.4", 3), Some(123400));
+        assert_eq!(parse_price(b"42", 2), Some(4200));
+        assert_eq!(parse_price(b"1.005", 2), None);
+        assert_eq!(parse_price(b".5", 2), None);
+        assert_eq!(parse_price(b"1e3", 0), None);
+
+        assert_eq!(&format_sending_time(UNIX_EPOCH + Duration::from_millis(1709210096789)), b"20240229-12:34:56.789");
+    }
+}
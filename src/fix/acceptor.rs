@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::io::{self, BufWriter, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::types::*;
+use crate::engin::{Engin, EngineError};
+use crate::report_sink::ReportSink;
+use crate::listener::{Listener, Submitter};
+use crate::reports::{decode, OutputMessage};
+use crate::fix::{FixConfig, FixEncoder, FixReport};
+use crate::fix::message::{FixMessage, FixReader};
+use crate::fix::session::{Session, K_RESEND_WINDOW};
+
+// 读超时, 也是检查心跳的间隔
+const POLL_INTERVAL : Duration = Duration::from_millis(200);
+// 券商长时间不读消息时, 连接的写线程写超时后断开该连接
+const WRITE_TIMEOUT : Duration = Duration::from_secs(1);
+// 每个连接待发的消息数上限, 一次重发整个窗口也放得下; 队列满时断开该连接, exe 阶段从不等待券商
+const WRITE_QUEUE_CAPACITY : usize = K_RESEND_WINDOW + (1 << 12);
+
+struct SessionTable {
+    encoder : FixEncoder,
+    sessions : HashMap<PBUID, Session>,
+}
+
+fn lock(table : &Mutex<SessionTable>) -> MutexGuard<'_, SessionTable> {
+    table.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// 在本地 TCP 端口上接受券商的 FIX 连接, 把委托交给 Engin, 报告经 FixRoutingSink 按交易单元送回
+pub struct FixAcceptor {
+    listener : Listener,
+    table : Arc<Mutex<SessionTable>>,
+}
+
+impl FixAcceptor {
+    // config 不合法时返回 InvalidInput
+    pub fn bind<A : ToSocketAddrs>(addr : A, config : FixConfig) -> io::Result<FixAcceptor> {
+        let encoder = FixEncoder::new(config).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        Ok(FixAcceptor {
+            listener : Listener::bind(addr)?,
+            table : Arc::new(Mutex::new(SessionTable { encoder, sessions : HashMap::new() })),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // 作为 Engin 的 ReportSink, 要求 Engin 输出 bincode 格式的报告
+    pub fn sink(&self) -> FixRoutingSink {
+        FixRoutingSink { table : self.table.clone(), body : Vec::with_capacity(512) }
+    }
+
+    // 接受连接并把委托依次交给 engin, 直到 stop 被调用或 engin 出错
+    pub fn run<S : ReportSink + 'static>(&self, engin : &Engin<S>) -> Result<(), EngineError> {
+        self.listener.run(&engin.ingress(), |stream, submitter| {
+            let _ = self.serve_session(stream, submitter);
+        })
+    }
+
+    // 可在其它线程调用, 断开所有连接, run 随后返回
+    pub fn stop(&self) {
+        self.listener.stop();
+    }
+
+    // 会话写出的消息经队列交给该连接的写线程, 持有会话表的锁时不会阻塞在 socket 上
+    fn serve_session(&self, stream : &TcpStream, submitter : &Submitter) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let (tx, rx) = mpsc::sync_channel(WRITE_QUEUE_CAPACITY);
+        let writer = stream.try_clone()?;
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                let mut writer = BufWriter::new(writer);
+                if write_queued(&mut writer, rx).is_err() {
+                    let _ = writer.get_ref().shutdown(Shutdown::Both);
+                }
+            });
+            self.read_messages(stream, submitter, tx)
+        })
+    }
+
+    // 会话断开后队列随之关闭, 写线程写完剩余的消息后退出
+    fn read_messages(&self, stream : &TcpStream, submitter : &Submitter, tx : SyncSender<Vec<u8>>) -> io::Result<()> {
+        let mut reader = FixReader::new(stream.try_clone()?);
+        let mut pbu_id : Option<PBUID> = None;
+        let mut tx = Some(tx);
+
+        let result = loop {
+            let bytes = match reader.read_message() {
+                Ok(Some(bytes)) => bytes,
+                Ok(None) => break Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+                    if let Some(pbu_id) = pbu_id {
+                        let mut table = lock(&self.table);
+                        let table = &mut *table;
+                        let session = table.sessions.get_mut(&pbu_id).unwrap();
+                        session.on_timer(&table.encoder, Instant::now());
+                        if !session.is_connected() {
+                            break Ok(());
+                        }
+                    }
+                    continue;
+                },
+                Err(e) => break Err(e),
+            };
+            // 格式错误的消息直接丢弃
+            let message = match FixMessage::parse(&bytes) {
+                Ok(message) => message,
+                Err(_) => continue,
+            };
+
+            let (inbound, connected) = {
+                let mut table = lock(&self.table);
+                let table = &mut *table;
+                let id = match pbu_id {
+                    Some(id) => id,
+                    None => match self.attach(table, &message, stream, &mut tx)? {
+                        Some(id) => *pbu_id.insert(id),
+                        None => break Ok(()),
+                    },
+                };
+                let session = table.sessions.get_mut(&id).unwrap();
+                let inbound = session.on_message(&table.encoder, &message, Instant::now());
+                (inbound, session.is_connected())
+            };
+
+            if let Some(inbound) = inbound {
+                if !submitter.submit(inbound) {
+                    break Ok(());
+                }
+            }
+            if !connected {
+                break Ok(());
+            }
+        };
+
+        if let Some(pbu_id) = pbu_id {
+            if let Some(session) = lock(&self.table).sessions.get_mut(&pbu_id) {
+                session.disconnect();
+            }
+        }
+        result
+    }
+
+    // 第一条消息必须是 Logon, 由 SenderCompID 确定交易单元; 同一交易单元只允许一个连接
+    fn attach(&self, table : &mut SessionTable, message : &FixMessage, stream : &TcpStream, tx : &mut Option<SyncSender<Vec<u8>>>) -> io::Result<Option<PBUID>> {
+        let sender = match message.get(49).and_then(|sender| std::str::from_utf8(sender).ok()) {
+            Some(sender) if message.msg_type() == b"A" && !sender.is_empty() && sender.len() <= 6 => sender,
+            _ => return Ok(None),
+        };
+        let pbu_id : PBUID = to_array(sender);
+        let session = table.sessions.entry(pbu_id).or_insert_with(|| Session::new(pbu_id));
+        if session.is_connected() {
+            return Ok(None);
+        }
+        let writer = QueueWriter { tx : tx.take().unwrap(), stream : stream.try_clone()?, message : Vec::new() };
+        session.connect(Box::new(writer), Instant::now());
+        Ok(Some(pbu_id))
+    }
+}
+
+// 会话的 writer: 每次 flush 把写入的一条消息放入连接的发送队列, 不阻塞; 队列满或写线程已退出时断开连接
+struct QueueWriter {
+    tx : SyncSender<Vec<u8>>,
+    stream : TcpStream,
+    message : Vec<u8>,
+}
+
+impl Write for QueueWriter {
+    fn write(&mut self, buf : &[u8]) -> io::Result<usize> {
+        self.message.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.message.is_empty() {
+            return Ok(());
+        }
+        if self.tx.try_send(std::mem::take(&mut self.message)).is_err() {
+            let _ = self.stream.shutdown(Shutdown::Both);
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "write queue full"));
+        }
+        Ok(())
+    }
+}
+
+// 依次写出队列中的消息, 队列暂时为空时才 flush; 写失败或超时后写线程断开连接, 读线程随之结束会话
+fn write_queued<W : Write>(writer : &mut W, rx : Receiver<Vec<u8>>) -> io::Result<()> {
+    loop {
+        let message = match rx.try_recv() {
+            Ok(message) => message,
+            Err(TryRecvError::Empty) => {
+                writer.flush()?;
+                match rx.recv() {
+                    Ok(message) => message,
+                    Err(_) => return Ok(()),
+                }
+            },
+            Err(TryRecvError::Disconnected) => return writer.flush(),
+        };
+        writer.write_all(&message)?;
+    }
+}
+
+// 把 exe 阶段输出的报告转为 FIX 消息, 放入报告所属交易单元连接的发送队列, 不等待券商读取
+pub struct FixRoutingSink {
+    table : Arc<Mutex<SessionTable>>,
+    body : Vec<u8>,
+}
+
+impl FixRoutingSink {
+    fn route<T : FixReport>(&mut self, report : &T) {
+        let mut table = lock(&self.table);
+        let table = &mut *table;
+        self.body.clear();
+        table.encoder.write_report_body(&mut self.body, report);
+        let pbu_id = *report.target();
+        let session = table.sessions.entry(pbu_id).or_insert_with(|| Session::new(pbu_id));
+        session.send_app(&table.encoder, T::FIX_MSG_TYPE, &self.body);
+    }
+}
+
+impl ReportSink for FixRoutingSink {
+    fn send(&mut self, report : &[u8]) -> io::Result<()> {
+        match decode(report).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))? {
+            OutputMessage::ExecutionReport(report) => self.route(&report),
+            OutputMessage::TradeCaptureReport(report) => self.route(&report),
+            OutputMessage::CancelReject(report) => self.route(&report),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn slow_client_disconnected() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        // 队列只有两个位置, 且没有写线程取走
+        let (tx, rx) = mpsc::sync_channel(2);
+        let mut writer = QueueWriter { tx, stream, message : Vec::new() };
+
+        for message in [&b"8=FIX.4.4\x01"[..], b"8=FIX.4.4\x01"] {
+            writer.write_all(message).unwrap();
+            writer.flush().unwrap();
+        }
+        writer.write_all(b"8=FIX.4.4\x01").unwrap();
+        assert!(writer.flush().is_err());
+        assert_eq!(rx.try_iter().count(), 2);
+        let mut buf = [0u8;1];
+        assert_eq!((&client).read(&mut buf).unwrap(), 0);
+    }
+}
@@ -0,0 +1,218 @@
+use std::fmt;
+use std::io::{self, Read};
+
+use crate::fix::{SOH, checksum};
+
+// BodyLength 的上限, 超过即认为数据流错乱
+const MAX_BODY_LEN : usize = 4096;
+// 8=BeginString<SOH>9=BodyLength<SOH> 两个字段各自的最大长度
+const MAX_FIELD_LEN : usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FixError {
+    // 消息格式错误, BodyLength 或 CheckSum 不符
+    Garbled(String),
+    MissingField(u32),
+    InvalidField(u32),
+    InvalidConfig(String),
+}
+
+impl fmt::Display for FixError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FixError::Garbled(msg) => write!(f, "garbled message: {}", msg),
+            FixError::MissingField(tag) => write!(f, "required tag {} missing", tag),
+            FixError::InvalidField(tag) => write!(f, "value is incorrect for tag {}", tag),
+            FixError::InvalidConfig(msg) => write!(f, "invalid FIX config: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for FixError {}
+
+// 解析后的 tag=value 消息, 按原顺序保存字段
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixMessage {
+    fields : Vec<(u32, Vec<u8>)>,
+}
+
+impl FixMessage {
+    // 校验 BeginString, BodyLength 和 CheckSum
+    pub fn parse(bytes : &[u8]) -> Result<FixMessage, FixError> {
+        let mut fields = Vec::new();
+        for field in bytes.split(|c| *c == SOH) {
+            if field.is_empty() {
+                continue;
+            }
+            let eq = field.iter().position(|c| *c == b'=').ok_or_else(|| FixError::Garbled(String::from("field without '='")))?;
+            let tag = std::str::from_utf8(&field[..eq]).ok().and_then(|tag| tag.parse().ok())
+                .ok_or_else(|| FixError::Garbled(String::from("invalid tag")))?;
+            fields.push((tag, field[eq + 1..].to_vec()));
+        }
+        if fields.len() < 4 || fields[0].0 != 8 || fields[1].0 != 9 || fields[2].0 != 35 || fields[fields.len() - 1].0 != 10 {
+            return Err(FixError::Garbled(String::from("message must start with 8, 9, 35 and end with 10")));
+        }
+
+        let body_start = fields[0].1.len() + fields[1].1.len() + 6;
+        let trailer_start = bytes.len() - fields[fields.len() - 1].1.len() - 4;
+        let message = FixMessage { fields };
+        if message.get_u64(9) != Some((trailer_start - body_start) as u64) {
+            return Err(FixError::Garbled(String::from("BodyLength mismatch")));
+        }
+        if message.get_u64(10) != Some(checksum(&bytes[..trailer_start]) as u64) {
+            return Err(FixError::Garbled(String::from("CheckSum mismatch")));
+        }
+        Ok(message)
+    }
+
+    pub fn msg_type(&self) -> &[u8] {
+        &self.fields[2].1
+    }
+
+    // 第一个 tag 对应的值
+    pub fn get(&self, tag : u32) -> Option<&[u8]> {
+        self.fields.iter().find(|(t, _)| *t == tag).map(|(_, value)| value.as_slice())
+    }
+
+    pub fn get_u64(&self, tag : u32) -> Option<u64> {
+        self.get(tag).and_then(|value| std::str::from_utf8(value).ok()).and_then(|value| value.parse().ok())
+    }
+
+    pub fn get_flag(&self, tag : u32) -> bool {
+        self.get(tag) == Some(b"Y")
+    }
+
+    pub fn require(&self, tag : u32) -> Result<&[u8], FixError> {
+        self.get(tag).ok_or(FixError::MissingField(tag))
+    }
+
+    pub fn require_u64(&self, tag : u32) -> Result<u64, FixError> {
+        let value = self.require(tag)?;
+        std::str::from_utf8(value).ok().and_then(|value| value.parse().ok()).ok_or(FixError::InvalidField(tag))
+    }
+
+    pub fn seq_num(&self) -> Result<u64, FixError> {
+        self.require_u64(34)
+    }
+}
+
+// 从字节流中切分出完整的 FIX 消息, 读超时后已读到的部分保留到下次
+pub struct FixReader<R> {
+    reader : R,
+    buffer : Vec<u8>,
+}
+
+impl<R : Read> FixReader<R> {
+    pub fn new(reader : R) -> FixReader<R> {
+        FixReader { reader, buffer : Vec::with_capacity(4096) }
+    }
+
+    // 对端关闭连接时返回 None
+    pub fn read_message(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(len) = self.frame_len()? {
+                return Ok(Some(self.buffer.drain(..len).collect()));
+            }
+            let mut chunk = [0u8;4096];
+            let n = self.reader.read(&mut chunk)?;
+            if n == 0 {
+                return Ok(None);
+            }
+            self.buffer.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    // 缓冲区中第一条完整消息的长度
+    fn frame_len(&self) -> io::Result<Option<usize>> {
+        let garbled = || io::Error::new(io::ErrorKind::InvalidData, "garbled FIX stream");
+        if self.buffer.len() >= 2 && !self.buffer.starts_with(b"8=") {
+            return Err(garbled());
+        }
+        let begin_end = match field_end(&self.buffer).map_err(|_| garbled())? {
+            Some(pos) => pos,
+            None => return Ok(None),
+        };
+        let rest = &self.buffer[begin_end + 1..];
+        let len_end = match field_end(rest).map_err(|_| garbled())? {
+            Some(pos) => pos,
+            None => return Ok(None),
+        };
+        let body_len = rest[..len_end].strip_prefix(b"9=").and_then(|len| std::str::from_utf8(len).ok())
+            .and_then(|len| len.parse::<usize>().ok()).filter(|len| *len <= MAX_BODY_LEN).ok_or_else(garbled)?;
+        // 10=xxx<SOH> 共 7 个字节
+        let total = (begin_end + 1 + len_end + 1).checked_add(body_len + 7).ok_or_else(garbled)?;
+        Ok(if self.buffer.len() >= total { Some(total) } else { None })
+    }
+}
+
+// 字段结尾 SOH 的位置; 超过 MAX_FIELD_LEN 仍没有 SOH 时返回 Err
+fn field_end(bytes : &[u8]) -> Result<Option<usize>, ()> {
+    match bytes.iter().take(MAX_FIELD_LEN).position(|c| *c == SOH) {
+        Some(pos) => Ok(Some(pos)),
+        None if bytes.len() >= MAX_FIELD_LEN => Err(()),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fix::*;
+
+    fn logon() -> Vec<u8> {
+        let encoder = FixEncoder::new(FixConfig { sender_comp_id : String::from("PBU001"), ..FixConfig::default() }).unwrap();
+        let sending_time = format_sending_time(std::time::SystemTime::now());
+        let header = FixHeader { msg_type : "A", target : b"ENGINE", seq_num : 1, sending_time : &sending_time, orig_sending_time : None };
+        let mut buffer = Vec::new();
+        encoder.write_message(&mut buffer, &header, b"98=0\x01108=30\x01");
+        buffer
+    }
+
+    #[test]
+    fn parse_message() {
+        let bytes = logon();
+        let message = FixMessage::parse(&bytes).unwrap();
+        assert_eq!(message.msg_type(), b"A");
+        assert_eq!(message.get(49), Some(&b"PBU001"[..]));
+        assert_eq!(message.get_u64(108), Some(30));
+        assert_eq!(message.seq_num(), Ok(1));
+        assert_eq!(message.require(11), Err(FixError::MissingField(11)));
+
+        let mut bytes = bytes;
+        let pos = bytes.len() - 10;
+        bytes[pos] = b'9';
+        assert!(matches!(FixMessage::parse(&bytes), Err(FixError::Garbled(_))));
+    }
+
+    #[test]
+    fn split_stream() {
+        let first = logon();
+        let second = logon();
+        let stream = [first.clone(), second.clone()].concat();
+        // 每次只读到 7 个字节也能切分出完整消息
+        struct Slow<'a>(&'a [u8]);
+        impl Read for Slow<'_> {
+            fn read(&mut self, buf : &mut [u8]) -> io::Result<usize> {
+                let n = self.0.len().min(buf.len()).min(7);
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+        let mut reader = FixReader::new(Slow(&stream));
+        assert_eq!(reader.read_message().unwrap(), Some(first));
+        assert_eq!(reader.read_message().unwrap(), Some(second));
+        assert_eq!(reader.read_message().unwrap(), None);
+    }
+
+    #[test]
+    fn body_length_limit() {
+        let read = |bytes : Vec<u8>| FixReader::new(bytes.as_slice()).read_message().map_err(|e| e.kind());
+        assert_eq!(read(b"8=FIX.4.4\x019=18446744073709551615\x0135=A\x01".to_vec()), Err(io::ErrorKind::InvalidData));
+        assert_eq!(read(format!("8=FIX.4.4\x019={}\x0135=A\x01", MAX_BODY_LEN + 1).into_bytes()), Err(io::ErrorKind::InvalidData));
+        // BeginString 或 BodyLength 一直没有结束
+        assert_eq!(read([&b"8="[..], &[b'F'; 64]].concat()), Err(io::ErrorKind::InvalidData));
+        assert_eq!(read([&b"8=FIX.4.4\x019="[..], &[b'1'; 64]].concat()), Err(io::ErrorKind::InvalidData));
+        assert_eq!(read(b"8=FIX.4.4\x019=10".to_vec()), Ok(None));
+    }
+}
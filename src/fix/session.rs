@@ -0,0 +1,618 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::types::*;
+use crate::messages::{NewOrder, CancelRequest, Inbound};
+use crate::fix::*;
+use crate::fix::message::{FixMessage, FixError};
+
+// 保留最近发出的消息数, 更早的消息不再重发, 以 GapFill 代替
+pub const K_RESEND_WINDOW : usize = 100_000;
+
+// 已发出的应用层消息, 用于响应 ResendRequest
+struct SentMessage {
+    msg_type : &'static str,
+    sending_time : [u8;21],
+    body : Vec<u8>,
+}
+
+// 一个交易单元的 FIX 会话, 序号在断线重连之间保持
+pub struct Session {
+    pbu_id : PBUID,
+    next_in : u64,
+    next_out : u64,
+    // 最近发出的消息, 下标为 MsgSeqNum - first_sent, 会话层消息为 None, 重发时以 GapFill 代替
+    sent : VecDeque<Option<SentMessage>>,
+    first_sent : u64,
+    resend_window : usize,
+    writer : Option<Box<dyn Write + Send>>,
+    logged_on : bool,
+    // 已发出 ResendRequest, 等待对端补齐到触发请求的序号为止
+    resend_pending : Option<u64>,
+    heart_bt_int : Duration,
+    last_sent : Instant,
+    last_received : Instant,
+    test_request_sent : bool,
+    buffer : Vec<u8>,
+}
+
+impl Session {
+    pub fn new(pbu_id : PBUID) -> Session {
+        let now = Instant::now();
+        Session {
+            pbu_id,
+            next_in : 1,
+            next_out : 1,
+            sent : VecDeque::new(),
+            first_sent : 1,
+            resend_window : K_RESEND_WINDOW,
+            writer : None,
+            logged_on : false,
+            resend_pending : None,
+            heart_bt_int : Duration::from_secs(30),
+            last_sent : now,
+            last_received : now,
+            test_request_sent : false,
+            buffer : Vec::with_capacity(512),
+        }
+    }
+
+    pub fn pbu_id(&self) -> &PBUID {
+        &self.pbu_id
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    pub fn is_logged_on(&self) -> bool {
+        self.logged_on
+    }
+
+    pub fn next_in(&self) -> u64 {
+        self.next_in
+    }
+
+    pub fn next_out(&self) -> u64 {
+        self.next_out
+    }
+
+    // 新连接, 等待 Logon
+    pub fn connect(&mut self, writer : Box<dyn Write + Send>, now : Instant) {
+        self.writer = Some(writer);
+        self.logged_on = false;
+        self.resend_pending = None;
+        self.test_request_sent = false;
+        self.last_received = now;
+    }
+
+    pub fn disconnect(&mut self) {
+        self.writer = None;
+        self.logged_on = false;
+    }
+
+    // 发送报告, 未登录时只保存, 等对端 ResendRequest
+    pub fn send_app(&mut self, encoder : &FixEncoder, msg_type : &'static str, body : &[u8]) {
+        let sending_time = format_sending_time(SystemTime::now());
+        let seq_num = self.next_out;
+        self.next_out += 1;
+        self.push_sent(Some(SentMessage { msg_type, sending_time, body : body.to_vec() }));
+        if self.logged_on {
+            self.write(encoder, msg_type, seq_num, &sending_time, None, body);
+        }
+    }
+
+    // 处理对端发来的一条消息, 返回其中的委托
+    pub fn on_message(&mut self, encoder : &FixEncoder, message : &FixMessage, now : Instant) -> Option<Inbound> {
+        self.last_received = now;
+        self.test_request_sent = false;
+
+        let config = encoder.config();
+        if message.get(8) != Some(config.version.begin_string().as_bytes())
+            || message.get(56) != Some(config.sender_comp_id.as_bytes())
+            || message.get(49).map(trim) != Some(trim(&self.pbu_id)) {
+            self.logout(encoder, "invalid BeginString, SenderCompID or TargetCompID");
+            return None;
+        }
+        let seq_num = match message.seq_num() {
+            Ok(seq_num) => seq_num,
+            Err(_) => {
+                self.logout(encoder, "MsgSeqNum missing");
+                return None;
+            }
+        };
+
+        if !self.logged_on {
+            if message.msg_type() != b"A" {
+                self.logout(encoder, "first message is not Logon");
+                return None;
+            }
+            self.on_logon(encoder, message, seq_num);
+            return None;
+        }
+
+        // 非 GapFill 的 SequenceReset 不检查序号
+        if message.msg_type() == b"4" && !message.get_flag(123) {
+            self.on_sequence_reset(encoder, message, seq_num);
+            return None;
+        }
+        if seq_num > self.next_in {
+            if message.msg_type() == b"2" {
+                self.on_resend_request(encoder, message, seq_num);
+            }
+            if self.resend_pending.is_none() {
+                self.resend_pending = Some(seq_num);
+                let body = format!("7={}\x0116=0\x01", self.next_in);
+                self.send_admin(encoder, "2", body.as_bytes());
+            }
+            return None;
+        }
+        if seq_num < self.next_in {
+            if !message.get_flag(43) {
+                let text = format!("MsgSeqNum too low, expecting {} but received {}", self.next_in, seq_num);
+                self.logout(encoder, &text);
+            }
+            return None;
+        }
+        self.next_in += 1;
+        self.check_resend_done();
+
+        match message.msg_type() {
+            b"0" => None,
+            b"1" => {
+                let body = [&b"112="[..], message.get(112).unwrap_or_default(), &[SOH]].concat();
+                self.send_admin(encoder, "0", &body);
+                None
+            },
+            b"2" => {
+                self.on_resend_request(encoder, message, seq_num);
+                None
+            },
+            b"4" => {
+                self.on_sequence_reset(encoder, message, seq_num);
+                None
+            },
+            b"5" => {
+                self.send_admin(encoder, "5", b"");
+                self.disconnect();
+                None
+            },
+            b"D" => self.parse_or_reject(encoder, message, seq_num, |session, message| session.new_order(encoder, message)),
+            b"F" => self.parse_or_reject(encoder, message, seq_num, |session, message| session.cancel_request(message)),
+            _ => {
+                self.reject(encoder, message, seq_num, 11, "unsupported MsgType");
+                None
+            }
+        }
+    }
+
+    // 读超时时调用, 负责 Heartbeat 和 TestRequest
+    pub fn on_timer(&mut self, encoder : &FixEncoder, now : Instant) {
+        if !self.logged_on {
+            return;
+        }
+        let idle = now.duration_since(self.last_received);
+        if self.test_request_sent && idle >= self.heart_bt_int * 2 {
+            self.disconnect();
+            return;
+        }
+        if !self.test_request_sent && idle >= self.heart_bt_int + self.heart_bt_int / 5 {
+            self.test_request_sent = true;
+            self.send_admin(encoder, "1", b"112=TEST\x01");
+        }
+        else if now.duration_since(self.last_sent) >= self.heart_bt_int {
+            self.send_admin(encoder, "0", b"");
+        }
+    }
+
+    fn on_logon(&mut self, encoder : &FixEncoder, message : &FixMessage, seq_num : u64) {
+        let heart_bt_int = match message.require_u64(108) {
+            Ok(heart_bt_int) if heart_bt_int > 0 => heart_bt_int,
+            _ => {
+                self.logout(encoder, "invalid HeartBtInt");
+                return;
+            }
+        };
+        let reset = message.get_flag(141);
+        if reset {
+            self.next_in = 1;
+            self.next_out = 1;
+            self.sent.clear();
+            self.first_sent = 1;
+        }
+        if seq_num < self.next_in {
+            let text = format!("MsgSeqNum too low, expecting {} but received {}", self.next_in, seq_num);
+            self.logout(encoder, &text);
+            return;
+        }
+
+        self.heart_bt_int = Duration::from_secs(heart_bt_int);
+        self.logged_on = true;
+        let body = format!("98=0\x01108={}\x01{}", heart_bt_int, if reset { "141=Y\x01" } else { "" });
+        self.send_admin(encoder, "A", body.as_bytes());
+
+        if seq_num > self.next_in {
+            self.resend_pending = Some(seq_num);
+            let body = format!("7={}\x0116=0\x01", self.next_in);
+            self.send_admin(encoder, "2", body.as_bytes());
+        }
+        else {
+            self.next_in += 1;
+        }
+    }
+
+    fn on_sequence_reset(&mut self, encoder : &FixEncoder, message : &FixMessage, seq_num : u64) {
+        match message.require_u64(36) {
+            Ok(new_seq_no) if new_seq_no >= self.next_in => {
+                self.next_in = new_seq_no;
+                self.check_resend_done();
+            },
+            _ => self.reject(encoder, message, seq_num, 5, "NewSeqNo must not decrease"),
+        }
+    }
+
+    // 对端可能以 GapFill 或重发的应用层消息补齐缺口, 越过触发 ResendRequest 的序号后才允许再次请求
+    fn check_resend_done(&mut self) {
+        if matches!(self.resend_pending, Some(end) if self.next_in > end) {
+            self.resend_pending = None;
+        }
+    }
+
+    // 应用层消息原样重发, 连续的会话层消息合并为一个 SequenceReset-GapFill
+    fn on_resend_request(&mut self, encoder : &FixEncoder, message : &FixMessage, seq_num : u64) {
+        let (begin, end) = match (message.require_u64(7), message.require_u64(16)) {
+            (Ok(begin), Ok(end)) if begin >= 1 => (begin, end),
+            _ => {
+                self.reject(encoder, message, seq_num, 5, "invalid BeginSeqNo or EndSeqNo");
+                return;
+            }
+        };
+        let last = self.next_out - 1;
+        let end = if end == 0 || end > last { last } else { end };
+
+        let mut gap_start = None;
+        for seq in begin..=end {
+            // 已移出窗口的消息也以 GapFill 代替
+            let index = seq.checked_sub(self.first_sent).map(|index| index as usize);
+            let sent = index.and_then(|index| self.sent.get_mut(index)).and_then(|sent| sent.take());
+            match sent {
+                None => {
+                    gap_start.get_or_insert(seq);
+                },
+                Some(sent) => {
+                    if let Some(start) = gap_start.take() {
+                        self.gap_fill(encoder, start, seq);
+                    }
+                    let sending_time = format_sending_time(SystemTime::now());
+                    self.write(encoder, sent.msg_type, seq, &sending_time, Some(&sent.sending_time), &sent.body);
+                    self.sent[(seq - self.first_sent) as usize] = Some(sent);
+                }
+            }
+        }
+        if let Some(start) = gap_start {
+            self.gap_fill(encoder, start, end + 1);
+        }
+    }
+
+    fn gap_fill(&mut self, encoder : &FixEncoder, seq_num : u64, new_seq_no : u64) {
+        let sending_time = format_sending_time(SystemTime::now());
+        let body = format!("123=Y\x0136={}\x01", new_seq_no);
+        self.write(encoder, "4", seq_num, &sending_time, Some(&sending_time), body.as_bytes());
+    }
+
+    fn parse_or_reject<F>(&mut self, encoder : &FixEncoder, message : &FixMessage, seq_num : u64, parse : F) -> Option<Inbound>
+        where F : FnOnce(&Session, &FixMessage) -> Result<Inbound, FixError> {
+        match parse(self, message) {
+            Ok(inbound) => Some(inbound),
+            Err(e) => {
+                let reason = match e {
+                    FixError::MissingField(_) => 1,
+                    _ => 5,
+                };
+                self.reject(encoder, message, seq_num, reason, &e.to_string());
+                None
+            }
+        }
+    }
+
+    fn new_order(&self, encoder : &FixEncoder, message : &FixMessage) -> Result<Inbound, FixError> {
+        // 只支持限价单
+        if message.require(40)? != b"2" {
+            return Err(FixError::InvalidField(40));
+        }
+        let security_id : SecurityID = id_field(message, 55)?;
+        let side = match message.require(54)? {
+            b"1" => K_BUY,
+            b"2" => K_SELL,
+            _ => return Err(FixError::InvalidField(54)),
+        };
+        let price = parse_price(message.require(44)?, encoder.price_scale(&security_id)).ok_or(FixError::InvalidField(44))?;
+        Ok(Inbound::NewOrder(NewOrder {
+            order_id : 0,
+            seq_num : 0,
+            pbu_id : self.pbu_id,
+            cl_ord_id : id_field(message, 11)?,
+            security_id,
+            side,
+            price,
+            qty : message.require_u64(38)?,
+        }))
+    }
+
+    fn cancel_request(&self, message : &FixMessage) -> Result<Inbound, FixError> {
+        Ok(Inbound::CancelRequest(CancelRequest {
+            order_id : 0,
+            seq_num : 0,
+            pbu_id : self.pbu_id,
+            cl_ord_id : id_field(message, 11)?,
+            orig_cl_ord_id : id_field(message, 41)?,
+            security_id : id_field(message, 55)?,
+        }))
+    }
+
+    // 35=3, reason 为 SessionRejectReason(373)
+    fn reject(&mut self, encoder : &FixEncoder, message : &FixMessage, seq_num : u64, reason : u32, text : &str) {
+        let mut body = format!("45={}\x01372=", seq_num).into_bytes();
+        body.extend_from_slice(message.msg_type());
+        body.extend_from_slice(format!("\x01373={}\x0158={}\x01", reason, text).as_bytes());
+        self.send_admin(encoder, "3", &body);
+    }
+
+    fn logout(&mut self, encoder : &FixEncoder, text : &str) {
+        let body = format!("58={}\x01", text);
+        self.send_admin(encoder, "5", body.as_bytes());
+        self.disconnect();
+    }
+
+    fn send_admin(&mut self, encoder : &FixEncoder, msg_type : &'static str, body : &[u8]) {
+        let sending_time = format_sending_time(SystemTime::now());
+        let seq_num = self.next_out;
+        self.next_out += 1;
+        self.push_sent(None);
+        self.write(encoder, msg_type, seq_num, &sending_time, None, body);
+    }
+
+    // 超出窗口时丢弃最早的消息, 避免长时间运行的会话无限增长
+    fn push_sent(&mut self, sent : Option<SentMessage>) {
+        if self.sent.len() >= self.resend_window && self.sent.pop_front().is_some() {
+            self.first_sent += 1;
+        }
+        self.sent.push_back(sent);
+    }
+
+    fn write(&mut self, encoder : &FixEncoder, msg_type : &str, seq_num : u64, sending_time : &[u8], orig_sending_time : Option<&[u8]>, body : &[u8]) {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => return,
+        };
+        self.buffer.clear();
+        let header = FixHeader { msg_type, target : &self.pbu_id, seq_num, sending_time, orig_sending_time };
+        encoder.write_message(&mut self.buffer, &header, body);
+        if writer.write_all(&self.buffer).and_then(|_| writer.flush()).is_err() {
+            self.disconnect();
+        }
+        self.last_sent = Instant::now();
+    }
+}
+
+// 定长 ID 字段, 超长时拒绝
+fn id_field<const N : usize>(message : &FixMessage, tag : u32) -> Result<[u8;N], FixError> {
+    let value = message.require(tag)?;
+    let value = std::str::from_utf8(value).map_err(|_| FixError::InvalidField(tag))?;
+    if value.len() > N {
+        return Err(FixError::InvalidField(tag));
+    }
+    Ok(to_array(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // 把会话写出的字节收集起来
+    #[derive(Clone, Default)]
+    struct Wire(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Wire {
+        fn write(&mut self, buf : &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Wire {
+        fn take(&self) -> Vec<FixMessage> {
+            let bytes = std::mem::take(&mut *self.0.lock().unwrap());
+            let mut reader = message::FixReader::new(bytes.as_slice());
+            let mut messages = Vec::new();
+            while let Some(message) = reader.read_message().unwrap() {
+                messages.push(FixMessage::parse(&message).unwrap());
+            }
+            messages
+        }
+    }
+
+    struct Client {
+        encoder : FixEncoder,
+        seq_num : u64,
+    }
+
+    impl Client {
+        fn new() -> Client {
+            Client { encoder : FixEncoder::new(FixConfig { sender_comp_id : String::from("PBU001"), ..FixConfig::default() }).unwrap(), seq_num : 0 }
+        }
+        fn message(&mut self, msg_type : &str, body : &str) -> FixMessage {
+            self.seq_num += 1;
+            self.message_with_seq(msg_type, self.seq_num, body)
+        }
+        fn message_with_seq(&self, msg_type : &str, seq_num : u64, body : &str) -> FixMessage {
+            let sending_time = format_sending_time(SystemTime::now());
+            let header = FixHeader { msg_type, target : b"ENGINE", seq_num, sending_time : &sending_time, orig_sending_time : None };
+            let mut buffer = Vec::new();
+            self.encoder.write_message(&mut buffer, &header, body.as_bytes());
+            FixMessage::parse(&buffer).unwrap()
+        }
+    }
+
+    fn session() -> (Session, FixEncoder, Wire) {
+        let mut session = Session::new(to_array("PBU001"));
+        let wire = Wire::default();
+        session.connect(Box::new(wire.clone()), Instant::now());
+        (session, FixEncoder::new(FixConfig::default()).unwrap(), wire)
+    }
+
+    #[test]
+    fn logon_and_orders() {
+        let (mut session, encoder, wire) = session();
+        let mut client = Client::new();
+        let now = Instant::now();
+
+        assert!(session.on_message(&encoder, &client.message("D", "11=C1\x0155=SEC001\x0154=1\x0138=10\x0140=2\x0144=100\x01"), now).is_none());
+        assert!(!session.is_connected());
+        assert_eq!(wire.take()[0].msg_type(), b"5");
+
+        session.connect(Box::new(wire.clone()), now);
+        client.seq_num = 0;
+        session.on_message(&encoder, &client.message("A", "98=0\x01108=30\x01"), now);
+        assert!(session.is_logged_on());
+        let replies = wire.take();
+        assert_eq!(replies[0].msg_type(), b"A");
+        assert_eq!(replies[0].get(56), Some(&b"PBU001"[..]));
+
+        match session.on_message(&encoder, &client.message("D", "11=C1\x0155=SEC001\x0154=1\x0138=10\x0140=2\x0144=100\x01"), now) {
+            Some(Inbound::NewOrder(order)) => {
+                assert_eq!(order.pbu_id, to_array("PBU001"));
+                assert_eq!(order.side, K_BUY);
+                assert_eq!((order.price, order.qty), (100, 10));
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(session.on_message(&encoder, &client.message("F", "11=C2\x0141=C1\x0155=SEC001\x01"), now),
+            Some(Inbound::CancelRequest(_))));
+
+        // 缺少价格的委托被会话层拒绝
+        assert!(session.on_message(&encoder, &client.message("D", "11=C3\x0155=SEC001\x0154=1\x0138=10\x0140=2\x01"), now).is_none());
+        let replies = wire.take();
+        assert_eq!(replies[0].msg_type(), b"3");
+        assert_eq!(replies[0].get_u64(373), Some(1));
+
+        session.on_message(&encoder, &client.message("1", "112=PING\x01"), now);
+        let replies = wire.take();
+        assert_eq!(replies[0].msg_type(), b"0");
+        assert_eq!(replies[0].get(112), Some(&b"PING"[..]));
+
+        session.on_message(&encoder, &client.message("5", ""), now);
+        assert_eq!(wire.take()[0].msg_type(), b"5");
+        assert!(!session.is_connected());
+    }
+
+    #[test]
+    fn sequence_gap() {
+        let (mut session, encoder, wire) = session();
+        let mut client = Client::new();
+        let now = Instant::now();
+        session.on_message(&encoder, &client.message("A", "98=0\x01108=30\x01"), now);
+        wire.take();
+
+        // 跳过序号 2, 会话要求重发且不处理后续消息
+        client.seq_num += 1;
+        assert!(session.on_message(&encoder, &client.message("D", "11=C1\x0155=SEC001\x0154=1\x0138=10\x0140=2\x0144=100\x01"), now).is_none());
+        let replies = wire.take();
+        assert_eq!(replies[0].msg_type(), b"2");
+        assert_eq!(replies[0].get_u64(7), Some(2));
+        assert!(session.on_message(&encoder, &client.message("0", ""), now).is_none());
+        assert!(wire.take().is_empty());
+
+        // 对端以 GapFill 补齐
+        let gap_fill = client.message_with_seq("4", 2, "123=Y\x0136=3\x01");
+        session.on_message(&encoder, &gap_fill, now);
+        assert_eq!(session.next_in(), 3);
+        client.seq_num = 2;
+        assert!(session.on_message(&encoder, &client.message("D", "11=C1\x0155=SEC001\x0154=1\x0138=10\x0140=2\x0144=100\x01"), now).is_some());
+
+        // 序号过小直接登出
+        session.on_message(&encoder, &client.message_with_seq("0", 1, ""), now);
+        assert!(!session.is_connected());
+    }
+
+    #[test]
+    fn gap_filled_by_resent_messages() {
+        let (mut session, encoder, wire) = session();
+        let mut client = Client::new();
+        let now = Instant::now();
+        session.on_message(&encoder, &client.message("A", "98=0\x01108=30\x01"), now);
+        wire.take();
+
+        // 跳过序号 2, 对端重发 2 与 3 两条委托补齐
+        client.seq_num += 1;
+        session.on_message(&encoder, &client.message("D", "11=C1\x0155=SEC001\x0154=1\x0138=10\x0140=2\x0144=100\x01"), now);
+        assert_eq!(wire.take()[0].msg_type(), b"2");
+        for seq_num in 2..=3 {
+            let body = format!("43=Y\x0111=C{}\x0155=SEC001\x0154=1\x0138=10\x0140=2\x0144=100\x01", seq_num);
+            assert!(session.on_message(&encoder, &client.message_with_seq("D", seq_num, &body), now).is_some());
+        }
+        assert_eq!(session.next_in(), 4);
+
+        // 之后的缺口再次要求重发
+        client.seq_num = 4;
+        assert!(session.on_message(&encoder, &client.message("0", ""), now).is_none());
+        let replies = wire.take();
+        assert_eq!(replies[0].msg_type(), b"2");
+        assert_eq!(replies[0].get_u64(7), Some(4));
+    }
+
+    #[test]
+    fn resend_reports() {
+        let (mut session, encoder, wire) = session();
+        let mut client = Client::new();
+        let now = Instant::now();
+
+        // 登录前的报告先保存
+        session.send_app(&encoder, "8", b"37=1\x01");
+        session.send_app(&encoder, "8", b"37=2\x01");
+        assert!(wire.take().is_empty());
+
+        session.on_message(&encoder, &client.message("A", "98=0\x01108=30\x01"), now);
+        let replies = wire.take();
+        assert_eq!(replies[0].seq_num(), Ok(3));
+
+        session.on_message(&encoder, &client.message("2", "7=1\x0116=0\x01"), now);
+        let replies = wire.take();
+        assert_eq!(replies.len(), 3);
+        assert_eq!((replies[0].msg_type(), replies[0].seq_num(), replies[0].get(37)), (&b"8"[..], Ok(1), Some(&b"1"[..])));
+        assert!(replies[0].get_flag(43));
+        assert!(replies[0].get(122).is_some());
+        assert_eq!(replies[1].seq_num(), Ok(2));
+        // Logon 以 GapFill 代替
+        assert_eq!((replies[2].msg_type(), replies[2].seq_num(), replies[2].get_u64(36)), (&b"4"[..], Ok(3), Some(4)));
+        assert!(replies[2].get_flag(123));
+    }
+
+    #[test]
+    fn resend_window() {
+        let (mut session, encoder, wire) = session();
+        session.resend_window = 2;
+        let mut client = Client::new();
+        let now = Instant::now();
+
+        session.on_message(&encoder, &client.message("A", "98=0\x01108=30\x01"), now);
+        for i in 0..3 {
+            session.send_app(&encoder, "8", format!("37={}\x01", i).as_bytes());
+        }
+        assert_eq!(session.sent.len(), 2);
+        wire.take();
+
+        // 1 是 Logon, 2 已移出窗口, 合并为一个 GapFill
+        session.on_message(&encoder, &client.message("2", "7=1\x0116=0\x01"), now);
+        let replies = wire.take();
+        assert_eq!(replies.len(), 3);
+        assert_eq!((replies[0].msg_type(), replies[0].seq_num(), replies[0].get_u64(36)), (&b"4"[..], Ok(1), Some(3)));
+        assert_eq!((replies[1].seq_num(), replies[1].get(37)), (Ok(3), Some(&b"1"[..])));
+        assert_eq!((replies[2].seq_num(), replies[2].get(37)), (Ok(4), Some(&b"2"[..])));
+    }
+}
@@ -0,0 +1,585 @@
+use std::collections::HashMap;
+use std::io::{self, BufWriter, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use serde::{Serialize, Deserialize};
+
+use crate::types::*;
+use crate::messages::{Inbound, NewOrder, CancelRequest};
+use crate::engin::{Engin, EngineError};
+use crate::report_sink::{ReportSink, write_frame, read_frame_with_limit};
+use crate::report_log::{ReportLog, StreamID};
+use crate::listener::{Listener, Submitter};
+use crate::reports::{self, DecodeError, OutputMessage};
+
+// 网关协议: 每帧为 4 字节小端长度 + 消息, 消息第一个字节为消息类型, 之后是消息体的 bincode 编码
+// 网关发出的报告帧为 K_MSG_TYPE_REPORT + 2 字节小端流编号 + 8 字节小端流序号 + reports::encode 的结果
+pub const K_MSG_TYPE_LOGON : u8 = b'A';
+pub const K_MSG_TYPE_LOGOUT : u8 = b'5';
+pub const K_MSG_TYPE_NEW_ORDER : u8 = b'D';
+pub const K_MSG_TYPE_CANCEL_REQUEST : u8 = b'F';
+pub const K_MSG_TYPE_REJECT : u8 = b'3';
+pub const K_MSG_TYPE_REPORT : u8 = b'R';
+
+// 请求帧的最大长度, 超过即断开连接
+const MAX_REQUEST_LEN : usize = 256;
+// 重传时每次从日志读出的报告数
+const RESEND_BATCH : u64 = 256;
+// 日志 flush 之前暂存的报告数上限, 达到时不等 exe 阶段的队列处理空即 flush
+const MAX_PENDING : usize = 1 << 10;
+// 客户端长时间不读报告时, 连接的写线程写超时后断开该连接
+const WRITE_TIMEOUT : Duration = Duration::from_secs(1);
+// 每个连接待发的帧数上限, 队列满时断开该连接, exe 阶段从不等待客户端
+const WRITE_QUEUE_CAPACITY : usize = 1 << 12;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Logon {
+    pub pbu_id : PBUID,
+    pub password : String,
+    // 断线重连时每条流希望收到的下一个序号, 登录应答之后先重传从此序号起已记录的报告; 未列出的流不重传
+    pub next_seqs : Vec<(StreamID, u64)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NewOrderRequest {
+    pub cl_ord_id : ClOrdID,
+    pub security_id : SecurityID,
+    pub side : Side,
+    pub price : Price,
+    pub qty : Qty,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CancelOrderRequest {
+    pub cl_ord_id : ClOrdID,
+    pub orig_cl_ord_id : ClOrdID,
+    pub security_id : SecurityID,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Logon(Logon),
+    NewOrder(NewOrderRequest),
+    CancelRequest(CancelOrderRequest),
+    Logout,
+}
+
+impl Request {
+    fn msg_type(&self) -> u8 {
+        match self {
+            Request::Logon(_) => K_MSG_TYPE_LOGON,
+            Request::NewOrder(_) => K_MSG_TYPE_NEW_ORDER,
+            Request::CancelRequest(_) => K_MSG_TYPE_CANCEL_REQUEST,
+            Request::Logout => K_MSG_TYPE_LOGOUT,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RejectReason {
+    NotLoggedOn,
+    AuthenticationFailed,
+    AlreadyLoggedOn,
+    Malformed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    LogonAccepted(PBUID),
+    Reject(RejectReason),
+    // 客户端记下每条流最后收到的 seq, 重连时以 seq + 1 作为 Logon::next_seqs
+    Report { stream : StreamID, seq : u64, report : OutputMessage },
+}
+
+pub fn encode_request(request : &Request) -> Vec<u8> {
+    let mut buffer = vec![request.msg_type()];
+    let result = match request {
+        Request::Logon(logon) => bincode::serialize_into(&mut buffer, logon),
+        Request::NewOrder(order) => bincode::serialize_into(&mut buffer, order),
+        Request::CancelRequest(cancel) => bincode::serialize_into(&mut buffer, cancel),
+        Request::Logout => Ok(()),
+    };
+    result.expect("encode request");
+    buffer
+}
+
+pub fn decode_request(bytes : &[u8]) -> Result<Request, DecodeError> {
+    let (msg_type, mut reader) = match bytes.split_first() {
+        Some((msg_type, body)) => (*msg_type, body),
+        None => return Err(DecodeError::Malformed("empty message".to_string())),
+    };
+    let request = match msg_type {
+        K_MSG_TYPE_LOGON => Request::Logon(bincode::deserialize_from(&mut reader)?),
+        K_MSG_TYPE_NEW_ORDER => Request::NewOrder(bincode::deserialize_from(&mut reader)?),
+        K_MSG_TYPE_CANCEL_REQUEST => Request::CancelRequest(bincode::deserialize_from(&mut reader)?),
+        K_MSG_TYPE_LOGOUT => Request::Logout,
+        msg_type => return Err(DecodeError::UnknownMsgType(msg_type)),
+    };
+    if !reader.is_empty() {
+        return Err(DecodeError::Malformed(format!("{} trailing bytes", reader.len())));
+    }
+    Ok(request)
+}
+
+fn encode_logon_accepted(pbu_id : &PBUID) -> Vec<u8> {
+    let mut buffer = vec![K_MSG_TYPE_LOGON];
+    buffer.extend_from_slice(pbu_id);
+    buffer
+}
+
+fn encode_reject(reason : RejectReason) -> Vec<u8> {
+    let mut buffer = vec![K_MSG_TYPE_REJECT];
+    bincode::serialize_into(&mut buffer, &reason).expect("encode reject");
+    buffer
+}
+
+fn encode_report(stream : StreamID, seq : u64, report : &[u8]) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(11 + report.len());
+    buffer.push(K_MSG_TYPE_REPORT);
+    buffer.extend_from_slice(&stream.to_le_bytes());
+    buffer.extend_from_slice(&seq.to_le_bytes());
+    buffer.extend_from_slice(report);
+    buffer
+}
+
+pub fn decode_response(bytes : &[u8]) -> Result<Response, DecodeError> {
+    match bytes.split_first() {
+        Some((&K_MSG_TYPE_LOGON, body)) => {
+            let pbu_id = body.try_into().map_err(|_| DecodeError::Malformed(format!("logon body of {} bytes", body.len())))?;
+            Ok(Response::LogonAccepted(pbu_id))
+        },
+        Some((&K_MSG_TYPE_REJECT, body)) => {
+            let mut reader = body;
+            let reason = bincode::deserialize_from(&mut reader)?;
+            if !reader.is_empty() {
+                return Err(DecodeError::Malformed(format!("{} trailing bytes", reader.len())));
+            }
+            Ok(Response::Reject(reason))
+        },
+        Some((&K_MSG_TYPE_REPORT, body)) if body.len() >= 10 => {
+            let stream = u16::from_le_bytes([body[0], body[1]]);
+            let mut seq = [0u8;8];
+            seq.copy_from_slice(&body[2..10]);
+            Ok(Response::Report { stream, seq : u64::from_le_bytes(seq), report : reports::decode(&body[10..])? })
+        },
+        Some((&K_MSG_TYPE_REPORT, body)) => Err(DecodeError::Malformed(format!("report body of {} bytes", body.len()))),
+        Some((msg_type, _)) => Err(DecodeError::UnknownMsgType(*msg_type)),
+        None => Err(DecodeError::Malformed("empty message".to_string())),
+    }
+}
+
+// 与 report_sink::read_frame 相同, 但请求帧的长度上限更小
+fn read_request<R : Read>(reader : &mut R) -> io::Result<Option<Vec<u8>>> {
+    read_frame_with_limit(reader, MAX_REQUEST_LEN)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GatewayConfig {
+    // 交易单元及其口令, 不在表中的交易单元不能登录
+    pub credentials : HashMap<PBUID, String>,
+    // 设置后每条报告先按交易单元与流编号记录, 日志 flush 之后才发送, 重连的交易单元可以取回断线期间的报告
+    pub report_log : Option<ReportLog>,
+    // 证券所属的报告流, 不在表中的证券属于流 0
+    pub streams : HashMap<SecurityID, StreamID>,
+}
+
+// 已登录的交易单元的连接: 发出的帧先放入队列, 由该连接的写线程写出
+struct Session {
+    id : u64,
+    tx : SyncSender<Vec<u8>>,
+    stream : TcpStream,
+    // 登录时各重传流的下一个序号, 更早的报告由写线程从日志重传, 不再经队列发送
+    live_from : HashMap<StreamID, u64>,
+}
+
+impl Session {
+    // 不阻塞; 队列满或写线程已退出时返回 false, 调用方随即断开连接
+    fn queue(&self, frame : Vec<u8>) -> bool {
+        self.tx.try_send(frame).is_ok()
+    }
+
+    fn disconnect(&self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+type Sessions = HashMap<PBUID, Session>;
+
+// 登录成功的连接: 会话编号, 发送队列与需要重传的 (流, 起始序号, 截止序号)
+struct Accepted {
+    id : u64,
+    rx : Receiver<Vec<u8>>,
+    resend : Vec<(StreamID, u64, u64)>,
+}
+
+fn lock(sessions : &Mutex<Sessions>) -> MutexGuard<'_, Sessions> {
+    sessions.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// 以二进制协议接受委托的网关, 报告经 GatewaySink 送回交易单元登录的连接
+pub struct Gateway {
+    listener : Listener,
+    config : GatewayConfig,
+    sessions : Arc<Mutex<Sessions>>,
+    session_id : AtomicU64,
+}
+
+impl Gateway {
+    pub fn bind<A : ToSocketAddrs>(addr : A, config : GatewayConfig) -> io::Result<Gateway> {
+        Ok(Gateway {
+            listener : Listener::bind(addr)?,
+            config,
+            sessions : Arc::new(Mutex::new(HashMap::new())),
+            session_id : AtomicU64::new(0),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // 作为 Engin 的 ReportSink, 要求 Engin 输出 bincode 格式的报告
+    pub fn sink(&self) -> GatewaySink {
+        GatewaySink {
+            sessions : self.sessions.clone(),
+            report_log : self.config.report_log.clone(),
+            streams : self.config.streams.clone(),
+            seqs : HashMap::new(),
+            pending : Vec::new(),
+        }
+    }
+
+    // 接受连接并把委托依次交给 engin, 直到 stop 被调用或 engin 出错
+    pub fn run<S : ReportSink + 'static>(&self, engin : &Engin<S>) -> Result<(), EngineError> {
+        self.listener.run(&engin.ingress(), |stream, submitter| {
+            let _ = self.serve_session(stream, submitter);
+        })
+    }
+
+    // 可在其它线程调用, 断开所有连接, run 随后返回
+    pub fn stop(&self) {
+        self.listener.stop();
+    }
+
+    fn serve_session(&self, stream : &TcpStream, submitter : &Submitter) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let mut reader = stream.try_clone()?;
+
+        // 第一条消息必须是 Logon
+        let (pbu_id, Accepted { id, rx, resend }) = match read_request(&mut reader)?.map(|bytes| decode_request(&bytes)) {
+            Some(Ok(Request::Logon(logon))) => match self.logon(&logon, stream)? {
+                Some(accepted) => (logon.pbu_id, accepted),
+                None => return Ok(()),
+            },
+            Some(Ok(_)) => return write_frame(&mut &*stream, &encode_reject(RejectReason::NotLoggedOn)),
+            Some(Err(_)) => return write_frame(&mut &*stream, &encode_reject(RejectReason::Malformed)),
+            None => return Ok(()),
+        };
+
+        // 写线程先发登录应答与重传的报告, 再发队列中的帧; 会话移除后队列关闭, 写线程写完剩余的帧后退出
+        let writer = stream.try_clone()?;
+        let report_log = self.config.report_log.as_ref();
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                let mut writer = BufWriter::new(writer);
+                let result = write_frame(&mut writer, &encode_logon_accepted(&pbu_id))
+                    .and_then(|_| resend.iter().try_for_each(|range| write_resend(&mut writer, report_log, &pbu_id, range)))
+                    .and_then(|_| write_queued(&mut writer, rx));
+                if result.is_err() {
+                    let _ = writer.get_ref().shutdown(Shutdown::Both);
+                }
+            });
+            let result = self.read_requests(&mut reader, pbu_id, id, submitter);
+            let mut sessions = lock(&self.sessions);
+            // 连接可能已因队列满被 GatewaySink 移除, 只移除自己登记的会话
+            if matches!(sessions.get(&pbu_id), Some(session) if session.id == id) {
+                sessions.remove(&pbu_id);
+            }
+            result
+        })
+    }
+
+    fn read_requests(&self, reader : &mut TcpStream, pbu_id : PBUID, id : u64, submitter : &Submitter) -> io::Result<()> {
+        loop {
+            let bytes = match read_request(reader)? {
+                Some(bytes) => bytes,
+                None => return Ok(()),
+            };
+            let inbound = match decode_request(&bytes) {
+                Ok(Request::NewOrder(order)) => Inbound::NewOrder(NewOrder {
+                    order_id : 0,
+                    seq_num : 0,
+                    pbu_id,
+                    cl_ord_id : order.cl_ord_id,
+                    security_id : order.security_id,
+                    side : order.side,
+                    price : order.price,
+                    qty : order.qty,
+                }),
+                Ok(Request::CancelRequest(cancel)) => Inbound::CancelRequest(CancelRequest {
+                    order_id : 0,
+                    seq_num : 0,
+                    pbu_id,
+                    cl_ord_id : cancel.cl_ord_id,
+                    orig_cl_ord_id : cancel.orig_cl_ord_id,
+                    security_id : cancel.security_id,
+                }),
+                Ok(Request::Logout) => return Ok(()),
+                Ok(Request::Logon(_)) => {
+                    self.reject(&pbu_id, id, RejectReason::AlreadyLoggedOn);
+                    continue;
+                },
+                Err(_) => {
+                    self.reject(&pbu_id, id, RejectReason::Malformed);
+                    continue;
+                },
+            };
+            if !submitter.submit(inbound) {
+                return Ok(());
+            }
+        }
+    }
+
+    // 校验口令, 同一交易单元只允许一个连接
+    fn logon(&self, logon : &Logon, stream : &TcpStream) -> io::Result<Option<Accepted>> {
+        let mut sessions = lock(&self.sessions);
+        let reason = match self.config.credentials.get(&logon.pbu_id) {
+            Some(password) if *password == logon.password => {
+                if sessions.contains_key(&logon.pbu_id) { Some(RejectReason::AlreadyLoggedOn) } else { None }
+            },
+            _ => Some(RejectReason::AuthenticationFailed),
+        };
+        if let Some(reason) = reason {
+            write_frame(&mut &*stream, &encode_reject(reason))?;
+            return Ok(None);
+        }
+        // 锁内只取各流当前的最后序号: 不超过它的报告已在日志中, 由写线程在锁外重传, 之后的报告经队列送达,
+        // 因此每条报告恰好送达一次且保持顺序
+        let mut resend = Vec::new();
+        let mut live_from = HashMap::new();
+        if let Some(report_log) = &self.config.report_log {
+            for (stream_id, next_seq) in &logon.next_seqs {
+                let last_seq = report_log.last_seq(&logon.pbu_id, *stream_id);
+                live_from.insert(*stream_id, last_seq + 1);
+                if *next_seq <= last_seq {
+                    resend.push((*stream_id, (*next_seq).max(1), last_seq));
+                }
+            }
+        }
+        let (tx, rx) = mpsc::sync_channel(WRITE_QUEUE_CAPACITY);
+        let id = self.session_id.fetch_add(1, Ordering::Relaxed);
+        sessions.insert(logon.pbu_id, Session { id, tx, stream : stream.try_clone()?, live_from });
+        Ok(Some(Accepted { id, rx, resend }))
+    }
+
+    fn reject(&self, pbu_id : &PBUID, id : u64, reason : RejectReason) {
+        let mut sessions = lock(&self.sessions);
+        if let Some(session) = sessions.get(pbu_id) {
+            if session.id == id && !session.queue(encode_reject(reason)) {
+                session.disconnect();
+                sessions.remove(pbu_id);
+            }
+        }
+    }
+}
+
+// 分批从日志读出重传的报告, 不占用会话表的锁
+fn write_resend<W : Write>(writer : &mut W, report_log : Option<&ReportLog>, pbu_id : &PBUID, range : &(StreamID, u64, u64)) -> io::Result<()> {
+    let (stream, mut seq, end) = *range;
+    let report_log = match report_log {
+        Some(report_log) => report_log,
+        None => return Ok(()),
+    };
+    while seq <= end {
+        let reports = report_log.range(pbu_id, stream, seq, end.min(seq + RESEND_BATCH - 1))?;
+        if reports.is_empty() {
+            break;
+        }
+        for report in reports {
+            write_frame(writer, &encode_report(stream, seq, &report))?;
+            seq += 1;
+        }
+    }
+    Ok(())
+}
+
+// 依次写出队列中的帧, 队列暂时为空时才 flush; 写失败或超时后写线程断开连接, 读线程随之结束会话
+fn write_queued<W : Write>(writer : &mut W, rx : Receiver<Vec<u8>>) -> io::Result<()> {
+    loop {
+        let frame = match rx.try_recv() {
+            Ok(frame) => frame,
+            Err(TryRecvError::Empty) => {
+                writer.flush()?;
+                match rx.recv() {
+                    Ok(frame) => frame,
+                    Err(_) => return Ok(()),
+                }
+            },
+            Err(TryRecvError::Disconnected) => return writer.flush(),
+        };
+        write_frame(writer, &frame)?;
+    }
+}
+
+// 给 exe 阶段输出的报告加上流编号与序号, 放入所属交易单元连接的发送队列; 未登录的交易单元的报告被丢弃,
+// 配置了 report_log 时可在重连后取回
+pub struct GatewaySink {
+    sessions : Arc<Mutex<Sessions>>,
+    report_log : Option<ReportLog>,
+    streams : HashMap<SecurityID, StreamID>,
+    // 未配置 report_log 时各流的最后序号
+    seqs : HashMap<(PBUID, StreamID), u64>,
+    // 已记录但日志还未 flush 的报告, flush 之后才发送, 客户端收到的报告在崩溃后一定能重传
+    pending : Vec<(PBUID, StreamID, u64, Vec<u8>)>,
+}
+
+impl GatewaySink {
+    fn deliver(sessions : &mut Sessions, pbu_id : &PBUID, stream : StreamID, seq : u64, frame : Vec<u8>) {
+        let session = match sessions.get(pbu_id) {
+            Some(session) => session,
+            None => return,
+        };
+        // 登录前已记录的报告由写线程重传
+        if seq < session.live_from.get(&stream).copied().unwrap_or(0) {
+            return;
+        }
+        // 客户端跟不上时只断开该连接, 不影响 engin
+        if !session.queue(frame) {
+            session.disconnect();
+            sessions.remove(pbu_id);
+        }
+    }
+}
+
+impl ReportSink for GatewaySink {
+    fn send(&mut self, report : &[u8]) -> io::Result<()> {
+        let (pbu_id, security_id) = match reports::decode(report).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))? {
+            OutputMessage::ExecutionReport(report) => (report.pbu_id, report.security_id),
+            OutputMessage::TradeCaptureReport(report) => (report.pbu_id, report.security_id),
+            OutputMessage::CancelReject(report) => (report.pbu_id, report.security_id),
+        };
+        let stream = self.streams.get(&security_id).copied().unwrap_or(0);
+        match &self.report_log {
+            Some(report_log) => {
+                let seq = report_log.append(&pbu_id, stream, report)?;
+                self.pending.push((pbu_id, stream, seq, encode_report(stream, seq, report)));
+                if self.pending.len() >= MAX_PENDING {
+                    self.flush()?;
+                }
+            },
+            None => {
+                let seq = self.seqs.entry((pbu_id, stream)).or_insert(0);
+                *seq += 1;
+                GatewaySink::deliver(&mut lock(&self.sessions), &pbu_id, stream, *seq, encode_report(stream, *seq, report));
+            },
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(report_log) = &self.report_log {
+            report_log.flush()?;
+        }
+        if !self.pending.is_empty() {
+            let mut sessions = lock(&self.sessions);
+            for (pbu_id, stream, seq, frame) in self.pending.drain(..) {
+                GatewaySink::deliver(&mut sessions, &pbu_id, stream, seq, frame);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reports::{encode, CancelReject};
+
+    #[test]
+    fn request_round_trip() {
+        let requests = [
+            Request::Logon(Logon { pbu_id : to_array("PBU001"), password : "secret".to_string(), next_seqs : vec![(0, 3), (2, 1)] }),
+            Request::NewOrder(NewOrderRequest {
+                cl_ord_id : to_array("C1"), security_id : to_array("SEC001"), side : K_BUY, price : 100, qty : 10,
+            }),
+            Request::CancelRequest(CancelOrderRequest {
+                cl_ord_id : to_array("C2"), orig_cl_ord_id : to_array("C1"), security_id : to_array("SEC001"),
+            }),
+            Request::Logout,
+        ];
+        for request in requests {
+            assert_eq!(decode_request(&encode_request(&request)).unwrap(), request);
+        }
+
+        assert!(matches!(decode_request(b""), Err(DecodeError::Malformed(_))));
+        assert!(matches!(decode_request(b"Z"), Err(DecodeError::UnknownMsgType(b'Z'))));
+        assert!(matches!(decode_request(b"D\x01"), Err(DecodeError::Malformed(_))));
+        assert!(matches!(decode_request(b"5\x00"), Err(DecodeError::Malformed(_))));
+    }
+
+    #[test]
+    fn response_decode() {
+        assert_eq!(decode_response(&encode_logon_accepted(&to_array("PBU001"))).unwrap(),
+            Response::LogonAccepted(to_array("PBU001")));
+        assert_eq!(decode_response(&encode_reject(RejectReason::AuthenticationFailed)).unwrap(),
+            Response::Reject(RejectReason::AuthenticationFailed));
+
+        let reject = CancelReject {
+            order_id : 1,
+            seq_num : 0,
+            pbu_id : to_array("PBU001"),
+            cl_ord_id : to_array("C2"),
+            orig_cl_ord_id : to_array("C1"),
+            security_id : to_array("SEC001"),
+            rejected_reason : CancelReasonCode::OrderNotExisted,
+        };
+        // 不带流序号的报告帧不是网关的消息
+        assert!(matches!(decode_response(&encode(&reject)), Err(DecodeError::UnknownMsgType(_))));
+        assert_eq!(decode_response(&encode_report(2, 7, &encode(&reject))).unwrap(),
+            Response::Report { stream : 2, seq : 7, report : OutputMessage::CancelReject(reject) });
+        assert!(matches!(decode_response(&[K_MSG_TYPE_REPORT, 0, 0]), Err(DecodeError::Malformed(_))));
+    }
+
+    #[test]
+    fn slow_client_disconnected() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        // 队列只有两个位置, 且没有写线程取走
+        let (tx, _rx) = mpsc::sync_channel(2);
+        let pbu_id = to_array("PBU001");
+        let sessions = Arc::new(Mutex::new(HashMap::new()));
+        lock(&sessions).insert(pbu_id, Session { id : 0, tx, stream, live_from : HashMap::new() });
+        let mut sink = GatewaySink { sessions : sessions.clone(), report_log : None, streams : HashMap::new(), seqs : HashMap::new(), pending : Vec::new() };
+
+        let report = encode(&CancelReject {
+            order_id : 1,
+            seq_num : 1,
+            pbu_id,
+            cl_ord_id : to_array("C2"),
+            orig_cl_ord_id : to_array("C1"),
+            security_id : to_array("SEC001"),
+            rejected_reason : CancelReasonCode::OrderNotExisted,
+        });
+        sink.send(&report).unwrap();
+        sink.send(&report).unwrap();
+        assert!(lock(&sessions).contains_key(&pbu_id));
+        sink.send(&report).unwrap();
+        assert!(lock(&sessions).is_empty());
+        let mut buf = [0u8;1];
+        assert_eq!((&client).read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn request_too_long() {
+        let mut frame = Vec::new();
+        write_frame(&mut frame, &[0u8; MAX_REQUEST_LEN + 1]).unwrap();
+        assert_eq!(read_request(&mut &frame[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
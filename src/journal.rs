@@ -0,0 +1,335 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::types::SeqNum;
+use crate::messages::Inbound;
+use crate::recorder::{self, InputRecorder};
+use crate::reports::DecodeError;
+
+// 帧格式: 4 字节负载长度 + 4 字节负载的 CRC32, 均为小端; 负载为 recorder::encode 的结果
+pub(crate) const FRAME_HEADER_LEN : usize = 8;
+// 超过此长度的帧视为损坏
+pub(crate) const MAX_ENTRY_LEN : usize = 1 << 16;
+
+const CRC_TABLE : [u32;256] = crc_table();
+
+const fn crc_table() -> [u32;256] {
+    let mut table = [0u32;256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { 0xEDB8_8320 ^ (crc >> 1) } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+// CRC-32 (IEEE 802.3)
+pub fn crc32(bytes : &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &b| CRC_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+// 写入一帧, 复制连接与日志文件使用相同的帧格式
+pub(crate) fn write_entry<W : Write>(writer : &mut W, payload : &[u8]) -> io::Result<()> {
+    writer.write_all(&(payload.len() as u32).to_le_bytes())?;
+    writer.write_all(&crc32(payload).to_le_bytes())?;
+    writer.write_all(payload)
+}
+
+// 日志中间的损坏无法自动修复
+pub(crate) fn corrupted(e : DecodeError) -> io::Error {
+    match e {
+        DecodeError::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
+
+// 写入的数据何时落盘
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPolicy {
+    // 每条输入写入后立即 fsync, 送往下游的输入一定已落盘
+    PerMessage,
+    // 定序阶段处理完一批输入 (入口队列为空或达到组提交上限) 时 fsync, 这一批在 fsync 之后才送往下游
+    PerBatch,
+    // 只交给操作系统, 由后台线程按间隔 fsync, 崩溃时可能丢失最后一段输入
+    Async(Duration),
+}
+
+// 预写日志: 定序后的每条输入在送入流水线之前追加到文件中, 用于崩溃后恢复
+pub struct Journal {
+    writer : BufWriter<File>,
+    buffer : Vec<u8>,
+    policy : SyncPolicy,
+    last_seq_num : SeqNum,
+    syncer : Option<Syncer>,
+}
+
+// SyncPolicy::Async 的后台线程, 按间隔 fsync, 丢弃时停止
+#[derive(Debug)]
+pub(crate) struct Syncer {
+    running : Arc<AtomicBool>,
+    handle : Option<JoinHandle<()>>,
+}
+
+impl Syncer {
+    pub(crate) fn spawn(file : File, interval : Duration) -> Syncer {
+        let running = Arc::new(AtomicBool::new(true));
+        let flag = running.clone();
+        let handle = thread::spawn(move || {
+            while flag.load(Ordering::Acquire) {
+                thread::park_timeout(interval);
+                let _ = file.sync_data();
+            }
+        });
+        Syncer { running, handle : Some(handle) }
+    }
+}
+
+impl Drop for Syncer {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            self.running.store(false, Ordering::Release);
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Journal {
+    // 打开或创建日志; 崩溃时写了一半的最后一帧会被截掉, 其它位置的损坏返回错误, 不改动文件
+    pub fn open<P : AsRef<Path>>(path : P, policy : SyncPolicy) -> io::Result<Journal> {
+        let file = OpenOptions::new().create(true).read(true).append(true).open(path)?;
+        let len = file.metadata()?.len();
+        let mut reader = JournalReader::new(BufReader::new(file.try_clone()?));
+        let mut last_seq_num = 0;
+        while let Some(entry) = reader.next() {
+            match entry {
+                Ok(input) => last_seq_num = input.seq_num(),
+                Err(e) if reader.torn_tail(&e, len) => break,
+                Err(e) => return Err(corrupted(e)),
+            }
+        }
+        if reader.offset() < len {
+            file.set_len(reader.offset())?;
+            file.sync_all()?;
+        }
+
+        let syncer = match policy {
+            SyncPolicy::Async(interval) => Some(Syncer::spawn(file.try_clone()?, interval)),
+            _ => None,
+        };
+        Ok(Journal { writer : BufWriter::new(file), buffer : Vec::with_capacity(128), policy, last_seq_num, syncer })
+    }
+
+    // 日志中最后一条输入的序号, 空日志为 0
+    pub fn last_seq_num(&self) -> SeqNum {
+        self.last_seq_num
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()
+    }
+}
+
+impl InputRecorder for Journal {
+    fn record(&mut self, input : &Inbound) -> io::Result<()> {
+        self.buffer.clear();
+        recorder::encode_into(&mut self.buffer, input).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        write_entry(&mut self.writer, &self.buffer)?;
+        self.last_seq_num = input.seq_num();
+        if self.policy == SyncPolicy::PerMessage {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.policy {
+            SyncPolicy::Async(_) => self.writer.flush(),
+            _ => self.sync(),
+        }
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        let _ = self.sync();
+        self.syncer.take();
+    }
+}
+
+// 按写入顺序读出 write_entry 写入的帧, 校验通过后返回负载; report_log::ReportLog 使用相同的帧格式
+pub(crate) struct EntryReader<R> {
+    reader : R,
+    offset : u64,
+    // 最后读取的帧的结束位置与其是否校验失败
+    end : u64,
+    checksum_failed : bool,
+}
+
+impl<R : Read> EntryReader<R> {
+    pub(crate) fn new(reader : R) -> EntryReader<R> {
+        EntryReader { reader, offset : 0, end : 0, checksum_failed : false }
+    }
+
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub(crate) fn torn_tail(&self, error : &DecodeError, len : u64) -> bool {
+        match error {
+            DecodeError::Io(e) => e.kind() == io::ErrorKind::UnexpectedEof,
+            _ => self.checksum_failed && self.end == len,
+        }
+    }
+
+    pub(crate) fn read_entry(&mut self) -> Result<Option<Vec<u8>>, DecodeError> {
+        let mut header = [0u8;FRAME_HEADER_LEN];
+        let mut read = 0;
+        while read < FRAME_HEADER_LEN {
+            match self.reader.read(&mut header[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(DecodeError::Io(io::ErrorKind::UnexpectedEof.into())),
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(DecodeError::Io(e)),
+            }
+        }
+        let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let crc = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if len > MAX_ENTRY_LEN {
+            return Err(DecodeError::Malformed(format!("entry length {} at offset {}", len, self.offset)));
+        }
+        self.end = self.offset + (FRAME_HEADER_LEN + len) as u64;
+        let mut entry = vec![0u8; len];
+        self.reader.read_exact(&mut entry)?;
+        if crc32(&entry) != crc {
+            self.checksum_failed = true;
+            return Err(DecodeError::Malformed(format!("checksum mismatch at offset {}", self.offset)));
+        }
+        self.offset += (FRAME_HEADER_LEN + len) as u64;
+        Ok(Some(entry))
+    }
+}
+
+// 按写入顺序读出日志中的输入; 校验失败或帧不完整时返回错误, 之后不再继续读
+pub struct JournalReader<R> {
+    reader : EntryReader<R>,
+    failed : bool,
+}
+
+impl<R : Read> JournalReader<R> {
+    pub fn new(reader : R) -> JournalReader<R> {
+        JournalReader { reader : EntryReader::new(reader), failed : false }
+    }
+
+    // 已读出的完整帧之后的位置
+    pub fn offset(&self) -> u64 {
+        self.reader.offset()
+    }
+
+    // 读取失败是否只是崩溃时写了一半的最后一帧: 帧不完整, 或校验失败的帧恰好结束于文件末尾 (len)
+    pub fn torn_tail(&self, error : &DecodeError, len : u64) -> bool {
+        self.reader.torn_tail(error, len)
+    }
+
+    fn read_entry(&mut self) -> Result<Option<Inbound>, DecodeError> {
+        match self.reader.read_entry()? {
+            Some(entry) => Ok(Some(recorder::decode(&entry)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<R : Read> Iterator for JournalReader<R> {
+    type Item = Result<Inbound, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let entry = self.read_entry().transpose();
+        self.failed = matches!(entry, Some(Err(_)));
+        entry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::NewOrder;
+    use crate::types::*;
+
+    fn order(seq_num : SeqNum) -> Inbound {
+        Inbound::NewOrder(NewOrder { order_id : seq_num as OrderID, seq_num, pbu_id : to_array("PBU001"), cl_ord_id : to_array(&format!("C{}", seq_num)),
+            security_id : to_array("SEC001"), side : K_BUY, price : 100, qty : 10 })
+    }
+
+    fn read_all(path : &Path) -> Vec<Result<Inbound, DecodeError>> {
+        JournalReader::new(File::open(path).unwrap()).collect()
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn torn_tail_and_corruption() {
+        let path = std::env::temp_dir().join(format!("journal_{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut journal = Journal::open(&path, SyncPolicy::PerMessage).unwrap();
+        (1..=3).for_each(|seq_num| journal.record(&order(seq_num)).unwrap());
+        drop(journal);
+        let entries = read_all(&path);
+        assert_eq!(entries.len(), 3);
+        assert!(matches!(entries[2], Ok(Inbound::NewOrder(o)) if o.seq_num == 3));
+
+        // 模拟崩溃时写了一半的帧, 重新打开后被截掉, 之后继续追加
+        let len = std::fs::metadata(&path).unwrap().len();
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 5).unwrap();
+        assert!(read_all(&path)[2].is_err());
+        let mut journal = Journal::open(&path, SyncPolicy::Async(Duration::from_millis(10))).unwrap();
+        assert_eq!(journal.last_seq_num(), 2);
+        journal.record(&order(3)).unwrap();
+        drop(journal);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+        assert!(read_all(&path).iter().all(|entry| entry.is_ok()));
+
+        // 负载被改动时校验失败
+        let mut bytes = std::fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        std::fs::write(&path, &bytes).unwrap();
+        let entries = read_all(&path);
+        assert_eq!(entries.len(), 3);
+        assert!(matches!(&entries[2], Err(DecodeError::Malformed(msg)) if msg.contains("checksum")));
+        // 校验失败的是最后一帧, 视为写了一半
+        let journal = Journal::open(&path, SyncPolicy::PerMessage).unwrap();
+        assert_eq!(journal.last_seq_num(), 2);
+        drop(journal);
+
+        // 中间的帧损坏时不截断, 返回错误
+        let mut journal = Journal::open(&path, SyncPolicy::PerMessage).unwrap();
+        journal.record(&order(3)).unwrap();
+        drop(journal);
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[FRAME_HEADER_LEN + 1] ^= 0xFF;
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(Journal::open(&path, SyncPolicy::PerMessage).err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
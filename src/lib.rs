@@ -3,4 +3,17 @@ pub mod order_book;
 pub mod types;
 pub mod engin;
 pub mod messages;
-pub mod auction;
\ No newline at end of file
+pub mod auction;
+pub mod ring_buffer;
+pub mod pool;
+pub mod report_sink;
+pub mod report_log;
+pub mod reports;
+pub mod market_data;
+pub mod recorder;
+pub mod journal;
+pub mod replication;
+pub mod fix;
+pub mod gateway;
+pub mod szse;
+mod listener;
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::messages::Inbound;
+use crate::engin::{Ingress, EngineError};
+
+// FixAcceptor 与 Gateway 共用的 TCP 监听: 每个连接一个线程, 各自通过同一个 Ingress 把委托送入引擎
+pub(crate) struct Listener {
+    listener : TcpListener,
+    connections : Mutex<HashMap<u64, TcpStream>>,
+    connection_id : AtomicU64,
+    running : AtomicBool,
+    error : Mutex<Option<EngineError>>,
+}
+
+// 连接线程送入委托的入口, 引擎出错时停止监听
+pub(crate) struct Submitter<'a> {
+    listener : &'a Listener,
+    ingress : &'a Ingress,
+}
+
+impl Submitter<'_> {
+    // 引擎已出错或已关闭时返回 false
+    pub fn submit(&self, inbound : Inbound) -> bool {
+        match self.ingress.submit(inbound) {
+            Ok(()) => true,
+            Err(e) => {
+                self.listener.error.lock().unwrap_or_else(|e| e.into_inner()).get_or_insert(e);
+                self.listener.stop();
+                false
+            }
+        }
+    }
+}
+
+impl Listener {
+    pub fn bind<A : ToSocketAddrs>(addr : A) -> io::Result<Listener> {
+        Ok(Listener {
+            listener : TcpListener::bind(addr)?,
+            connections : Mutex::new(HashMap::new()),
+            connection_id : AtomicU64::new(0),
+            running : AtomicBool::new(true),
+            error : Mutex::new(None),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // 直到 stop 被调用或引擎出错才返回
+    pub fn run<F>(&self, ingress : &Ingress, serve : F) -> Result<(), EngineError>
+        where F : Fn(&TcpStream, &Submitter) + Sync {
+        let serve = &serve;
+        thread::scope(|scope| {
+            for stream in self.listener.incoming() {
+                if !self.running.load(Ordering::Acquire) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    let submitter = Submitter { listener : self, ingress };
+                    scope.spawn(move || self.serve(stream, &submitter, serve));
+                }
+            }
+        });
+        match self.error.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    // 可在其它线程调用, 断开所有连接, run 随后返回
+    pub fn stop(&self) {
+        if !self.running.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        // 唤醒阻塞在 accept 上的线程
+        if let Ok(addr) = self.listener.local_addr() {
+            let _ = TcpStream::connect(addr);
+        }
+        if let Ok(connections) = self.connections.lock() {
+            connections.values().for_each(|stream| { let _ = stream.shutdown(Shutdown::Both); });
+        }
+    }
+
+    fn serve<F>(&self, stream : TcpStream, submitter : &Submitter, serve : &F) where F : Fn(&TcpStream, &Submitter) {
+        let id = self.connection_id.fetch_add(1, Ordering::Relaxed);
+        if let (Ok(clone), Ok(mut connections)) = (stream.try_clone(), self.connections.lock()) {
+            connections.insert(id, clone);
+        }
+        // stop 先于登记时, 连接不会被 stop 关闭
+        if self.running.load(Ordering::Acquire) {
+            serve(&stream, submitter);
+        }
+        let _ = stream.shutdown(Shutdown::Both);
+        if let Ok(mut connections) = self.connections.lock() {
+            connections.remove(&id);
+        }
+    }
+}
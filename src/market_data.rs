@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use serde::{Serialize, Deserialize};
+
+use crate::types::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LevelAction {
+    // 新出现的价位
+    New,
+    // 价位上的总量变化
+    Change,
+    // 价位上已没有委托
+    Delete,
+}
+
+// 一个价位处理完一条输入后的状态, Delete 时 qty 与 order_count 为 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LevelUpdate {
+    pub security_id : SecurityID,
+    pub side : Side,
+    pub price : Price,
+    pub qty : Qty,
+    pub order_count : u32,
+    pub action : LevelAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Trade {
+    pub security_id : SecurityID,
+    pub price : Price,
+    pub qty : Qty,
+    pub buy_order_id : OrderID,
+    pub sell_order_id : OrderID,
+    // 主动方
+    pub aggressor_side : Side,
+}
+
+// core 阶段由簿的变化得到的行情; 同一条输入先给出所有成交, 再给出价位变化
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MarketDataEvent {
+    Level(LevelUpdate),
+    Trade(Trade),
+}
+
+// 行情阶段给每条行情分配从 1 开始连续的 md_seq_num, seq_num 为引起变化的输入的序号
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MarketDataMessage {
+    pub md_seq_num : u64,
+    pub seq_num : SeqNum,
+    pub event : MarketDataEvent,
+}
+
+// 一只证券两边按价格优先排列的全部价位. md_seq_num 为生成快照时最后一条增量行情的编号,
+// 载入快照后只需应用编号更大的增量
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepthSnapshot {
+    pub security_id : SecurityID,
+    pub md_seq_num : u64,
+    pub seq_num : SeqNum,
+    // (price, qty, order_count)
+    pub bids : Vec<(Price, Qty, u32)>,
+    pub asks : Vec<(Price, Qty, u32)>,
+}
+
+// 行情阶段按 md_seq_num 顺序交出行情
+pub trait MarketDataSink : Send {
+    fn publish(&mut self, message : &MarketDataMessage) -> io::Result<()>;
+    // 快照与增量共用编号, 在快照之前发出的增量都已包含在内
+    fn publish_snapshot(&mut self, _snapshot : &DepthSnapshot) -> io::Result<()> {
+        Ok(())
+    }
+    // 行情阶段在输入队列处理空时以及退出前调用
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S : MarketDataSink + ?Sized> MarketDataSink for Box<S> {
+    fn publish(&mut self, message : &MarketDataMessage) -> io::Result<()> {
+        (**self).publish(message)
+    }
+    fn publish_snapshot(&mut self, snapshot : &DepthSnapshot) -> io::Result<()> {
+        (**self).publish_snapshot(snapshot)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+}
+
+impl MarketDataSink for Sender<MarketDataMessage> {
+    fn publish(&mut self, message : &MarketDataMessage) -> io::Result<()> {
+        self.send(*message).map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+// 各证券最新的行情快照, 由行情阶段更新; 可克隆后交给其它线程, 供晚加入的订阅方取用
+#[derive(Debug, Clone, Default)]
+pub struct DepthSnapshots {
+    inner : Arc<Mutex<BTreeMap<SecurityID, DepthSnapshot>>>,
+}
+
+impl DepthSnapshots {
+    pub fn new() -> DepthSnapshots {
+        DepthSnapshots::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<SecurityID, DepthSnapshot>> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn get(&self, security_id : &SecurityID) -> Option<DepthSnapshot> {
+        self.lock().get(security_id).cloned()
+    }
+
+    // 按证券代码排列
+    pub fn all(&self) -> Vec<DepthSnapshot> {
+        self.lock().values().cloned().collect()
+    }
+
+    // 同一次快照中所有证券的 md_seq_num 相同, 旧的快照不会覆盖新的
+    pub(crate) fn update(&self, snapshot : DepthSnapshot) {
+        let mut snapshots = self.lock();
+        match snapshots.get(&snapshot.security_id) {
+            Some(latest) if latest.md_seq_num > snapshot.md_seq_num => {},
+            _ => { snapshots.insert(snapshot.security_id, snapshot); },
+        }
+    }
+}
+
+// 订阅方按顺序应用价位变化得到的各证券两边的价位, 只有 qty 与 order_count
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DepthBook {
+    levels : BTreeMap<(SecurityID, Side, Price), (Qty, u32)>,
+}
+
+impl DepthBook {
+    pub fn new() -> DepthBook {
+        DepthBook::default()
+    }
+
+    // 与当前状态不符的变化 (New 已有的价位, Change 或 Delete 没有的价位) 返回 false, 说明漏掉了行情
+    pub fn apply(&mut self, update : &LevelUpdate) -> bool {
+        let key = (update.security_id, update.side, update.price);
+        match update.action {
+            LevelAction::New => self.levels.insert(key, (update.qty, update.order_count)).is_none(),
+            LevelAction::Change => self.levels.get_mut(&key).map(|level| *level = (update.qty, update.order_count)).is_some(),
+            LevelAction::Delete => self.levels.remove(&key).is_some(),
+        }
+    }
+
+    // 用快照替换该证券两边的价位, 之后应用 md_seq_num 大于快照的增量
+    pub fn load(&mut self, snapshot : &DepthSnapshot) {
+        let security_id = snapshot.security_id;
+        self.levels.retain(|(id, _, _), _| *id != security_id);
+        for (side, levels) in [(K_BUY, &snapshot.bids), (K_SELL, &snapshot.asks)] {
+            self.levels.extend(levels.iter().map(|(price, qty, order_count)| ((security_id, side, *price), (*qty, *order_count))));
+        }
+    }
+
+    // side 一边按价格优先排列的 (price, qty, order_count)
+    pub fn depth(&self, security_id : &SecurityID, side : Side) -> Vec<(Price, Qty, u32)> {
+        let levels = self.levels.range((*security_id, side, Price::MIN)..=(*security_id, side, Price::MAX))
+            .map(|((_, _, price), (qty, order_count))| (*price, *qty, *order_count));
+        if side == K_BUY {
+            levels.rev().collect()
+        }
+        else {
+            levels.collect()
+        }
+    }
+
+    pub fn securities(&self) -> Vec<SecurityID> {
+        let mut securities : Vec<SecurityID> = self.levels.keys().map(|(security_id, _, _)| *security_id).collect();
+        securities.dedup();
+        securities
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(side : Side, price : Price, qty : Qty, action : LevelAction) -> LevelUpdate {
+        LevelUpdate { security_id : to_array("SEC001"), side, price, qty, order_count : 1, action }
+    }
+
+    #[test]
+    fn depth_in_priority_order() {
+        let mut book = DepthBook::new();
+        assert!(book.apply(&update(K_BUY, 100, 10, LevelAction::New)));
+        assert!(book.apply(&update(K_BUY, 101, 5, LevelAction::New)));
+        assert!(book.apply(&update(K_SELL, 103, 7, LevelAction::New)));
+        assert!(book.apply(&update(K_SELL, 102, 1, LevelAction::New)));
+        assert!(book.apply(&update(K_BUY, 100, 4, LevelAction::Change)));
+        assert!(!book.apply(&update(K_BUY, 100, 4, LevelAction::New)));
+        assert!(!book.apply(&update(K_SELL, 99, 0, LevelAction::Delete)));
+
+        let security_id = to_array("SEC001");
+        assert_eq!(book.depth(&security_id, K_BUY), vec![(101, 5, 1), (100, 4, 1)]);
+        assert_eq!(book.depth(&security_id, K_SELL), vec![(102, 1, 1), (103, 7, 1)]);
+        assert!(book.apply(&update(K_SELL, 102, 0, LevelAction::Delete)));
+        assert_eq!(book.depth(&security_id, K_SELL), vec![(103, 7, 1)]);
+        assert_eq!(book.securities(), vec![security_id]);
+    }
+
+    #[test]
+    fn load_replaces_security() {
+        let mut book = DepthBook::new();
+        book.apply(&update(K_BUY, 100, 10, LevelAction::New));
+        book.apply(&update(K_SELL, 105, 3, LevelAction::New));
+        let other = LevelUpdate { security_id : to_array("SEC002"), ..update(K_BUY, 50, 1, LevelAction::New) };
+        book.apply(&other);
+
+        let snapshot = DepthSnapshot { security_id : to_array("SEC001"), md_seq_num : 3, seq_num : 2, bids : vec![(101, 2, 1), (99, 6, 3)], asks : vec![] };
+        book.load(&snapshot);
+        let security_id = to_array("SEC001");
+        assert_eq!(book.depth(&security_id, K_BUY), vec![(101, 2, 1), (99, 6, 3)]);
+        assert!(book.depth(&security_id, K_SELL).is_empty());
+        assert_eq!(book.depth(&to_array("SEC002"), K_BUY), vec![(50, 1, 1)]);
+
+        let snapshots = DepthSnapshots::new();
+        snapshots.update(snapshot.clone());
+        snapshots.update(DepthSnapshot { md_seq_num : 1, ..snapshot.clone() });
+        assert_eq!(snapshots.get(&security_id), Some(snapshot));
+        assert_eq!(snapshots.all().len(), 1);
+    }
+}
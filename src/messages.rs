@@ -1,10 +1,16 @@
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Deserialize};
+
 use crate::types::*;
-use std::sync::Arc;
+use crate::market_data::{MarketDataEvent, DepthSnapshot};
 
 
-#[derive(Debug)]
+// order_id 与 seq_num 由 Engin 的定序阶段分配, 调用方填写的值会被覆盖
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct NewOrder {
     pub order_id : OrderID,
+    pub seq_num : SeqNum,
     pub pbu_id : PBUID,
     pub cl_ord_id : ClOrdID,
     pub security_id : SecurityID,
@@ -20,16 +26,17 @@ impl NewOrder {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
 pub struct CancelRequest {
     pub order_id : OrderID,
+    pub seq_num : SeqNum,
     pub pbu_id : PBUID,
     pub cl_ord_id : ClOrdID,
     pub orig_cl_ord_id : ClOrdID,
     pub security_id : SecurityID,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct OrigOrderInfoForCancel {
     pub security_id : SecurityID,
     pub order_id : OrderID,
@@ -42,10 +49,98 @@ impl OrigOrderInfoForCancel {
             order_id: self.order_id.clone(), side: self.side.clone(), price: self.price.clone() }
     }
 }
+// 网关收到的委托, 也是定序后记录的输入
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Inbound {
+    NewOrder(NewOrder),
+    CancelRequest(CancelRequest),
+}
+
+impl Inbound {
+    pub fn seq_num(&self) -> SeqNum {
+        match self {
+            Inbound::NewOrder(order) => order.seq_num,
+            Inbound::CancelRequest(cancel_request) => cancel_request.seq_num,
+        }
+    }
+}
+
+// 同一输入序号时各阶段的状态. 快照标记由定序阶段发出, 和其它任务一样经过各阶段之间的队列,
+// 每个阶段处理到标记时写入自己的状态, 因此各部分都恰好包含序号不超过 seq_num 的输入
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub seq_num : SeqNum,
+    pub(crate) next_order_id : OrderID,
+    // PreProcessor 的重复委托检查状态
+    pub(crate) pre : Vec<u8>,
+    // 各证券买卖两边簿中的委托, 保留排队顺序与剩余数量
+    pub(crate) core : Vec<u8>,
+    pub(crate) exec_id : ExecID,
+    // exe 阶段部分成交且未结束的委托已成交的金额, 用于计算平均价格
+    pub(crate) notional : BTreeMap<OrderID, i128>,
+    // 行情阶段最后一条行情的编号, 恢复后接着编号
+    pub(crate) md_seq_num : u64,
+}
+
+// 处理完序号不超过 seq_num 的输入时各阶段状态的摘要. 每个摘要只取决于当时的状态, 不与之前的摘要串联,
+// 因此从快照恢复的引擎也能与原来的运行逐点比较
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StateChecksum {
+    pub seq_num : SeqNum,
+    // PreProcessor 的重复委托检查状态
+    pub pre : u64,
+    // 各证券买卖两边簿的价位与排队顺序
+    pub core : u64,
+    // exec_id 与未完成委托的成交金额
+    pub exe : u64,
+}
+
+impl StateChecksum {
+    pub fn new(seq_num : SeqNum) -> StateChecksum {
+        StateChecksum { seq_num, ..StateChecksum::default() }
+    }
+
+    // 不一致的阶段名
+    pub fn diverged_stages(&self, other : &StateChecksum) -> Vec<&'static str> {
+        [("pre", self.pre != other.pre), ("core", self.core != other.core), ("exe", self.exe != other.exe)]
+            .iter().filter(|(_, diverged)| *diverged).map(|(stage, _)| *stage).collect()
+    }
+
+    // 文本格式: 序号与三个十六进制摘要, 空格分隔
+    pub fn to_line(&self) -> String {
+        format!("{} {:016x} {:016x} {:016x}", self.seq_num, self.pre, self.core, self.exe)
+    }
+
+    pub fn parse_line(line : &str) -> Option<StateChecksum> {
+        let mut fields = line.split_whitespace();
+        let seq_num = fields.next()?.parse().ok()?;
+        let mut hash = || fields.next().and_then(|field| u64::from_str_radix(field, 16).ok());
+        let (pre, core, exe) = (hash()?, hash()?, hash()?);
+        Some(StateChecksum { seq_num, pre, core, exe })
+    }
+}
+
 #[derive(Debug)]
 pub enum PreProcessorTask {
     NewOrder(Box<NewOrder>),
     CancelRequest(Box<CancelRequest>),
+    // 快照标记, 各阶段处理到时把自己的状态写入快照后继续向下游传递
+    Snapshot(Box<Snapshot>),
+    // 摘要标记, 与快照标记一样经过各阶段, 各阶段填入自己状态的摘要
+    Checksum(Box<StateChecksum>),
+    // 行情快照标记, 定序阶段填入已编号的最后一条输入的序号, core 阶段由此生成各证券的全部价位
+    MarketDataSnapshot(SeqNum),
+}
+
+impl PreProcessorTask {
+    // 各种标记都不是输入, 返回 None
+    pub fn inbound(&self) -> Option<Inbound> {
+        match self {
+            PreProcessorTask::NewOrder(order) => Some(Inbound::NewOrder(**order)),
+            PreProcessorTask::CancelRequest(cancel_request) => Some(Inbound::CancelRequest(**cancel_request)),
+            PreProcessorTask::Snapshot(_) | PreProcessorTask::Checksum(_) | PreProcessorTask::MarketDataSnapshot(_) => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -53,7 +148,10 @@ pub enum RcProcessorTask {
     NewOrder(Box<NewOrder>),
     NewOrderRejected((CancelReasonCode, Box<NewOrder>)),
     CancelRequest(OrigOrderInfoForCancel, Box<CancelRequest>),
-    CancelRequestRejected((CancelReasonCode, Box<CancelRequest>))
+    CancelRequestRejected((CancelReasonCode, Box<CancelRequest>)),
+    Snapshot(Box<Snapshot>),
+    Checksum(Box<StateChecksum>),
+    MarketDataSnapshot(SeqNum),
 }
 
 #[derive(Debug)]
@@ -64,15 +162,18 @@ pub enum CoreProcessorTask {
     NewOrder(Box<NewOrder>, Box<RcResult>),
     NewOrderRejected((CancelReasonCode, Box<NewOrder>)),
     CancelRequest(OrigOrderInfoForCancel, Box<CancelRequest>),
-    CancelRequestRejected((CancelReasonCode, Box<CancelRequest>))
+    CancelRequestRejected((CancelReasonCode, Box<CancelRequest>)),
+    Snapshot(Box<Snapshot>),
+    Checksum(Box<StateChecksum>),
+    MarketDataSnapshot(SeqNum),
 }
 
  
 #[derive(Debug)]
 pub struct OrderMatchedInfo {
-    pub order1 : Arc<NewOrder>,
+    pub order1 : NewOrder,
     pub leaves_qty1 : Qty,
-    pub order2 : Arc<NewOrder>,
+    pub order2 : NewOrder,
     pub leaves_qty2 : Qty,
     pub last_px : Price,
     pub last_qty : Qty
@@ -80,13 +181,82 @@ pub struct OrderMatchedInfo {
 
 #[derive(Debug)]
 pub enum ExecutionTask {
-    NewOrderAccepted(Arc<NewOrder>),
+    NewOrderAccepted(Box<NewOrder>),
     NewOrderRejected((CancelReasonCode, Box<NewOrder>)),
-    CancelRequestAccepted(Qty/*leaves_qty */, Box<CancelRequest>, Arc<NewOrder>),
+    CancelRequestAccepted(Qty/*leaves_qty */, Box<CancelRequest>, NewOrder),
     CancelRequestRejected(CancelReasonCode, Box<CancelRequest>),
-    NewoOrderMatched(OrderMatchedInfo)
+    NewoOrderMatched(OrderMatchedInfo),
+    Snapshot(Box<Snapshot>),
+    Checksum(Box<StateChecksum>),
+}
+
+// core 阶段处理一条输入后簿上的变化, 交给行情阶段
+#[derive(Debug)]
+pub struct MarketDataTask {
+    pub order_id : OrderID,
+    pub seq_num : SeqNum,
+    pub events : Vec<MarketDataEvent>,
+    // 行情快照标记生成的各证券价位, md_seq_num 由行情阶段填入
+    pub snapshots : Vec<DepthSnapshot>,
+}
+
+// 用于定位出错时正在处理的消息
+pub trait Traceable {
+    fn order_id(&self) -> OrderID;
+}
+
+impl Traceable for PreProcessorTask {
+    fn order_id(&self) -> OrderID {
+        match self {
+            PreProcessorTask::NewOrder(order) => order.order_id,
+            PreProcessorTask::CancelRequest(cancel_request) => cancel_request.order_id,
+            PreProcessorTask::Snapshot(_) | PreProcessorTask::Checksum(_) | PreProcessorTask::MarketDataSnapshot(_) => 0,
+        }
+    }
+}
+
+impl Traceable for RcProcessorTask {
+    fn order_id(&self) -> OrderID {
+        match self {
+            RcProcessorTask::NewOrder(order) => order.order_id,
+            RcProcessorTask::NewOrderRejected((_, order)) => order.order_id,
+            RcProcessorTask::CancelRequest(_, cancel_request) => cancel_request.order_id,
+            RcProcessorTask::CancelRequestRejected((_, cancel_request)) => cancel_request.order_id,
+            RcProcessorTask::Snapshot(_) | RcProcessorTask::Checksum(_) | RcProcessorTask::MarketDataSnapshot(_) => 0,
+        }
+    }
+}
+
+impl Traceable for CoreProcessorTask {
+    fn order_id(&self) -> OrderID {
+        match self {
+            CoreProcessorTask::NewOrder(order, _) => order.order_id,
+            CoreProcessorTask::NewOrderRejected((_, order)) => order.order_id,
+            CoreProcessorTask::CancelRequest(_, cancel_request) => cancel_request.order_id,
+            CoreProcessorTask::CancelRequestRejected((_, cancel_request)) => cancel_request.order_id,
+            CoreProcessorTask::Snapshot(_) | CoreProcessorTask::Checksum(_) | CoreProcessorTask::MarketDataSnapshot(_) => 0,
+        }
+    }
 }
 
+impl Traceable for ExecutionTask {
+    fn order_id(&self) -> OrderID {
+        match self {
+            ExecutionTask::NewOrderAccepted(order) => order.order_id,
+            ExecutionTask::NewOrderRejected((_, order)) => order.order_id,
+            ExecutionTask::CancelRequestAccepted(_, cancel_request, _) => cancel_request.order_id,
+            ExecutionTask::CancelRequestRejected(_, cancel_request) => cancel_request.order_id,
+            ExecutionTask::NewoOrderMatched(info) => info.order1.order_id,
+            ExecutionTask::Snapshot(_) | ExecutionTask::Checksum(_) => 0,
+        }
+    }
+}
+
+impl Traceable for MarketDataTask {
+    fn order_id(&self) -> OrderID {
+        self.order_id
+    }
+}
 
 #[cfg(test)]
     use std::rc::Rc;
@@ -104,6 +274,7 @@ pub enum ExecutionTask {
 
             Box::new(NewOrder { 
                 order_id: self.order_id,
+                seq_num: 0,
                 pbu_id: to_array("PBU001"), 
                 cl_ord_id: to_array(&self.order_id.to_string()),
                 security_id: to_array(""), 
@@ -115,6 +286,7 @@ pub enum ExecutionTask {
         pub fn get_cancel_request(&mut self, orig_order : &Box<NewOrder>) -> Box<CancelRequest> {
             self.order_id += 1;
             Box::new(CancelRequest { order_id: self.order_id, 
+                seq_num: 0,
                 pbu_id: orig_order.pbu_id.clone(), 
                 cl_ord_id: to_array(&self.order_id.to_string()),
                 orig_cl_ord_id: orig_order.cl_ord_id.clone(), 
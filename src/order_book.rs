@@ -1,35 +1,75 @@
 mod price_node;
+mod price_ladder;
 pub mod auction_order;
 
 use std::collections::{BTreeMap};
-use std::collections::btree_map::Iter;
-use std::collections::btree_map::IterMut;
+use std::collections::btree_map;
 use price_node::*;
+use price_ladder::*;
 use auction_order::*;
 use std::rc::Rc;
-use std::cell::RefCell;
+
+use serde::{Serialize, Deserialize};
 
 use crate::types::{Qty, Price};
 
+// 价位的存储方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookBackend {
+    // 有序树, 适用于任意价格
+    Tree,
+    // 按最小价位变动单位寻址的数组, 只接受 [min_price, max_price] 内且对齐 tick 的价格
+    Ladder { min_price : Price, max_price : Price, tick : Price },
+}
+
+// 簿中的一个价位, 委托按时间优先排列并带有剩余数量; 不含 Rc, 可以跨线程传递、序列化和比较
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BookLevel<T> {
+    pub price : Price,
+    pub orders : Vec<(T, Qty)>,
+}
+
+enum Levels<Order> {
+    Tree(BTreeMap<i64, PriceNode<Order>>),
+    Ladder(PriceLadder<Order>),
+}
+
 pub struct PriceOrderBook<Order> {
     price_multiplier : i64,
-    nodes : BTreeMap<i64, PriceNode<Order>>,
+    levels : Levels<Order>,
 }
 
 impl<Order : AuctionOrder> PriceOrderBook<Order> {
     pub fn create_high_price_priority_order_book() -> PriceOrderBook<Order> {
-        PriceOrderBook {
-            price_multiplier : -1,
-            nodes : BTreeMap::new()
-        }
+        PriceOrderBook::create_high_price_priority_order_book_with_backend(BookBackend::Tree)
     }
 
     pub fn create_low_price_priority_order_book() -> PriceOrderBook<Order> {
-        PriceOrderBook {
-            price_multiplier : 1,
-            nodes : BTreeMap::new()
-            
-        }
+        PriceOrderBook::create_low_price_priority_order_book_with_backend(BookBackend::Tree)
+    }
+
+    pub fn create_high_price_priority_order_book_with_backend(backend : BookBackend) -> PriceOrderBook<Order> {
+        PriceOrderBook::with_backend(-1, backend)
+    }
+
+    pub fn create_low_price_priority_order_book_with_backend(backend : BookBackend) -> PriceOrderBook<Order> {
+        PriceOrderBook::with_backend(1, backend)
+    }
+
+    fn with_backend(price_multiplier : i64, backend : BookBackend) -> PriceOrderBook<Order> {
+        let levels = match backend {
+            BookBackend::Tree => Levels::Tree(BTreeMap::new()),
+            BookBackend::Ladder { min_price, max_price, tick } => {
+                let (min_key, max_key) = if price_multiplier > 0 {
+                    (min_price, max_price)
+                }
+                else {
+                    (-max_price, -min_price)
+                };
+                Levels::Ladder(PriceLadder::new(min_key, max_key, tick))
+            }
+        };
+        PriceOrderBook { price_multiplier, levels }
     }
 
     pub fn insert_order(&mut self, order : Rc<Order>) {
@@ -38,28 +78,40 @@ impl<Order : AuctionOrder> PriceOrderBook<Order> {
     
     pub fn insert_order_with_leaves_qty(&mut self, leaves_qty : Qty, order : Rc<Order>) {
         let tmp_price = order.price() * self.price_multiplier;
-        match self.nodes.get_mut(&tmp_price) {
-            Some(node ) => node.append_order_with_leaves_qty(leaves_qty, order),
-            None => {
-                let mut node = PriceNode::new();
-                let price = order.price();
-                node.append_order_with_leaves_qty(leaves_qty, order);
-                self.nodes.insert(price * self.price_multiplier, node);
+        match &mut self.levels {
+            Levels::Tree(nodes) => nodes.entry(tmp_price).or_insert_with(PriceNode::new)
+                .append_order_with_leaves_qty(leaves_qty, order),
+            Levels::Ladder(ladder) => {
+                let index = match ladder.index(tmp_price) {
+                    Some(index) => index,
+                    None => panic!("Price {} is outside of the price ladder!", order.price()),
+                };
+                ladder.node_at(index).append_order_with_leaves_qty(leaves_qty, order);
+                ladder.mark(index);
             }
         }
-
     }
 
     pub fn remove_order(&mut self, price : i64, order_id : u128) -> Option<ConsumedOrder<Order>> {
-        if let Some(node) = self.nodes.get_mut(&(price * self.price_multiplier)) {
-            let order = node.remove_order(order_id);
-            if node.total() == 0 {
-                self.nodes.remove(&(price * self.price_multiplier));
+        let key = price * self.price_multiplier;
+        match &mut self.levels {
+            Levels::Tree(nodes) => {
+                let node = nodes.get_mut(&key)?;
+                let order = node.remove_order(order_id);
+                if node.is_empty() {
+                    nodes.remove(&key);
+                }
+                order
+            },
+            Levels::Ladder(ladder) => {
+                let index = ladder.index(key)?;
+                let node = ladder.node_at(index);
+                let order = node.remove_order(order_id);
+                if order.is_some() && node.is_empty() {
+                    ladder.clear(index);
+                }
+                order
             }
-            order
-        }
-        else {
-            None
         }
     }
 
@@ -67,48 +119,107 @@ impl<Order : AuctionOrder> PriceOrderBook<Order> {
         let mut orders : Vec<ConsumedOrder<Order>> = Vec::new();
 
         let mut left_qty = qty;
+        let limit_key = limit_price * self.price_multiplier;
 
-        loop {
-            if let Some(mut entry)= self.nodes.first_entry() {
-                if *entry.key() <= limit_price * self.price_multiplier {
+        match &mut self.levels {
+            Levels::Tree(nodes) => {
+                while let Some(mut entry) = nodes.first_entry() {
+                    if *entry.key() > limit_key || left_qty == 0 {
+                        break;
+                    }
                     let node = entry.get_mut();
-                    if node.total() < left_qty {
-                        left_qty -= node.total();
-                        let mut vec = node.consume_order(node.total());
-                        orders.append(&mut vec);
-                        self.nodes.pop_first();
+                    left_qty -= consume_node(node, left_qty, &mut orders);
+                    if node.is_empty() {
+                        entry.remove();
                     }
-                    else {
-                        let mut vec = node.consume_order(left_qty);
-                        orders.append(&mut vec);
-                        left_qty = 0;
+                }
+            },
+            Levels::Ladder(ladder) => {
+                while let Some(index) = ladder.best() {
+                    if ladder.key(index) > limit_key || left_qty == 0 {
                         break;
                     }
+                    let node = ladder.node_at(index);
+                    left_qty -= consume_node(node, left_qty, &mut orders);
+                    if node.is_empty() {
+                        ladder.clear(index);
+                    }
                 }
-                else {
-                    break;
-                }
-            }
-            else {
-                break;
             }
         }
         (left_qty, orders)
 
     }
 
+    // 按优先顺序导出所有价位, convert 把簿中的委托转为普通数据
+    pub fn export<T, F>(&self, mut convert : F) -> Vec<BookLevel<T>> where F : FnMut(&Order) -> T {
+        self.level_iter().filter(|(_, node)| !node.is_empty()).map(|(key, node)| BookLevel {
+            price : key * self.price_multiplier,
+            orders : node.order_iter().map(|order| (convert(&order.orig_order()), order.leaves_qty())).collect(),
+        }).collect()
+    }
+
+    // 按 export 的顺序追加到簿中, 导入空簿后与原簿的价格优先、时间优先顺序完全相同
+    pub fn import<T, F>(&mut self, levels : Vec<BookLevel<T>>, mut convert : F) where F : FnMut(T) -> Order {
+        for level in levels {
+            for (order, leaves_qty) in level.orders {
+                self.insert_order_with_leaves_qty(leaves_qty, Rc::new(convert(order)));
+            }
+        }
+    }
+
+    // price 价位上的总量与委托数, 没有委托时为 None
+    pub fn level(&self, price : Price) -> Option<(Qty, usize)> {
+        let key = price * self.price_multiplier;
+        let node = match &self.levels {
+            Levels::Tree(nodes) => nodes.get(&key)?,
+            Levels::Ladder(ladder) => ladder.node(ladder.index(key)?),
+        };
+        if node.is_empty() { None } else { Some((node.total(), node.len())) }
+    }
+
     pub fn price_iter(&self) -> BookPriceIter<'_, Order> {
-        BookPriceIter { iter: (self.nodes.iter()), price_multiplier : self.price_multiplier }
+        BookPriceIter { iter: self.level_iter(), price_multiplier : self.price_multiplier }
     }
 
-    pub fn order_iter_mut(&mut self) -> BookOrderIterMut<'_, Order> {
-        BookOrderIterMut::new(self)
+    pub fn order_iter(&self) -> BookOrderIter<'_, Order> {
+        BookOrderIter { price_iter: self.level_iter(), order_iter : None }
+    }
 
+    fn level_iter(&self) -> LevelIter<'_, Order> {
+        match &self.levels {
+            Levels::Tree(nodes) => LevelIter::Tree(nodes.iter()),
+            Levels::Ladder(ladder) => LevelIter::Ladder(ladder.iter()),
+        }
+    }
+}
+
+// 返回本档位成交的数量
+fn consume_node<Order : AuctionOrder>(node : &mut PriceNode<Order>, qty : Qty, orders : &mut Vec<ConsumedOrder<Order>>) -> Qty {
+    let qty = qty.min(node.total());
+    let mut vec = node.consume_order(qty);
+    orders.append(&mut vec);
+    qty
+}
+
+enum LevelIter<'a, Order> {
+    Tree(btree_map::Iter<'a, i64, PriceNode<Order>>),
+    Ladder(LadderIter<'a, Order>),
+}
+
+impl <'a, Order : AuctionOrder> Iterator for LevelIter<'a, Order> {
+    type Item = (i64, &'a PriceNode<Order>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            LevelIter::Tree(iter) => iter.next().map(|(key, node)| (*key, node)),
+            LevelIter::Ladder(iter) => iter.next(),
+        }
     }
 }
 
 pub struct BookPriceIter<'a, Order> {
-    iter : Iter<'a, i64, PriceNode<Order>>,
+    iter : LevelIter<'a, Order>,
     price_multiplier : i64
 }
 
@@ -116,51 +227,29 @@ impl <Order : AuctionOrder> BookPriceIter<'_, Order> {
     pub fn next(&mut self) -> Option<(i64, u64)> {
         while let Some(node) = self.iter.next() {
             if node.1.total() != 0 {
-                return Some((*(node.0) * self.price_multiplier, node.1.total()))
+                return Some((node.0 * self.price_multiplier, node.1.total()))
             }
         }
         None
     }
 }
 
-pub struct BookOrderIterMut<'a, Order> {
-    price_iter : IterMut<'a, i64, PriceNode<Order>>,
-    order_iter : Option<price_node::OrderIterMut<'a, Order>>
+// 按价格优先、时间优先的顺序遍历簿中所有委托
+pub struct BookOrderIter<'a, Order> {
+    price_iter : LevelIter<'a, Order>,
+    order_iter : Option<price_node::OrderIter<'a, Order>>
 }
 
-impl <Order : AuctionOrder> BookOrderIterMut<'_, Order> {
-    pub fn new(order_book : &mut PriceOrderBook<Order>) -> BookOrderIterMut<Order>{
-        let mut tmp = BookOrderIterMut { price_iter: order_book.nodes.iter_mut() , order_iter : None};
+impl <'a, Order : AuctionOrder> Iterator for BookOrderIter<'a, Order> {
+    type Item = &'a OrderWithStatus<Order>;
 
-        while let Some(node) = tmp.price_iter.next() {
-            if node.1.total() != 0 {
-                tmp.order_iter = Some(node.1.order_iter_mut());
-                break;
-            }
-        }
-        tmp
-    }
-
-    pub fn next(&mut self) -> Option<Rc<RefCell<OrderWithStatus<Order>>>> {
-
-        let has_next = self.order_iter.as_mut().unwrap().has_next();
-
-        if has_next == false {
-            self.order_iter = None;
-            while let Some(node) = self.price_iter.next() {
-                if node.1.total() != 0 {
-                    self.order_iter = Some(node.1.order_iter_mut());
-                    break;
-                }
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(order) = self.order_iter.as_mut().and_then(|iter| iter.next()) {
+                return Some(order);
             }
+            self.order_iter = Some(self.price_iter.next()?.1.order_iter());
         }
-
-        if let None = self.order_iter {
-            return None
-        }
-
-        self.order_iter.as_mut().unwrap().next()
-
     }
 }
 
@@ -169,7 +258,7 @@ impl <Order : AuctionOrder> BookOrderIterMut<'_, Order> {
 mod tests {
 
     use super::*;
-    use crate::{types::{Price, Qty}};
+    use crate::{types::{Price, Qty, OrderID}};
 
     #[test]
     fn remove_order() {
@@ -247,9 +336,9 @@ mod tests {
         assert_eq!(None, iter.next());
     }
 
-    fn assert_order(order : Rc<RefCell<OrderWithStatus<TestOrder>>>, price : Price, qty : Qty) {
-        assert_eq!(order.borrow().orig_order().price(), price);
-        assert_eq!(order.borrow().leaves_qty(), qty);
+    fn assert_order(order : &OrderWithStatus<TestOrder>, price : Price, qty : Qty) {
+        assert_eq!(order.orig_order().price(), price);
+        assert_eq!(order.leaves_qty(), qty);
     }
 
     #[test]
@@ -272,7 +361,7 @@ mod tests {
         ob.remove_order(100, 1);
         ob.remove_order(102, 5);
 
-        let mut iter = ob.order_iter_mut();
+        let mut iter = ob.order_iter();
 
         let order = iter.next().unwrap();
         assert_order(order, 100, 20);
@@ -356,4 +445,86 @@ mod tests {
         assert_eq!(consumed.len(), 0);
 
     }
+
+    #[test]
+    fn traverse_high_price_priority_ladder() {
+        let mut ob = PriceOrderBook::create_high_price_priority_order_book_with_backend(
+            BookBackend::Ladder { min_price : 90, max_price : 110, tick : 1 });
+        let orders_info = [
+            (99, 1, Some(0)),
+            (100, 20, None),
+            (101, 20, None),
+            (101, 20, None),
+            (102, 2, Some(4)),
+            (105, 20, None),
+            (106, 3, Some(6))
+        ];
+        let mut gen = TestOrderGen::new();
+        gen.work(&orders_info).iter().for_each(|order| ob.insert_order(order.clone()));
+
+        ob.remove_order(99,0);
+        ob.remove_order(102,4);
+        ob.remove_order(106,6);
+        assert!(ob.remove_order(106,6).is_none());
+        assert!(ob.remove_order(120,6).is_none());
+
+        let mut iter = ob.price_iter();
+
+        assert_eq!(Some((105, 20)), iter.next());
+        assert_eq!(Some((101, 40)), iter.next());
+        assert_eq!(Some((100, 20)), iter.next());
+        assert_eq!(None, iter.next());
+
+        let (leaves_qty, consumed) = ob.consume_order(50, 101);
+        assert_eq!(leaves_qty, 0);
+        assert_eq!(consumed.len(), 3);
+        assert_eq!(consumed[0].orig_order().price(), 105);
+        assert_eq!(consumed[2].orig_order().price(), 101);
+        assert_eq!(consumed[2].consumed_qty(), 10);
+        assert_eq!(consumed[2].leaves_qty(), 10);
+
+        let prices : Vec<Price> = ob.order_iter().map(|order| order.orig_order().price()).collect();
+        assert_eq!(prices, vec![101, 100]);
+    }
+
+    #[test]
+    fn export_levels() {
+        let orders_info = [
+            (101, 20, None),
+            (100, 10, None),
+            (101, 30, None),
+            (102, 5, None),
+        ];
+        let mut tree = PriceOrderBook::create_high_price_priority_order_book();
+        let mut ladder = PriceOrderBook::create_high_price_priority_order_book_with_backend(
+            BookBackend::Ladder { min_price : 90, max_price : 110, tick : 1 });
+        let orders = TestOrderGen::new().work(&orders_info);
+        orders.iter().for_each(|order| {
+            tree.insert_order(order.clone());
+            ladder.insert_order(order.clone());
+        });
+        tree.consume_order(15, 101);
+        ladder.consume_order(15, 101);
+
+        let levels = tree.export(|order| order.order_id());
+        assert_eq!(levels, ladder.export(|order| order.order_id()));
+        let ids : Vec<OrderID> = orders.iter().map(|order| order.order_id()).collect();
+        assert_eq!(levels, vec![
+            BookLevel { price : 101, orders : vec![(ids[0], 10), (ids[2], 30)] },
+            BookLevel { price : 100, orders : vec![(ids[1], 10)] },
+        ]);
+        for book in [&tree, &ladder] {
+            assert_eq!((book.level(101), book.level(100)), (Some((40, 2)), Some((10, 1))));
+            assert_eq!((book.level(102), book.level(200)), (None, None));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn insert_outside_of_ladder() {
+        let mut ob = PriceOrderBook::create_low_price_priority_order_book_with_backend(
+            BookBackend::Ladder { min_price : 100, max_price : 200, tick : 5 });
+        let mut gen = TestOrderGen::new();
+        gen.work(&[(102, 1, None)]).iter().for_each(|order| ob.insert_order(order.clone()));
+    }
 }
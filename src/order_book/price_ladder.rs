@@ -0,0 +1,141 @@
+use crate::order_book::price_node::PriceNode;
+use crate::order_book::AuctionOrder;
+
+const NIL : usize = usize::MAX;
+
+// 以价格档位下标直接寻址的价位数组, 位图记录非空档位, 用于涨跌停范围固定的证券
+// key 与 BTreeMap 后端一致, 为 price * price_multiplier, 下标越小越优先
+pub struct PriceLadder<Order> {
+    min_key : i64,
+    tick : i64,
+    nodes : Vec<PriceNode<Order>>,
+    bitmap : Vec<u64>,
+    // 最优非空档位的下标, 没有时为 NIL
+    best : usize,
+}
+
+impl<Order : AuctionOrder> PriceLadder<Order> {
+    pub fn new(min_key : i64, max_key : i64, tick : i64) -> PriceLadder<Order> {
+        assert!(tick > 0 && max_key >= min_key, "invalid price ladder range");
+        let len = ((max_key - min_key) / tick + 1) as usize;
+        PriceLadder {
+            min_key,
+            tick,
+            nodes : (0..len).map(|_| PriceNode::new()).collect(),
+            bitmap : vec![0; len.div_ceil(64)],
+            best : NIL,
+        }
+    }
+
+    // 不在范围内或不在最小价位变动单位上的 key 返回 None
+    pub fn index(&self, key : i64) -> Option<usize> {
+        let offset = key - self.min_key;
+        if offset < 0 || offset % self.tick != 0 {
+            return None;
+        }
+        let index = (offset / self.tick) as usize;
+        if index < self.nodes.len() { Some(index) } else { None }
+    }
+
+    pub fn key(&self, index : usize) -> i64 {
+        self.min_key + index as i64 * self.tick
+    }
+
+    // 向某档位加入委托后调用
+    pub fn mark(&mut self, index : usize) {
+        self.bitmap[index / 64] |= 1 << (index % 64);
+        if self.best == NIL || index < self.best {
+            self.best = index;
+        }
+    }
+
+    // 某档位被取空后调用
+    pub fn clear(&mut self, index : usize) {
+        self.bitmap[index / 64] &= !(1 << (index % 64));
+        if index == self.best {
+            self.best = self.next_from(index + 1).unwrap_or(NIL);
+        }
+    }
+
+    pub fn best(&self) -> Option<usize> {
+        if self.best == NIL { None } else { Some(self.best) }
+    }
+
+    pub fn node(&self, index : usize) -> &PriceNode<Order> {
+        &self.nodes[index]
+    }
+
+    pub fn node_at(&mut self, index : usize) -> &mut PriceNode<Order> {
+        &mut self.nodes[index]
+    }
+
+    // 从 index 开始(含)查找下一个非空档位
+    pub fn next_from(&self, index : usize) -> Option<usize> {
+        let mut word = index / 64;
+        if word >= self.bitmap.len() {
+            return None;
+        }
+        let mut bits = self.bitmap[word] & (!0u64 << (index % 64));
+        loop {
+            if bits != 0 {
+                return Some(word * 64 + bits.trailing_zeros() as usize);
+            }
+            word += 1;
+            if word >= self.bitmap.len() {
+                return None;
+            }
+            bits = self.bitmap[word];
+        }
+    }
+
+    pub fn iter(&self) -> LadderIter<'_, Order> {
+        LadderIter { ladder : self, next : self.best().unwrap_or(self.nodes.len()) }
+    }
+}
+
+pub struct LadderIter<'a, Order> {
+    ladder : &'a PriceLadder<Order>,
+    next : usize,
+}
+
+impl <'a, Order : AuctionOrder> Iterator for LadderIter<'a, Order> {
+    type Item = (i64, &'a PriceNode<Order>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.ladder.next_from(self.next)?;
+        self.next = index + 1;
+        Some((self.ladder.key(index), self.ladder.node(index)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order_book::auction_order::*;
+
+    #[test]
+    fn find_best_level() {
+        let mut ladder : PriceLadder<TestOrder> = PriceLadder::new(-300, -100, 2);
+        assert_eq!(ladder.index(-301), None);
+        assert_eq!(ladder.index(-99), None);
+        assert_eq!(ladder.index(-101), None);
+        assert_eq!(ladder.index(-300), Some(0));
+        assert_eq!(ladder.index(-100), Some(100));
+        assert_eq!(ladder.best(), None);
+
+        ladder.mark(100);
+        ladder.mark(70);
+        ladder.mark(3);
+        assert_eq!(ladder.best(), Some(3));
+        let keys : Vec<i64> = ladder.iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, vec![-294, -160, -100]);
+
+        ladder.clear(3);
+        assert_eq!(ladder.best(), Some(70));
+        ladder.clear(100);
+        assert_eq!(ladder.best(), Some(70));
+        ladder.clear(70);
+        assert_eq!(ladder.best(), None);
+        assert_eq!(ladder.iter().count(), 0);
+    }
+}
@@ -1,12 +1,11 @@
-
-use std::collections::{LinkedList, linked_list::IterMut};
-use std::collections::BTreeMap;
-use std::cell::RefCell;
+use std::collections::HashMap;
 use std::rc::Rc;
 use crate::types::{Qty, OrderID};
 use crate::order_book::AuctionOrder;
 use crate::order_book::ConsumedOrder;
 
+const NIL : usize = usize::MAX;
+
 pub struct OrderWithStatus<Order> {
     leaves_qty : Qty,
     orig_order : Rc<Order>
@@ -21,16 +20,26 @@ impl <Order> OrderWithStatus<Order> {
     }
 }
 
+enum Slot<Order> {
+    Occupied { order : OrderWithStatus<Order>, prev : usize, next : usize },
+    // 空闲槽位串成单链表, 新委托优先复用
+    Vacant { next_free : usize },
+}
+
+// 同一价位的委托按时间优先排成双向链表, 链表节点存放在 slots 中, 以下标互相引用
 pub struct PriceNode<Order> {
     total : Qty,
-    zero_orders : usize,
-    order_list : LinkedList<Rc<RefCell<OrderWithStatus<Order>>>>,
-    order_map : BTreeMap<u128, Rc<RefCell<OrderWithStatus<Order>>>>
+    len : usize,
+    head : usize,
+    tail : usize,
+    free : usize,
+    slots : Vec<Slot<Order>>,
+    order_map : HashMap<OrderID, usize>
 }
 
 impl<Order : AuctionOrder> PriceNode<Order> {
     pub fn new() -> PriceNode<Order> {
-        PriceNode { total: 0, zero_orders: 0, order_list: LinkedList::new(), order_map : BTreeMap::new() }
+        PriceNode { total: 0, len: 0, head: NIL, tail: NIL, free: NIL, slots: Vec::new(), order_map : HashMap::new() }
     }
     pub fn append_order(&mut self, order : Rc<Order>) {
         self.append_order_with_leaves_qty(order.qty(), order);
@@ -42,53 +51,57 @@ impl<Order : AuctionOrder> PriceNode<Order> {
 
         self.total += leaves_qty;
 
-        let tmp_order = Rc::new(RefCell::new(OrderWithStatus{ leaves_qty : leaves_qty, orig_order : order.clone()}));
-        self.order_list.push_back(tmp_order.clone());
-        self.order_map.insert(order.order_id(), tmp_order.clone());
-
-    }
-    pub fn remove_order(&mut self, order_id : OrderID) -> Option<ConsumedOrder<Order>>
-    {
-        let order = self.order_map.remove(&order_id);
-        if let Some(tmp_order) = order {
-            let qty = tmp_order.borrow().leaves_qty;
-            tmp_order.borrow_mut().leaves_qty = 0;
-            self.total -= qty;
-            self.zero_orders += 1;
+        let order_id = order.order_id();
+        let slot = Slot::Occupied { order : OrderWithStatus { leaves_qty, orig_order : order }, prev : self.tail, next : NIL };
+        let index = if self.free != NIL {
+            let index = self.free;
+            if let Slot::Vacant { next_free } = self.slots[index] {
+                self.free = next_free;
+            }
+            self.slots[index] = slot;
+            index
+        }
+        else {
+            self.slots.push(slot);
+            self.slots.len() - 1
+        };
 
-            Some(ConsumedOrder {consumed_qty : qty, leaves_qty : 0, orig_order : tmp_order.borrow().orig_order()})
+        if self.tail != NIL {
+            self.set_next(self.tail, index);
         }
         else {
-            None
+            self.head = index;
         }
+        self.tail = index;
+        self.len += 1;
+        self.order_map.insert(order_id, index);
+    }
+    pub fn remove_order(&mut self, order_id : OrderID) -> Option<ConsumedOrder<Order>>
+    {
+        let index = self.order_map.remove(&order_id)?;
+        let order = self.unlink(index);
+        self.total -= order.leaves_qty;
+        Some(ConsumedOrder {consumed_qty : order.leaves_qty, leaves_qty : 0, orig_order : order.orig_order})
     }
     pub fn consume_order(&mut self, qty : Qty) -> Vec<ConsumedOrder<Order>> {
         let mut orders = Vec::new();
         let mut left_qty = qty;
-        loop {
-            if let Some(order) = self.order_list.front() {
-                if order.borrow().leaves_qty() == 0 {
-                    self.order_map.remove(&order.borrow().orig_order().order_id());
-                    self.order_list.pop_front();
-                    continue;
-                }
-                if left_qty > order.borrow().leaves_qty() {
-                    orders.push(ConsumedOrder { consumed_qty: order.borrow().leaves_qty(), leaves_qty : 0, orig_order: order.borrow().orig_order().clone() });
-                    left_qty -= order.borrow().leaves_qty();
-                    self.total -= order.borrow().leaves_qty();
-                    self.order_map.remove(&order.borrow().orig_order().order_id());
-                    self.order_list.pop_front();
-                    
-                }
-                else {
-                    order.borrow_mut().leaves_qty -= left_qty;
-                    orders.push(ConsumedOrder { consumed_qty: left_qty, leaves_qty : order.borrow().leaves_qty, orig_order: order.borrow().orig_order().clone() });
-                    self.total -= left_qty;
-                    break;
-                }
+        while left_qty > 0 && self.head != NIL {
+            let head = self.head;
+            let leaves_qty = self.get(head).leaves_qty;
+            if left_qty >= leaves_qty {
+                let order = self.unlink(head);
+                self.order_map.remove(&order.orig_order.order_id());
+                orders.push(ConsumedOrder { consumed_qty: leaves_qty, leaves_qty : 0, orig_order: order.orig_order });
+                left_qty -= leaves_qty;
+                self.total -= leaves_qty;
             }
             else {
-                break;
+                let order = self.get_mut(head);
+                order.leaves_qty -= left_qty;
+                orders.push(ConsumedOrder { consumed_qty: left_qty, leaves_qty : order.leaves_qty, orig_order: order.orig_order.clone() });
+                self.total -= left_qty;
+                left_qty = 0;
             }
         }
 
@@ -98,69 +111,84 @@ impl<Order : AuctionOrder> PriceNode<Order> {
     pub fn total(&self) -> Qty{
         self.total
     }
-    fn remove_front_and_tail_zero_order(&mut self) {
-        loop {
-            match self.order_list.front() {
-                Some(order) =>
-                {
-                    if order.borrow().leaves_qty == 0 {
-                        self.order_map.remove(&order.borrow().orig_order().order_id());
-                        self.order_list.pop_front();
-                        self.zero_orders -= 1;
-                    }
-                    else {
-                        break;
-                    }
-                },
-                None => ()
-            }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    // 已分配的槽位数, 包括空闲槽位
+    #[cfg(test)]
+    pub fn slot_count(&self) -> usize {
+        self.slots.len()
+    }
+    pub fn order_iter(&self) -> OrderIter<'_, Order> {
+        OrderIter { node : self, curr : self.head }
+    }
+
+    fn get(&self, index : usize) -> &OrderWithStatus<Order> {
+        match &self.slots[index] {
+            Slot::Occupied { order, .. } => order,
+            Slot::Vacant { .. } => unreachable!("vacant slot in order queue"),
         }
-        loop {
-            match self.order_list.back() {
-                Some(order) =>
-                {
-                    if order.borrow().leaves_qty == 0 {
-                        self.order_map.remove(&order.borrow().orig_order().order_id());
-                        self.order_list.pop_back();
-                        self.zero_orders -= 1;
-                    }
-                    else {
-                        break;
-                    }
-                },
-                None => ()
-            }
+    }
+    fn get_mut(&mut self, index : usize) -> &mut OrderWithStatus<Order> {
+        match &mut self.slots[index] {
+            Slot::Occupied { order, .. } => order,
+            Slot::Vacant { .. } => unreachable!("vacant slot in order queue"),
         }
     }
-    pub fn order_iter_mut(&mut self) -> OrderIterMut<'_, Order> {
-        OrderIterMut::new(self)
+    fn links(&self, index : usize) -> (usize, usize) {
+        match self.slots[index] {
+            Slot::Occupied { prev, next, .. } => (prev, next),
+            Slot::Vacant { .. } => unreachable!("vacant slot in order queue"),
+        }
+    }
+    fn set_prev(&mut self, index : usize, value : usize) {
+        if let Slot::Occupied { prev, .. } = &mut self.slots[index] {
+            *prev = value;
+        }
+    }
+    fn set_next(&mut self, index : usize, value : usize) {
+        if let Slot::Occupied { next, .. } = &mut self.slots[index] {
+            *next = value;
+        }
+    }
+    // 从链表中摘除并立即回收槽位
+    fn unlink(&mut self, index : usize) -> OrderWithStatus<Order> {
+        let (prev, next) = self.links(index);
+        if prev != NIL { self.set_next(prev, next) } else { self.head = next }
+        if next != NIL { self.set_prev(next, prev) } else { self.tail = prev }
+        self.len -= 1;
+
+        let slot = std::mem::replace(&mut self.slots[index], Slot::Vacant { next_free : self.free });
+        self.free = index;
+        match slot {
+            Slot::Occupied { order, .. } => order,
+            Slot::Vacant { .. } => unreachable!("vacant slot in order queue"),
+        }
     }
 }
 
-pub struct OrderIterMut<'a, Order> {
-    iter : IterMut<'a, Rc<RefCell<OrderWithStatus<Order>>>>,
-    len : usize,   //list 中的元素个数
-    curr : usize  // 当前遍历到第几个
+pub struct OrderIter<'a, Order> {
+    node : &'a PriceNode<Order>,
+    curr : usize
 }
 
-impl <Order : AuctionOrder> OrderIterMut<'_, Order> {
-    pub fn new(node : &mut PriceNode<Order>) -> OrderIterMut<Order> {
-        let tmp_len = node.order_list.len();
-        node.remove_front_and_tail_zero_order();
-        OrderIterMut {iter : node.order_list.iter_mut(), len : tmp_len, curr : 0 }
-    }
-    pub fn next(&mut self) -> Option<Rc<RefCell<OrderWithStatus<Order>>>> {
-        while let Some(order) = self.iter.next() {
-            self.curr += 1;
-            if order.borrow().leaves_qty != 0 {
-                return Some(order.clone())
-            }
+impl <'a, Order : AuctionOrder> Iterator for OrderIter<'a, Order> {
+    type Item = &'a OrderWithStatus<Order>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.curr == NIL {
+            return None;
+        }
+        match &self.node.slots[self.curr] {
+            Slot::Occupied { order, next, .. } => {
+                self.curr = *next;
+                Some(order)
+            },
+            Slot::Vacant { .. } => None,
         }
-        None
-    }
-    pub fn has_next(&self) -> bool {
-        // 因为在创建本实例时会清除首尾的数量为0道委托，所以下面的条件成立则表示未遍历完
-        self.curr < self.len
     }
 }
 
@@ -169,7 +197,7 @@ impl <Order : AuctionOrder> OrderIterMut<'_, Order> {
 mod tests {
     use super::*;
     use crate::order_book::auction_order::*;
-    
+
     #[test]
     fn append_order() {
         let order_id : u128 = 0;
@@ -202,18 +230,40 @@ mod tests {
         node.remove_order(4);
         node.remove_order(6);
 
-        let mut iter = node.order_iter_mut();
+        let mut iter = node.order_iter();
 
-        assert_eq!(iter.next().unwrap().borrow().leaves_qty, 100);
-        assert_eq!(iter.next().unwrap().borrow().leaves_qty, 200);
+        assert_eq!(iter.next().unwrap().leaves_qty, 100);
+        assert_eq!(iter.next().unwrap().leaves_qty, 200);
 
         if let Some(_order) = iter.next() {
             assert!(false);
         }
         assert_eq!(node.total(), 300);
+        assert_eq!(node.len(), 2);
 
     }
     #[test]
+    fn reuse_removed_slot() {
+        let mut gen = TestOrderGen::new();
+        let mut node : PriceNode<TestOrder> = PriceNode::new();
+        gen.work(&[(10, 100, Some(0)), (10, 200, Some(1))]).into_iter().for_each(|order| node.append_order(order));
+
+        // 持续的挂单、撤单不会让节点无限增长
+        for order_id in 2..1000 {
+            gen.work(&[(10, 10, Some(order_id))]).into_iter().for_each(|order| node.append_order(order));
+            assert_eq!(node.remove_order(order_id).unwrap().consumed_qty(), 10);
+        }
+        assert_eq!(node.slot_count(), 3);
+
+        assert_eq!(node.remove_order(0).unwrap().consumed_qty(), 100);
+        gen.work(&[(10, 300, Some(1000))]).into_iter().for_each(|order| node.append_order(order));
+        assert_eq!(node.slot_count(), 3);
+
+        let qty : Vec<Qty> = node.order_iter().map(|order| order.leaves_qty()).collect();
+        assert_eq!(qty, vec![200, 300]);
+        assert!(node.remove_order(0).is_none());
+    }
+    #[test]
     fn consume_order() {
         let orders_info   = [
             (10, 100, None),
@@ -252,4 +302,4 @@ mod tests {
         let consumed = node.consume_order(1000);
         assert_eq!(consumed.len(), 0);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,84 @@
+use std::cell::Cell;
+
+use crate::ring_buffer::{ring_buffer, Consumer, Producer, WaitStrategy};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PoolStats {
+    // 复用回收对象的次数
+    pub reused : u64,
+    // 池中没有空闲对象而新分配的次数
+    pub allocated : u64,
+}
+
+// 入口线程持有, 从回收队列中取出空闲的 Box 重新填充
+pub struct Pool<T> {
+    free : Consumer<Box<T>>,
+    stats : Cell<PoolStats>,
+}
+
+// 最后使用消息的线程(exe 阶段)持有, 把用完的 Box 送回入口线程
+pub struct Recycler<T> {
+    free : Producer<Box<T>>,
+}
+
+// 预先分配 capacity 个对象, 回收队列满时多出的对象直接释放
+pub fn pool<T : Default>(capacity : usize) -> (Pool<T>, Recycler<T>) {
+    let (tx, rx) = ring_buffer(capacity, WaitStrategy::Yield);
+    for _ in 0..tx.capacity() {
+        let _ = tx.try_send(Box::default());
+    }
+    (Pool { free : rx, stats : Cell::new(PoolStats::default()) }, Recycler { free : tx })
+}
+
+impl<T> Pool<T> {
+    pub fn alloc(&self, value : T) -> Box<T> {
+        let mut stats = self.stats.get();
+        let result = match self.free.try_recv() {
+            Ok(mut free) => {
+                *free = value;
+                stats.reused += 1;
+                free
+            },
+            Err(_) => {
+                stats.allocated += 1;
+                Box::new(value)
+            }
+        };
+        self.stats.set(stats);
+        result
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        self.stats.get()
+    }
+}
+
+impl<T> Recycler<T> {
+    pub fn recycle(&self, value : Box<T>) {
+        let _ = self.free.try_send(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reuse() {
+        let (pool, recycler) = pool::<u64>(2);
+        let a = pool.alloc(1);
+        let b = pool.alloc(2);
+        let c = pool.alloc(3);
+        assert_eq!((*a, *b, *c), (1, 2, 3));
+        assert_eq!(pool.stats(), PoolStats { reused : 2, allocated : 1 });
+
+        let addr = &*a as *const u64;
+        recycler.recycle(a);
+        recycler.recycle(b);
+        recycler.recycle(c);
+        let a = pool.alloc(4);
+        assert_eq!(&*a as *const u64, addr);
+        assert_eq!(*a, 4);
+        assert_eq!(pool.stats(), PoolStats { reused : 3, allocated : 1 });
+    }
+}
@@ -0,0 +1,170 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+
+use serde::{Serialize, Deserialize};
+
+use crate::messages::Inbound;
+use crate::report_sink::{read_frame, write_frame};
+use crate::reports::DecodeError;
+
+// 输入记录格式的版本, Inbound 字段变化时递增
+pub const INPUT_VERSION : u16 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputHeader {
+    pub version : u16,
+}
+
+// 定序阶段按全局顺序把每条输入交给 InputRecorder, 之后才送往下游
+// 按记录的顺序把输入重新交给一个新的 Engin, 得到的 order_id / seq_num 与报告都与原来相同
+pub trait InputRecorder : Send {
+    fn record(&mut self, input : &Inbound) -> io::Result<()>;
+    // 定序阶段在输入队列处理空、一批达到上限时以及退出前调用, 返回之后这一批输入才送往下游
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<R : InputRecorder + ?Sized> InputRecorder for Box<R> {
+    fn record(&mut self, input : &Inbound) -> io::Result<()> {
+        (**self).record(input)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+}
+
+// 同时交给两个记录器, 例如预写日志与复制连接
+impl<A : InputRecorder, B : InputRecorder> InputRecorder for (A, B) {
+    fn record(&mut self, input : &Inbound) -> io::Result<()> {
+        self.0.record(input)?;
+        self.1.record(input)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()?;
+        self.1.flush()
+    }
+}
+
+pub fn encode_into(buffer : &mut Vec<u8>, input : &Inbound) -> bincode::Result<()> {
+    bincode::serialize_into(&mut *buffer, &InputHeader { version : INPUT_VERSION })?;
+    bincode::serialize_into(buffer, input)
+}
+
+pub fn encode(input : &Inbound) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(128);
+    encode_into(&mut buffer, input).expect("encode input");
+    buffer
+}
+
+pub fn decode(bytes : &[u8]) -> Result<Inbound, DecodeError> {
+    let mut reader = bytes;
+    let header : InputHeader = bincode::deserialize_from(&mut reader)?;
+    if header.version != INPUT_VERSION {
+        return Err(DecodeError::UnsupportedVersion(header.version));
+    }
+    let input = bincode::deserialize_from(&mut reader)?;
+    if !reader.is_empty() {
+        return Err(DecodeError::Malformed(format!("{} trailing bytes", reader.len())));
+    }
+    Ok(input)
+}
+
+// 把输入收集在内存中
+#[derive(Debug, Default)]
+pub struct MemoryRecorder {
+    pub inputs : Vec<Inbound>,
+}
+
+impl MemoryRecorder {
+    pub fn new() -> MemoryRecorder {
+        MemoryRecorder::default()
+    }
+}
+
+impl InputRecorder for MemoryRecorder {
+    fn record(&mut self, input : &Inbound) -> io::Result<()> {
+        self.inputs.push(*input);
+        Ok(())
+    }
+}
+
+// 以追加方式写入文件, 帧格式与 FileSink 相同
+pub struct FileRecorder {
+    writer : BufWriter<File>,
+    buffer : Vec<u8>,
+}
+
+impl FileRecorder {
+    pub fn open<P : AsRef<Path>>(path : P) -> io::Result<FileRecorder> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileRecorder { writer : BufWriter::new(file), buffer : Vec::with_capacity(128) })
+    }
+}
+
+impl InputRecorder for FileRecorder {
+    fn record(&mut self, input : &Inbound) -> io::Result<()> {
+        self.buffer.clear();
+        encode_into(&mut self.buffer, input).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        write_frame(&mut self.writer, &self.buffer)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// 从 FileRecorder 写出的字节流中按顺序读出输入
+pub struct InputReader<R> {
+    reader : R,
+}
+
+impl<R : Read> InputReader<R> {
+    pub fn new(reader : R) -> InputReader<R> {
+        InputReader { reader }
+    }
+}
+
+impl<R : Read> Iterator for InputReader<R> {
+    type Item = Result<Inbound, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match read_frame(&mut self.reader) {
+            Ok(Some(frame)) => Some(decode(&frame)),
+            Ok(None) => None,
+            Err(e) => Some(Err(DecodeError::Io(e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{NewOrder, CancelRequest};
+    use crate::types::*;
+
+    #[test]
+    fn file_round_trip() {
+        let path = std::env::temp_dir().join(format!("recorder_{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let order = NewOrder { order_id : 1, seq_num : 1, pbu_id : to_array("PBU001"), cl_ord_id : to_array("C1"),
+            security_id : to_array("SEC001"), side : K_BUY, price : 100, qty : 10 };
+        let cancel_request = CancelRequest { order_id : 2, seq_num : 2, pbu_id : to_array("PBU001"), cl_ord_id : to_array("C2"),
+            orig_cl_ord_id : to_array("C1"), security_id : to_array("SEC001") };
+        let mut recorder = FileRecorder::open(&path).unwrap();
+        recorder.record(&Inbound::NewOrder(order)).unwrap();
+        recorder.record(&Inbound::CancelRequest(cancel_request)).unwrap();
+        recorder.flush().unwrap();
+
+        let inputs : Vec<Inbound> = InputReader::new(File::open(&path).unwrap()).map(|input| input.unwrap()).collect();
+        assert_eq!(inputs.len(), 2);
+        assert!(matches!(inputs[0], Inbound::NewOrder(o) if o.seq_num == 1 && o.cl_ord_id == order.cl_ord_id && o.price == 100));
+        assert!(matches!(inputs[1], Inbound::CancelRequest(c) if c.seq_num == 2 && c.orig_cl_ord_id == order.cl_ord_id));
+        std::fs::remove_file(&path).unwrap();
+
+        let mut bytes = encode(&inputs[0]);
+        bytes[0] = 9;
+        assert!(matches!(decode(&bytes), Err(DecodeError::UnsupportedVersion(9))));
+    }
+}
@@ -0,0 +1,415 @@
+use std::io::{self, BufWriter, Read, Write};
+use std::net::{Shutdown, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::types::SeqNum;
+use crate::messages::Inbound;
+#[cfg(test)]
+use crate::messages::NewOrder;
+use crate::engin::{Ingress, EngineError};
+use crate::recorder::{self, InputRecorder};
+use crate::journal::{crc32, write_entry, FRAME_HEADER_LEN, MAX_ENTRY_LEN};
+
+// 接收端读超时, 也是检查心跳与停止标志的间隔
+const POLL_INTERVAL : Duration = Duration::from_millis(20);
+// 对端长时间不读时写超时, 随后断开连接, 不让定序阶段一直等待
+const WRITE_TIMEOUT : Duration = Duration::from_secs(1);
+// 确认帧的负载: 8 字节小端序号
+const ACK_LEN : usize = 8;
+
+// 复制连接: 本机的 TCP 或 Unix 域套接字. 帧格式与 journal 相同, 负载为空的帧是心跳;
+// 备用引擎在同一连接上回送确认帧, 负载为已收到的最后一条输入的序号
+pub trait Connection : Read + Write + Send + 'static {
+    fn set_read_timeout(&self, timeout : Option<Duration>) -> io::Result<()>;
+    fn set_write_timeout(&self, timeout : Option<Duration>) -> io::Result<()>;
+    // 读写分别在两个线程中进行
+    fn try_clone_connection(&self) -> io::Result<Box<dyn Connection>>;
+    fn shutdown(&self);
+}
+
+impl Connection for TcpStream {
+    fn set_read_timeout(&self, timeout : Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)?;
+        self.set_nodelay(true)
+    }
+    fn set_write_timeout(&self, timeout : Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+    fn try_clone_connection(&self) -> io::Result<Box<dyn Connection>> {
+        Ok(Box::new(self.try_clone()?))
+    }
+    fn shutdown(&self) {
+        let _ = TcpStream::shutdown(self, Shutdown::Both);
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn set_read_timeout(&self, timeout : Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+    fn set_write_timeout(&self, timeout : Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+    fn try_clone_connection(&self) -> io::Result<Box<dyn Connection>> {
+        Ok(Box::new(self.try_clone()?))
+    }
+    fn shutdown(&self) {
+        let _ = UnixStream::shutdown(self, Shutdown::Both);
+    }
+}
+
+#[derive(Default)]
+struct SenderState {
+    writer : Mutex<Option<BufWriter<Box<dyn Connection>>>>,
+    // 备用引擎确认收到的最后一条输入的序号
+    acknowledged : AtomicU64,
+    stop : AtomicBool,
+}
+
+impl SenderState {
+    fn lock(&self) -> MutexGuard<'_, Option<BufWriter<Box<dyn Connection>>>> {
+        self.writer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // 出错 (包括写超时) 后断开连接, 之后不再发送, 主引擎照常处理; 备用引擎随后因超时认为主引擎失效
+    fn write<F>(&self, write : F) where F : FnOnce(&mut BufWriter<Box<dyn Connection>>) -> io::Result<()> {
+        let mut writer = self.lock();
+        if let Some(connection) = writer.as_mut() {
+            if write(connection).is_err() {
+                connection.get_ref().shutdown();
+                *writer = None;
+            }
+        }
+    }
+}
+
+// 主引擎一侧: 作为 InputRecorder 把定序后的输入发给备用引擎, 后台线程按间隔发送心跳并读取备用引擎的确认.
+// 复制是异步的: 输入在定序阶段的一批处理完时才发出, 主引擎不等待确认就输出报告, 主引擎失效时备用引擎可能缺少
+// 最后一批输入. 需要据此决策时 (例如接管前核对) 读取 ReplicationMonitor::acknowledged_seq_num
+pub struct ReplicationSender {
+    state : Arc<SenderState>,
+    buffer : Vec<u8>,
+    background : Option<JoinHandle<()>>,
+}
+
+impl ReplicationSender {
+    pub fn new<C : Connection>(connection : C, heartbeat_interval : Duration) -> io::Result<ReplicationSender> {
+        connection.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let mut reader = connection.try_clone_connection()?;
+        reader.set_read_timeout(Some(heartbeat_interval.min(POLL_INTERVAL)))?;
+        let state = Arc::new(SenderState { writer : Mutex::new(Some(BufWriter::new(Box::new(connection)))), ..SenderState::default() });
+        let background_state = state.clone();
+        let background = thread::spawn(move || {
+            let state = background_state;
+            let mut buffer = Vec::with_capacity(64);
+            let mut chunk = [0u8;256];
+            let mut last_heartbeat = Instant::now();
+            while !state.stop.load(Ordering::Acquire) {
+                if last_heartbeat.elapsed() >= heartbeat_interval {
+                    state.write(|connection| write_entry(connection, &[]).and_then(|_| connection.flush()));
+                    last_heartbeat = Instant::now();
+                }
+                match reader.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                }
+                let mut consumed = 0;
+                while let Ok(Some((payload, len))) = split_entry(&buffer[consumed..]) {
+                    if let Ok(seq_num) = <[u8;ACK_LEN]>::try_from(payload) {
+                        state.acknowledged.fetch_max(u64::from_le_bytes(seq_num), Ordering::AcqRel);
+                    }
+                    consumed += len;
+                }
+                buffer.drain(..consumed);
+            }
+            // 连接已断开或停止
+            let connection = state.lock().take();
+            if let Some(connection) = connection {
+                connection.get_ref().shutdown();
+            }
+        });
+        Ok(ReplicationSender { state, buffer : Vec::with_capacity(128), background : Some(background) })
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.monitor().is_connected()
+    }
+
+    // 可交给其它线程, 在 ReplicationSender 交给 Engin 之后读取复制进度
+    pub fn monitor(&self) -> ReplicationMonitor {
+        ReplicationMonitor { state : self.state.clone() }
+    }
+}
+
+#[derive(Clone)]
+pub struct ReplicationMonitor {
+    state : Arc<SenderState>,
+}
+
+impl ReplicationMonitor {
+    // 备用引擎已收到 (送入其入口队列) 的最后一条输入的序号, 序号更大的输入在主引擎失效时可能丢失
+    pub fn acknowledged_seq_num(&self) -> SeqNum {
+        self.state.acknowledged.load(Ordering::Acquire)
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state.lock().is_some()
+    }
+}
+
+impl InputRecorder for ReplicationSender {
+    fn record(&mut self, input : &Inbound) -> io::Result<()> {
+        self.buffer.clear();
+        recorder::encode_into(&mut self.buffer, input).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let buffer = &self.buffer;
+        self.state.write(|connection| write_entry(connection, buffer));
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.state.write(|connection| connection.flush());
+        Ok(())
+    }
+}
+
+impl Drop for ReplicationSender {
+    fn drop(&mut self) {
+        self.state.write(|connection| connection.flush());
+        self.state.stop.store(true, Ordering::Release);
+        if let Some(background) = self.background.take() {
+            let _ = background.join();
+        }
+    }
+}
+
+#[derive(Default)]
+struct ReplicaState {
+    stop : AtomicBool,
+    failed : AtomicBool,
+    broken : AtomicBool,
+    last_seq_num : AtomicU64,
+}
+
+// 备用引擎一侧: 接收主引擎的输入, 保留原有编号送入以 EnginConfig::standby 启动的 Engin
+pub struct Replica {
+    state : Arc<ReplicaState>,
+    receiver : Option<JoinHandle<io::Result<()>>>,
+    ingress : Ingress,
+}
+
+impl Replica {
+    // last_seq_num 为备用引擎已有的最后一条输入的序号, 从快照或日志恢复时不为 0, 复制的输入必须紧接其后;
+    // 超过 timeout 没有收到任何帧 (包括心跳) 即认为主引擎失效
+    pub fn start<C : Connection>(mut connection : C, ingress : Ingress, last_seq_num : SeqNum, timeout : Duration) -> io::Result<Replica> {
+        connection.set_read_timeout(Some(POLL_INTERVAL.min(timeout)))?;
+        connection.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let state = Arc::new(ReplicaState { last_seq_num : AtomicU64::new(last_seq_num), ..ReplicaState::default() });
+        let (receiver_state, receiver_ingress) = (state.clone(), ingress.clone());
+        let receiver = thread::spawn(move || {
+            let result = receive(&mut connection, &receiver_ingress, &receiver_state, timeout);
+            if let (Err(e), false) = (&result, receiver_state.stop.load(Ordering::Acquire)) {
+                let state = if primary_lost(e) { &receiver_state.failed } else { &receiver_state.broken };
+                state.store(true, Ordering::Release);
+            }
+            result
+        });
+        Ok(Replica { state, receiver : Some(receiver), ingress })
+    }
+
+    // 已送入备用引擎的最后一条输入的序号
+    pub fn last_seq_num(&self) -> SeqNum {
+        self.state.last_seq_num.load(Ordering::Acquire)
+    }
+
+    // 心跳超时或连接断开
+    pub fn primary_failed(&self) -> bool {
+        self.state.failed.load(Ordering::Acquire)
+    }
+
+    // 复制的输入不连续, 帧损坏或无法送入备用引擎; 主引擎可能仍在运行, 不应据此接管, 需要重新建立备用引擎
+    pub fn replication_broken(&self) -> bool {
+        self.state.broken.load(Ordering::Acquire)
+    }
+
+    // 停止接收并接管: 已收到的输入处理完后, 备用引擎以相同的订单簿与 exec_id 继续, 返回最后一条复制输入的序号.
+    // 接收线程的错误 (如主引擎断开) 是接管的原因, 不作为失败返回
+    pub fn promote(mut self) -> Result<SeqNum, EngineError> {
+        self.stop();
+        self.ingress.promote()?;
+        Ok(self.last_seq_num())
+    }
+
+    fn stop(&mut self) {
+        self.state.stop.store(true, Ordering::Release);
+        if let Some(receiver) = self.receiver.take() {
+            let _ = receiver.join();
+        }
+    }
+}
+
+impl Drop for Replica {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// 读不到主引擎的数据, 而不是收到的数据有问题
+fn primary_lost(e : &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::UnexpectedEof | io::ErrorKind::TimedOut | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted | io::ErrorKind::BrokenPipe)
+}
+
+fn receive<C : Connection>(connection : &mut C, ingress : &Ingress, state : &ReplicaState, timeout : Duration) -> io::Result<()> {
+    let mut buffer = Vec::with_capacity(1 << 16);
+    let mut chunk = [0u8;4096];
+    let mut ack = Vec::with_capacity(FRAME_HEADER_LEN + ACK_LEN);
+    let mut last_received = Instant::now();
+    loop {
+        let acknowledged = state.last_seq_num.load(Ordering::Acquire);
+        let mut consumed = 0;
+        while let Some((payload, len)) = split_entry(&buffer[consumed..])? {
+            // 空负载为心跳
+            if !payload.is_empty() {
+                let input = recorder::decode(payload).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                let expected = state.last_seq_num.load(Ordering::Acquire) + 1;
+                if input.seq_num() != expected {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("replicated input {} while expecting {}", input.seq_num(), expected)));
+                }
+                ingress.replicate(input).map_err(io::Error::other)?;
+                state.last_seq_num.store(expected, Ordering::Release);
+            }
+            consumed += len;
+            last_received = Instant::now();
+        }
+        buffer.drain(..consumed);
+        // 每读完一段即确认, 主引擎据此得知哪些输入已不会丢失
+        let last_seq_num = state.last_seq_num.load(Ordering::Acquire);
+        if last_seq_num != acknowledged {
+            ack.clear();
+            write_entry(&mut ack, &last_seq_num.to_le_bytes())?;
+            connection.write_all(&ack)?;
+        }
+
+        if state.stop.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        match connection.read(&mut chunk) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+                if last_received.elapsed() > timeout {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "primary heartbeat timed out"));
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// 从缓冲区开头取出一个完整的帧, 返回负载与整帧长度; 数据不足时返回 None
+fn split_entry(bytes : &[u8]) -> io::Result<Option<(&[u8], usize)>> {
+    if bytes.len() < FRAME_HEADER_LEN {
+        return Ok(None);
+    }
+    let len = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    let crc = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    if len > MAX_ENTRY_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("entry length {}", len)));
+    }
+    if bytes.len() < FRAME_HEADER_LEN + len {
+        return Ok(None);
+    }
+    let payload = &bytes[FRAME_HEADER_LEN..FRAME_HEADER_LEN + len];
+    if crc32(payload) != crc {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "checksum mismatch"));
+    }
+    Ok(Some((payload, FRAME_HEADER_LEN + len)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use crate::engin::{Engin, EnginConfig};
+
+    #[test]
+    fn heartbeat_keeps_replica_alive() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let sender = ReplicationSender::new(TcpStream::connect(listener.local_addr().unwrap()).unwrap(), Duration::from_millis(10)).unwrap();
+        let (connection, _) = listener.accept().unwrap();
+        let mut engin = Engin::with_config(crate::report_sink::CountingSink::new(), EnginConfig { standby : true, ..EnginConfig::default() });
+        let replica = Replica::start(connection, engin.ingress(), 0, Duration::from_millis(200)).unwrap();
+
+        thread::sleep(Duration::from_millis(500));
+        assert!(!replica.primary_failed());
+
+        // 连接未断开, 但不再有心跳
+        let _connection = sender.state.lock().take();
+        let start = Instant::now();
+        while !replica.primary_failed() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(replica.promote(), Ok(0));
+        assert!(!engin.ingress().is_standby());
+        engin.close().unwrap();
+    }
+
+    fn new_order(seq_num : SeqNum) -> Inbound {
+        Inbound::NewOrder(NewOrder { order_id : seq_num as u128, seq_num, qty : 1, price : 100, ..NewOrder::default() })
+    }
+
+    #[test]
+    fn acknowledged_after_flush() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut sender = ReplicationSender::new(TcpStream::connect(listener.local_addr().unwrap()).unwrap(), Duration::from_millis(10)).unwrap();
+        let monitor = sender.monitor();
+        let (connection, _) = listener.accept().unwrap();
+        let mut engin = Engin::with_config(crate::report_sink::CountingSink::new(), EnginConfig { standby : true, ..EnginConfig::default() });
+        let replica = Replica::start(connection, engin.ingress(), 0, Duration::from_millis(500)).unwrap();
+
+        (1..=3).for_each(|seq_num| sender.record(&new_order(seq_num)).unwrap());
+        sender.flush().unwrap();
+        let start = Instant::now();
+        while monitor.acknowledged_seq_num() < 3 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(replica.last_seq_num(), 3);
+        drop(sender);
+        drop(replica);
+        engin.close().unwrap();
+    }
+
+    #[test]
+    fn out_of_sequence_is_not_primary_failure() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut primary = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (connection, _) = listener.accept().unwrap();
+        let mut engin = Engin::with_config(crate::report_sink::CountingSink::new(), EnginConfig { standby : true, ..EnginConfig::default() });
+        let replica = Replica::start(connection, engin.ingress(), 0, Duration::from_secs(5)).unwrap();
+
+        // 跳过了序号 1
+        let mut payload = Vec::new();
+        recorder::encode_into(&mut payload, &new_order(2)).unwrap();
+        write_entry(&mut primary, &payload).unwrap();
+        let start = Instant::now();
+        while !replica.replication_broken() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(!replica.primary_failed());
+        assert_eq!(replica.last_seq_num(), 0);
+        drop(replica);
+        engin.close().unwrap();
+    }
+}
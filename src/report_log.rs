@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::types::*;
+use crate::journal::{SyncPolicy, Syncer, EntryReader, FRAME_HEADER_LEN, write_entry, corrupted};
+
+// 交易单元的报告按流分别编号, 例如按证券划分的分区; 未划分时都在流 0 中
+pub type StreamID = u16;
+
+// 记录的帧格式与 journal::Journal 相同, 带 CRC32; 帧内容: 交易单元 + 2 字节小端流编号 + 8 字节小端流序号 + 报告编码
+const RECORD_HEADER_LEN : usize = 6 + 2 + 8;
+
+#[derive(Debug)]
+struct LogInner {
+    writer : BufWriter<File>,
+    // 同一文件的只读句柄, 重传时按偏移读取
+    reader : File,
+    len : u64,
+    // 每个交易单元的每条流一个序列, 下标 + 1 即流序号, 值为记录在文件中的偏移
+    streams : HashMap<(PBUID, StreamID), Vec<u64>>,
+    policy : SyncPolicy,
+    _syncer : Option<Syncer>,
+}
+
+impl LogInner {
+    fn sync(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        match self.policy {
+            SyncPolicy::Async(_) => Ok(()),
+            _ => self.writer.get_ref().sync_data(),
+        }
+    }
+}
+
+// 按交易单元与流记录发出的报告, 每条流的序号从 1 开始连续, 断线重连后可按序号范围取回
+// 记录追加到文件中, 重启后从文件重建索引; 可克隆后在 exe 阶段与网关线程之间共享
+#[derive(Debug, Clone)]
+pub struct ReportLog {
+    inner : Arc<Mutex<LogInner>>,
+}
+
+impl ReportLog {
+    // 打开或创建日志; 与 journal::Journal 相同, 只截掉崩溃时写了一半的最后一条记录, 其它位置的损坏返回错误, 不改动文件.
+    // policy 决定 flush 时是否 fsync
+    pub fn open<P : AsRef<Path>>(path : P, policy : SyncPolicy) -> io::Result<ReportLog> {
+        let file = OpenOptions::new().create(true).read(true).append(true).open(&path)?;
+        let file_len = file.metadata()?.len();
+        let mut reader = EntryReader::new(BufReader::new(file.try_clone()?));
+        let mut streams : HashMap<(PBUID, StreamID), Vec<u64>> = HashMap::new();
+        loop {
+            let offset = reader.offset();
+            let record = match reader.read_entry() {
+                Ok(Some(record)) if record.len() >= RECORD_HEADER_LEN => record,
+                Ok(Some(record)) => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("record of {} bytes at offset {}", record.len(), offset)));
+                },
+                Ok(None) => break,
+                Err(e) if reader.torn_tail(&e, file_len) => break,
+                Err(e) => return Err(corrupted(e)),
+            };
+            let (pbu_id, stream_id, seq) = record_header(&record);
+            let stream = streams.entry((pbu_id, stream_id)).or_default();
+            if seq != stream.len() as u64 + 1 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("report {} out of sequence at offset {}", seq, offset)));
+            }
+            stream.push(offset);
+        }
+        let len = reader.offset();
+        if len < file_len {
+            file.set_len(len)?;
+            file.sync_all()?;
+        }
+
+        let syncer = match policy {
+            SyncPolicy::Async(interval) => Some(Syncer::spawn(file.try_clone()?, interval)),
+            _ => None,
+        };
+        let reader = File::open(&path)?;
+        let inner = LogInner { writer : BufWriter::new(file), reader, len, streams, policy, _syncer : syncer };
+        Ok(ReportLog { inner : Arc::new(Mutex::new(inner)) })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, LogInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // 追加一条发往 pbu_id 的报告, 返回它在 stream 中的序号; 记录在 flush 之后才算写入
+    pub fn append(&self, pbu_id : &PBUID, stream : StreamID, report : &[u8]) -> io::Result<u64> {
+        let mut inner = self.lock();
+        let inner = &mut *inner;
+        let offsets = inner.streams.entry((*pbu_id, stream)).or_default();
+        let seq = offsets.len() as u64 + 1;
+
+        let mut record = Vec::with_capacity(RECORD_HEADER_LEN + report.len());
+        record.extend_from_slice(pbu_id);
+        record.extend_from_slice(&stream.to_le_bytes());
+        record.extend_from_slice(&seq.to_le_bytes());
+        record.extend_from_slice(report);
+        write_entry(&mut inner.writer, &record)?;
+        offsets.push(inner.len);
+        inner.len += (FRAME_HEADER_LEN + record.len()) as u64;
+        if inner.policy == SyncPolicy::PerMessage {
+            inner.sync()?;
+        }
+        Ok(seq)
+    }
+
+    // pbu_id 在 stream 中最后一条报告的序号, 没有报告时为 0
+    pub fn last_seq(&self, pbu_id : &PBUID, stream : StreamID) -> u64 {
+        self.lock().streams.get(&(*pbu_id, stream)).map(|offsets| offsets.len() as u64).unwrap_or(0)
+    }
+
+    // 取回 stream 中序号在 [begin, end] 内的报告, end 超过最后一条时截至最后一条; 先按 policy 落盘, 重传的报告不会在崩溃后丢失
+    pub fn range(&self, pbu_id : &PBUID, stream : StreamID, begin : u64, end : u64) -> io::Result<Vec<Vec<u8>>> {
+        let mut inner = self.lock();
+        let inner = &mut *inner;
+        let offsets = match inner.streams.get(&(*pbu_id, stream)) {
+            Some(offsets) if begin >= 1 && begin <= end => &offsets[(begin as usize - 1).min(offsets.len())..(end.min(offsets.len() as u64)) as usize],
+            _ => return Ok(Vec::new()),
+        };
+        if offsets.is_empty() {
+            return Ok(Vec::new());
+        }
+        let offsets = offsets.to_vec();
+        inner.sync()?;
+        offsets.iter().map(|offset| {
+            inner.reader.seek(SeekFrom::Start(*offset))?;
+            let record = EntryReader::new(&mut inner.reader).read_entry().map_err(corrupted)?
+                .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+            Ok(record[RECORD_HEADER_LEN..].to_vec())
+        }).collect()
+    }
+
+    // 把已追加的记录交给操作系统, 除 SyncPolicy::Async 外还会 fsync
+    pub fn flush(&self) -> io::Result<()> {
+        self.lock().sync()
+    }
+}
+
+fn record_header(record : &[u8]) -> (PBUID, StreamID, u64) {
+    let mut pbu_id = PBUID::default();
+    pbu_id.copy_from_slice(&record[..6]);
+    let stream = u16::from_le_bytes([record[6], record[7]]);
+    let mut seq = [0u8;8];
+    seq.copy_from_slice(&record[8..RECORD_HEADER_LEN]);
+    (pbu_id, stream, u64::from_le_bytes(seq))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_after_reopen() {
+        let path = std::env::temp_dir().join(format!("report_log_{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let (pbu1, pbu2) = (to_array("PBU001"), to_array("PBU002"));
+
+        let log = ReportLog::open(&path, SyncPolicy::PerBatch).unwrap();
+        for i in 1..=5u8 {
+            assert_eq!(log.append(&pbu1, 0, &[i]).unwrap(), i as u64);
+            assert_eq!(log.append(&pbu2, 0, &[i, i]).unwrap(), i as u64);
+        }
+        // 另一条流单独编号
+        assert_eq!(log.append(&pbu1, 1, &[7]).unwrap(), 1);
+        assert_eq!(log.range(&pbu1, 0, 2, 3).unwrap(), vec![vec![2], vec![3]]);
+        log.flush().unwrap();
+        drop(log);
+
+        // 截掉最后一条记录的一部分
+        let len = std::fs::metadata(&path).unwrap().len();
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 1).unwrap();
+        let log = ReportLog::open(&path, SyncPolicy::PerMessage).unwrap();
+        assert_eq!((log.last_seq(&pbu1, 0), log.last_seq(&pbu2, 0), log.last_seq(&pbu1, 1)), (5, 5, 0));
+        assert_eq!(log.append(&pbu1, 1, &[9]).unwrap(), 1);
+        assert_eq!(log.range(&pbu2, 0, 4, 100).unwrap(), vec![vec![4, 4], vec![5, 5]]);
+        assert_eq!(log.range(&pbu1, 0, 4, 100).unwrap(), vec![vec![4], vec![5]]);
+        assert_eq!(log.range(&pbu1, 1, 1, 1).unwrap(), vec![vec![9]]);
+        assert!(log.range(&pbu1, 0, 6, 10).unwrap().is_empty());
+        assert!(log.range(&pbu1, 0, 0, 0).unwrap().is_empty());
+        assert!(log.range(&to_array("PBU003"), 0, 1, 10).unwrap().is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corruption() {
+        let path = std::env::temp_dir().join(format!("report_log_corruption_{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let pbu_id = to_array("PBU001");
+        let log = ReportLog::open(&path, SyncPolicy::PerBatch).unwrap();
+        for i in 1..=3u8 {
+            log.append(&pbu_id, 0, &[i; 8]).unwrap();
+        }
+        log.flush().unwrap();
+        drop(log);
+        let record_len = FRAME_HEADER_LEN + RECORD_HEADER_LEN + 8;
+
+        // 最后一条记录校验失败, 视为写了一半
+        let mut bytes = std::fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        std::fs::write(&path, &bytes).unwrap();
+        let log = ReportLog::open(&path, SyncPolicy::PerMessage).unwrap();
+        assert_eq!(log.last_seq(&pbu_id, 0), 2);
+        log.append(&pbu_id, 0, &[3; 8]).unwrap();
+        drop(log);
+
+        // 中间的记录损坏时不截断, 返回错误
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[record_len + FRAME_HEADER_LEN + 1] ^= 0xFF;
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(ReportLog::open(&path, SyncPolicy::PerMessage).err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+
+        // 完整但比记录头还短的记录也是损坏
+        let mut bytes = bytes[..record_len].to_vec();
+        write_entry(&mut bytes, b"short").unwrap();
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(ReportLog::open(&path, SyncPolicy::PerMessage).err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
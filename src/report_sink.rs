@@ -0,0 +1,242 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+
+// exe 阶段把每条报告的编码交给 ReportSink 输出, report 只在本次调用内有效
+pub trait ReportSink : Send {
+    fn send(&mut self, report : &[u8]) -> io::Result<()>;
+    // exe 阶段在输入队列处理空时以及退出前调用
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S : ReportSink + ?Sized> ReportSink for Box<S> {
+    fn send(&mut self, report : &[u8]) -> io::Result<()> {
+        (**self).send(report)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+}
+
+// 只统计报告条数和字节数
+#[derive(Debug, Default)]
+pub struct CountingSink {
+    pub count : u32,
+    pub bytes : usize,
+}
+
+impl CountingSink {
+    pub fn new() -> CountingSink {
+        CountingSink::default()
+    }
+}
+
+impl ReportSink for CountingSink {
+    fn send(&mut self, report : &[u8]) -> io::Result<()> {
+        self.count += 1;
+        self.bytes += report.len();
+        Ok(())
+    }
+}
+
+// 把报告收集在内存中, 用于测试
+#[derive(Debug, Default)]
+pub struct MemorySink {
+    pub reports : Vec<Vec<u8>>,
+}
+
+impl MemorySink {
+    pub fn new() -> MemorySink {
+        MemorySink::default()
+    }
+}
+
+impl ReportSink for MemorySink {
+    fn send(&mut self, report : &[u8]) -> io::Result<()> {
+        self.reports.push(report.to_vec());
+        Ok(())
+    }
+}
+
+// 读取时帧长的上限, 超过即认为数据损坏, 不按对端给出的长度分配内存
+pub const MAX_FRAME_LEN : usize = 1 << 16;
+
+// 文件和 TCP 输出的帧格式: 4 字节小端长度 + 报告编码
+pub fn write_frame<W : Write>(writer : &mut W, report : &[u8]) -> io::Result<()> {
+    writer.write_all(&(report.len() as u32).to_le_bytes())?;
+    writer.write_all(report)
+}
+
+// 读取一帧, 流正常结束时返回 None
+pub fn read_frame<R : Read>(reader : &mut R) -> io::Result<Option<Vec<u8>>> {
+    read_frame_with_limit(reader, MAX_FRAME_LEN)
+}
+
+// 只有在帧边界上结束才是正常结束, 长度读到一半返回 UnexpectedEof, 超长返回 InvalidData
+pub(crate) fn read_frame_with_limit<R : Read>(reader : &mut R, max_len : usize) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8;4];
+    let mut read = 0;
+    while read < len.len() {
+        match reader.read(&mut len[read..]) {
+            Ok(0) if read == 0 => return Ok(None),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    let len = u32::from_le_bytes(len) as usize;
+    if len > max_len {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame of {} bytes", len)));
+    }
+    let mut frame = vec![0u8; len];
+    reader.read_exact(&mut frame)?;
+    Ok(Some(frame))
+}
+
+// 以追加方式写入文件
+pub struct FileSink {
+    writer : BufWriter<File>,
+}
+
+impl FileSink {
+    pub fn open<P : AsRef<Path>>(path : P) -> io::Result<FileSink> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileSink { writer : BufWriter::new(file) })
+    }
+}
+
+impl ReportSink for FileSink {
+    fn send(&mut self, report : &[u8]) -> io::Result<()> {
+        write_frame(&mut self.writer, report)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+pub struct TcpSink {
+    writer : BufWriter<TcpStream>,
+}
+
+impl TcpSink {
+    pub fn connect<A : ToSocketAddrs>(addr : A) -> io::Result<TcpSink> {
+        TcpSink::new(TcpStream::connect(addr)?)
+    }
+    pub fn new(stream : TcpStream) -> io::Result<TcpSink> {
+        stream.set_nodelay(true)?;
+        Ok(TcpSink { writer : BufWriter::new(stream) })
+    }
+}
+
+impl ReportSink for TcpSink {
+    fn send(&mut self, report : &[u8]) -> io::Result<()> {
+        write_frame(&mut self.writer, report)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// 每条报告依次交给所有下游, 任一下游出错即返回错误
+#[derive(Default)]
+pub struct FanOutSink {
+    sinks : Vec<Box<dyn ReportSink>>,
+}
+
+impl FanOutSink {
+    pub fn new(sinks : Vec<Box<dyn ReportSink>>) -> FanOutSink {
+        FanOutSink { sinks }
+    }
+    pub fn push<S : ReportSink + 'static>(&mut self, sink : S) {
+        self.sinks.push(Box::new(sink));
+    }
+}
+
+impl ReportSink for FanOutSink {
+    fn send(&mut self, report : &[u8]) -> io::Result<()> {
+        self.sinks.iter_mut().try_for_each(|sink| sink.send(report))
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.sinks.iter_mut().try_for_each(|sink| sink.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn file_sink_append() {
+        let path = std::env::temp_dir().join(format!("report_sink_{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut sink = FileSink::open(&path).unwrap();
+        sink.send(b"first").unwrap();
+        sink.flush().unwrap();
+        drop(sink);
+        let mut sink = FileSink::open(&path).unwrap();
+        sink.send(b"second").unwrap();
+        sink.flush().unwrap();
+
+        let mut file = File::open(&path).unwrap();
+        assert_eq!(read_frame(&mut file).unwrap(), Some(b"first".to_vec()));
+        assert_eq!(read_frame(&mut file).unwrap(), Some(b"second".to_vec()));
+        assert_eq!(read_frame(&mut file).unwrap(), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn frame_limits() {
+        let read = |bytes : &[u8]| read_frame(&mut &bytes[..]).map_err(|e| e.kind());
+        assert_eq!(read(b""), Ok(None));
+        // 长度只写了一部分
+        assert_eq!(read(&[3, 0]), Err(io::ErrorKind::UnexpectedEof));
+        assert_eq!(read(&[3, 0, 0, 0, b'a']), Err(io::ErrorKind::UnexpectedEof));
+        assert_eq!(read(&u32::MAX.to_le_bytes()), Err(io::ErrorKind::InvalidData));
+        assert_eq!(read(&((MAX_FRAME_LEN + 1) as u32).to_le_bytes()), Err(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn tcp_sink() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let reader = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reports = Vec::new();
+            while let Some(report) = read_frame(&mut stream).unwrap() {
+                reports.push(report);
+            }
+            reports
+        });
+
+        let mut sink = TcpSink::connect(addr).unwrap();
+        sink.send(b"abc").unwrap();
+        sink.send(b"").unwrap();
+        sink.flush().unwrap();
+        drop(sink);
+        assert_eq!(reader.join().unwrap(), vec![b"abc".to_vec(), Vec::new()]);
+    }
+
+    struct FailingSink;
+
+    impl ReportSink for FailingSink {
+        fn send(&mut self, _report : &[u8]) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+        }
+    }
+
+    #[test]
+    fn fan_out() {
+        let mut sink = FanOutSink::new(vec![Box::new(CountingSink::new())]);
+        sink.push(MemorySink::new());
+        sink.send(b"abc").unwrap();
+        sink.push(FailingSink);
+        assert_eq!(sink.send(b"abc").unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    }
+}
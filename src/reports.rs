@@ -0,0 +1,258 @@
+use std::fmt;
+use std::io::{self, Read};
+
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+
+use crate::types::*;
+use crate::messages::NewOrder;
+use crate::report_sink::read_frame;
+
+// 输出消息格式的版本, 报告字段变化时递增
+// 2: TradeCaptureReport 增加 side
+// 3: 各报告增加 seq_num
+// 4: ExecutionReport 增加 avg_px
+pub const REPORT_VERSION : u16 = 4;
+
+pub const K_MSG_TYPE_EXECUTION_REPORT : u8 = b'8';
+pub const K_MSG_TYPE_CANCEL_REJECT : u8 = b'9';
+pub const K_MSG_TYPE_TRADE_CAPTURE_REPORT : u8 = b'T';
+
+// 每条输出消息以消息头开始, 之后是对应报告的 bincode 编码
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReportHeader {
+    pub msg_type : u8,
+    pub version : u16,
+}
+
+pub trait Report : Serialize + DeserializeOwned {
+    const MSG_TYPE : u8;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CancelReject {
+    pub order_id : OrderID,
+    pub seq_num : SeqNum,
+    pub pbu_id : PBUID,
+    pub cl_ord_id : ClOrdID,
+    pub orig_cl_ord_id : ClOrdID,
+    pub security_id : SecurityID,
+    pub rejected_reason : CancelReasonCode,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TradeCaptureReport {
+    pub security_id : SecurityID,
+    pub order_id : OrderID,
+    pub seq_num : SeqNum,
+    pub pbu_id : PBUID,
+    pub cl_ord_id : ClOrdID,
+    pub side : Side,
+    pub exec_id : ExecID,
+    pub counterparty_order_id : OrderID,
+    pub counterparty_pbu_id : PBUID,
+    pub counterparty_cl_ord_id : ClOrdID,
+    pub counterparty_exec_id : ExecID,
+    pub last_px : Price,
+    pub last_qty : Qty,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecutionReport {
+    pub order_id : OrderID,
+    pub seq_num : SeqNum,
+    pub pbu_id : PBUID,
+    pub cl_ord_id : ClOrdID,
+    pub orig_cl_ord_id : ClOrdID,
+    pub security_id : SecurityID,
+    pub side : Side,
+    pub price : Price,
+    pub qty : Qty,
+    pub cum_qty : Qty,
+    pub leaves_qty : Qty,
+    pub rejected_reason : CancelReasonCode,
+    pub exec_type : char,
+    pub ord_status : char,
+    pub last_px : Price,
+    pub last_qty : Qty,
+    // 已成交部分的平均价格, 四舍五入到价位单位; 没有成交时为 0
+    pub avg_px : Price,
+    pub exec_id : ExecID,
+}
+
+impl ExecutionReport {
+    pub fn new(order : &NewOrder) -> ExecutionReport{
+        ExecutionReport {
+            order_id : order.order_id,
+            seq_num : order.seq_num,
+            pbu_id : order.pbu_id,
+            cl_ord_id : order.cl_ord_id,
+            orig_cl_ord_id : to_array(""),
+            security_id : order.security_id,
+            side : order.side,
+            price : order.price,
+            qty : order.qty,
+            cum_qty : 0,
+            leaves_qty : 0,
+            rejected_reason : CancelReasonCode::Passed,
+            exec_type : ' ',
+            ord_status : ' ' ,
+            last_px : 0,
+            last_qty : 0,
+            avg_px : 0,
+            exec_id : 0
+        }
+    }
+}
+
+impl Report for ExecutionReport {
+    const MSG_TYPE : u8 = K_MSG_TYPE_EXECUTION_REPORT;
+}
+
+impl Report for CancelReject {
+    const MSG_TYPE : u8 = K_MSG_TYPE_CANCEL_REJECT;
+}
+
+impl Report for TradeCaptureReport {
+    const MSG_TYPE : u8 = K_MSG_TYPE_TRADE_CAPTURE_REPORT;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputMessage {
+    ExecutionReport(ExecutionReport),
+    TradeCaptureReport(TradeCaptureReport),
+    CancelReject(CancelReject),
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    UnknownMsgType(u8),
+    UnsupportedVersion(u16),
+    // 消息体与消息头声明的类型不符, 或长度不对
+    Malformed(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnknownMsgType(msg_type) => write!(f, "unknown message type {}", msg_type),
+            DecodeError::UnsupportedVersion(version) => write!(f, "unsupported report version {}", version),
+            DecodeError::Malformed(msg) => write!(f, "malformed report: {}", msg),
+            DecodeError::Io(e) => write!(f, "read report failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<bincode::Error> for DecodeError {
+    fn from(e : bincode::Error) -> DecodeError {
+        DecodeError::Malformed(e.to_string())
+    }
+}
+
+impl From<io::Error> for DecodeError {
+    fn from(e : io::Error) -> DecodeError {
+        DecodeError::Io(e)
+    }
+}
+
+// 编码到 buffer 末尾, buffer 由调用方复用
+pub fn encode_into<T : Report>(buffer : &mut Vec<u8>, report : &T) -> bincode::Result<()> {
+    bincode::serialize_into(&mut *buffer, &ReportHeader { msg_type : T::MSG_TYPE, version : REPORT_VERSION })?;
+    bincode::serialize_into(buffer, report)
+}
+
+pub fn encode<T : Report>(report : &T) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    encode_into(&mut buffer, report).unwrap();
+    buffer
+}
+
+// 解码一条完整的输出消息
+pub fn decode(bytes : &[u8]) -> Result<OutputMessage, DecodeError> {
+    let mut reader = bytes;
+    let header : ReportHeader = bincode::deserialize_from(&mut reader)?;
+    if header.version != REPORT_VERSION {
+        return Err(DecodeError::UnsupportedVersion(header.version));
+    }
+    let message = match header.msg_type {
+        K_MSG_TYPE_EXECUTION_REPORT => OutputMessage::ExecutionReport(bincode::deserialize_from(&mut reader)?),
+        K_MSG_TYPE_TRADE_CAPTURE_REPORT => OutputMessage::TradeCaptureReport(bincode::deserialize_from(&mut reader)?),
+        K_MSG_TYPE_CANCEL_REJECT => OutputMessage::CancelReject(bincode::deserialize_from(&mut reader)?),
+        msg_type => return Err(DecodeError::UnknownMsgType(msg_type)),
+    };
+    if !reader.is_empty() {
+        return Err(DecodeError::Malformed(format!("{} trailing bytes", reader.len())));
+    }
+    Ok(message)
+}
+
+// 从 FileSink / TcpSink 写出的字节流中逐条读出输出消息
+pub struct ReportDecoder<R> {
+    reader : R,
+}
+
+impl<R : Read> ReportDecoder<R> {
+    pub fn new(reader : R) -> ReportDecoder<R> {
+        ReportDecoder { reader }
+    }
+}
+
+impl<R : Read> Iterator for ReportDecoder<R> {
+    type Item = Result<OutputMessage, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match read_frame(&mut self.reader) {
+            Ok(Some(frame)) => Some(decode(&frame)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cancel_reject() -> CancelReject {
+        CancelReject {
+            order_id : 7,
+            seq_num : 0,
+            pbu_id : to_array("PBU001"),
+            cl_ord_id : to_array("2"),
+            orig_cl_ord_id : to_array("1"),
+            security_id : to_array("SEC001"),
+            rejected_reason : CancelReasonCode::OrderNotExisted,
+        }
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        let report = cancel_reject();
+        let bytes = encode(&report);
+        assert_eq!(bytes[0], K_MSG_TYPE_CANCEL_REJECT);
+        assert_eq!(decode(&bytes).unwrap(), OutputMessage::CancelReject(report));
+
+        let mut bytes = bytes;
+        bytes[0] = b'?';
+        assert!(matches!(decode(&bytes), Err(DecodeError::UnknownMsgType(b'?'))));
+        bytes[0] = K_MSG_TYPE_EXECUTION_REPORT;
+        assert!(matches!(decode(&bytes), Err(DecodeError::Malformed(_))));
+        bytes[1] = 0xff;
+        assert!(matches!(decode(&bytes), Err(DecodeError::UnsupportedVersion(_))));
+    }
+
+    #[test]
+    fn decode_stream() {
+        let mut stream = Vec::new();
+        for _ in 0..3 {
+            let bytes = encode(&cancel_reject());
+            stream.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+            stream.extend_from_slice(&bytes);
+        }
+        let messages : Vec<OutputMessage> = ReportDecoder::new(stream.as_slice()).map(|m| m.unwrap()).collect();
+        assert_eq!(messages.len(), 3);
+    }
+}
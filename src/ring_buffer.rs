@@ -0,0 +1,423 @@
+use std::cell::{Cell, UnsafeCell};
+use std::hint;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{RecvError, SendError, TryRecvError, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, Thread};
+use std::time::Duration;
+
+// 队列满或空时的等待方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitStrategy {
+    // 一直自旋, 延迟最低, 需要每个阶段独占一个 CPU 核
+    BusySpin,
+    // 自旋若干次后让出 CPU
+    Yield,
+    // 自旋、让出后挂起线程, 由对端唤醒
+    Park,
+}
+
+const SPIN_LIMIT : u32 = 64;
+const YIELD_LIMIT : u32 = 128;
+// 唤醒丢失时的兜底超时
+const PARK_TIMEOUT : Duration = Duration::from_millis(1);
+
+#[repr(align(64))]
+struct CachePadded<T>(T);
+
+struct Waiter {
+    sleeping : AtomicBool,
+    thread : Mutex<Option<Thread>>,
+}
+
+impl Waiter {
+    fn new() -> Waiter {
+        Waiter { sleeping : AtomicBool::new(false), thread : Mutex::new(None) }
+    }
+
+    fn wake(&self) {
+        fence(Ordering::SeqCst);
+        if self.sleeping.load(Ordering::Relaxed) {
+            if let Ok(thread) = self.thread.lock() {
+                if let Some(thread) = thread.as_ref() {
+                    thread.unpark();
+                }
+            }
+        }
+    }
+}
+
+struct Ring<T> {
+    // 消费者下一个读取位置
+    head : CachePadded<AtomicUsize>,
+    // 生产者下一个写入位置
+    tail : CachePadded<AtomicUsize>,
+    producer_alive : CachePadded<AtomicBool>,
+    consumer_alive : CachePadded<AtomicBool>,
+    producer_waiter : Waiter,
+    consumer_waiter : Waiter,
+    // 生产者写入时观察到的最大队列深度
+    high_watermark : AtomicUsize,
+    mask : usize,
+    wait : WaitStrategy,
+    slots : Box<[UnsafeCell<MaybeUninit<T>>]>,
+}
+
+unsafe impl<T : Send> Send for Ring<T> {}
+unsafe impl<T : Send> Sync for Ring<T> {}
+
+impl<T> Ring<T> {
+    fn capacity(&self) -> usize {
+        self.mask + 1
+    }
+
+    fn len(&self) -> usize {
+        let tail = self.tail.0.load(Ordering::Acquire);
+        let head = self.head.0.load(Ordering::Acquire);
+        tail.wrapping_sub(head)
+    }
+
+    // ready 用于挂起前最后确认一次, 避免错过对端的唤醒
+    fn idle(&self, waiter : &Waiter, spins : &mut u32, ready : impl Fn() -> bool) {
+        *spins += 1;
+        match self.wait {
+            WaitStrategy::BusySpin => hint::spin_loop(),
+            WaitStrategy::Yield => {
+                if *spins < SPIN_LIMIT { hint::spin_loop() } else { thread::yield_now() }
+            },
+            WaitStrategy::Park => {
+                if *spins < SPIN_LIMIT {
+                    hint::spin_loop();
+                }
+                else if *spins < YIELD_LIMIT {
+                    thread::yield_now();
+                }
+                else {
+                    if let Ok(mut thread) = waiter.thread.lock() {
+                        if thread.is_none() {
+                            *thread = Some(thread::current());
+                        }
+                    }
+                    waiter.sleeping.store(true, Ordering::Relaxed);
+                    fence(Ordering::SeqCst);
+                    if !ready() {
+                        thread::park_timeout(PARK_TIMEOUT);
+                    }
+                    waiter.sleeping.store(false, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        let tail = *self.tail.0.get_mut();
+        let mut head = *self.head.0.get_mut();
+        while head != tail {
+            unsafe { (*self.slots[head & self.mask].get()).assume_init_drop(); }
+            head = head.wrapping_add(1);
+        }
+    }
+}
+
+pub struct Producer<T> {
+    ring : Arc<Ring<T>>,
+    cached_head : Cell<usize>,
+}
+
+pub struct Consumer<T> {
+    ring : Arc<Ring<T>>,
+    cached_tail : Cell<usize>,
+}
+
+unsafe impl<T : Send> Send for Producer<T> {}
+unsafe impl<T : Send> Send for Consumer<T> {}
+
+// 单生产者单消费者的环形队列, 容量向上取整为 2 的幂, 预先分配所有槽位
+pub fn ring_buffer<T>(capacity : usize, wait : WaitStrategy) -> (Producer<T>, Consumer<T>) {
+    let capacity = capacity.max(2).next_power_of_two();
+    let slots = (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect();
+    let ring = Arc::new(Ring {
+        head : CachePadded(AtomicUsize::new(0)),
+        tail : CachePadded(AtomicUsize::new(0)),
+        producer_alive : CachePadded(AtomicBool::new(true)),
+        consumer_alive : CachePadded(AtomicBool::new(true)),
+        producer_waiter : Waiter::new(),
+        consumer_waiter : Waiter::new(),
+        high_watermark : AtomicUsize::new(0),
+        mask : capacity - 1,
+        wait,
+        slots,
+    });
+    (Producer { ring : ring.clone(), cached_head : Cell::new(0) }, Consumer { ring, cached_tail : Cell::new(0) })
+}
+
+impl<T> Producer<T> {
+    pub fn send(&self, value : T) -> Result<(), SendError<T>> {
+        let ring = &self.ring;
+        let tail = ring.tail.0.load(Ordering::Relaxed);
+        let mut spins = 0;
+        while tail.wrapping_sub(self.cached_head.get()) > ring.mask {
+            self.cached_head.set(ring.head.0.load(Ordering::Acquire));
+            if tail.wrapping_sub(self.cached_head.get()) <= ring.mask {
+                break;
+            }
+            if !ring.consumer_alive.0.load(Ordering::Acquire) {
+                return Err(SendError(value));
+            }
+            ring.idle(&ring.producer_waiter, &mut spins, || {
+                tail.wrapping_sub(ring.head.0.load(Ordering::Acquire)) <= ring.mask
+                    || !ring.consumer_alive.0.load(Ordering::Acquire)
+            });
+        }
+        if !ring.consumer_alive.0.load(Ordering::Relaxed) {
+            return Err(SendError(value));
+        }
+        self.publish(tail, value);
+        Ok(())
+    }
+
+    pub fn try_send(&self, value : T) -> Result<(), TrySendError<T>> {
+        let ring = &self.ring;
+        if !ring.consumer_alive.0.load(Ordering::Acquire) {
+            return Err(TrySendError::Disconnected(value));
+        }
+        let tail = ring.tail.0.load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.cached_head.get()) > ring.mask {
+            self.cached_head.set(ring.head.0.load(Ordering::Acquire));
+            if tail.wrapping_sub(self.cached_head.get()) > ring.mask {
+                return Err(TrySendError::Full(value));
+            }
+        }
+        self.publish(tail, value);
+        Ok(())
+    }
+
+    fn publish(&self, tail : usize, value : T) {
+        let ring = &self.ring;
+        unsafe { (*ring.slots[tail & ring.mask].get()).write(value); }
+        ring.tail.0.store(tail.wrapping_add(1), Ordering::Release);
+
+        let depth = tail.wrapping_add(1).wrapping_sub(ring.head.0.load(Ordering::Relaxed));
+        if depth > ring.high_watermark.load(Ordering::Relaxed) {
+            ring.high_watermark.store(depth, Ordering::Relaxed);
+        }
+        if ring.wait == WaitStrategy::Park {
+            ring.consumer_waiter.wake();
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.ring.capacity()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ring.len() == 0
+    }
+
+    pub fn monitor(&self) -> QueueMonitor where T : Send + 'static {
+        QueueMonitor { ring : self.ring.clone() }
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.ring.producer_alive.0.store(false, Ordering::Release);
+        self.ring.consumer_waiter.wake();
+    }
+}
+
+impl<T> Consumer<T> {
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let ring = &self.ring;
+        let head = ring.head.0.load(Ordering::Relaxed);
+        let mut spins = 0;
+        while self.cached_tail.get() == head {
+            self.cached_tail.set(ring.tail.0.load(Ordering::Acquire));
+            if self.cached_tail.get() != head {
+                break;
+            }
+            if !ring.producer_alive.0.load(Ordering::Acquire) {
+                // 生产者退出前写入的数据仍需读完
+                self.cached_tail.set(ring.tail.0.load(Ordering::Acquire));
+                if self.cached_tail.get() == head {
+                    return Err(RecvError);
+                }
+                break;
+            }
+            ring.idle(&ring.consumer_waiter, &mut spins, || {
+                ring.tail.0.load(Ordering::Acquire) != head || !ring.producer_alive.0.load(Ordering::Acquire)
+            });
+        }
+        Ok(self.take(head))
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let ring = &self.ring;
+        let head = ring.head.0.load(Ordering::Relaxed);
+        if self.cached_tail.get() == head {
+            let alive = ring.producer_alive.0.load(Ordering::Acquire);
+            self.cached_tail.set(ring.tail.0.load(Ordering::Acquire));
+            if self.cached_tail.get() == head {
+                return Err(if alive { TryRecvError::Empty } else { TryRecvError::Disconnected });
+            }
+        }
+        Ok(self.take(head))
+    }
+
+    fn take(&self, head : usize) -> T {
+        let ring = &self.ring;
+        let value = unsafe { (*ring.slots[head & ring.mask].get()).assume_init_read() };
+        ring.head.0.store(head.wrapping_add(1), Ordering::Release);
+        if ring.wait == WaitStrategy::Park {
+            ring.producer_waiter.wake();
+        }
+        value
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        self.ring.consumer_alive.0.store(false, Ordering::Release);
+        self.ring.producer_waiter.wake();
+    }
+}
+
+trait QueueDepth : Send + Sync {
+    fn len(&self) -> usize;
+    fn capacity(&self) -> usize;
+    fn high_watermark(&self) -> usize;
+}
+
+impl<T : Send> QueueDepth for Ring<T> {
+    fn len(&self) -> usize {
+        Ring::len(self)
+    }
+    fn capacity(&self) -> usize {
+        Ring::capacity(self)
+    }
+    fn high_watermark(&self) -> usize {
+        self.high_watermark.load(Ordering::Relaxed)
+    }
+}
+
+// 只读的队列监控句柄, 可在任意线程读取, 不影响生产者和消费者
+#[derive(Clone)]
+pub struct QueueMonitor {
+    ring : Arc<dyn QueueDepth>,
+}
+
+impl QueueMonitor {
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.ring.len() == 0
+    }
+    pub fn capacity(&self) -> usize {
+        self.ring.capacity()
+    }
+    pub fn high_watermark(&self) -> usize {
+        self.ring.high_watermark()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_send_recv() {
+        let (tx, rx) = ring_buffer(3, WaitStrategy::BusySpin);
+        assert_eq!(tx.capacity(), 4);
+        for i in 0..4 {
+            tx.try_send(i).unwrap();
+        }
+        assert_eq!(tx.try_send(4), Err(TrySendError::Full(4)));
+        assert_eq!(rx.recv(), Ok(0));
+        tx.try_send(4).unwrap();
+        for i in 1..5 {
+            assert_eq!(rx.try_recv(), Ok(i));
+        }
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn test_monitor() {
+        let (tx, rx) = ring_buffer(8, WaitStrategy::Yield);
+        let monitor = tx.monitor();
+        assert_eq!(monitor.capacity(), 8);
+        assert!(monitor.is_empty());
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(monitor.len(), 5);
+        assert_eq!(rx.recv(), Ok(0));
+        assert_eq!(rx.recv(), Ok(1));
+        tx.send(5).unwrap();
+        assert_eq!(monitor.len(), 4);
+        assert_eq!(monitor.high_watermark(), 5);
+    }
+
+    #[test]
+    fn test_disconnected() {
+        let (tx, rx) = ring_buffer(4, WaitStrategy::Yield);
+        tx.send(1).unwrap();
+        drop(tx);
+        assert_eq!(rx.recv(), Ok(1));
+        assert_eq!(rx.recv(), Err(RecvError));
+
+        let (tx, rx) = ring_buffer(4, WaitStrategy::Yield);
+        drop(rx);
+        assert_eq!(tx.send(1), Err(SendError(1)));
+    }
+
+    #[test]
+    fn test_drop_pending() {
+        let value = Arc::new(0);
+        let (tx, rx) = ring_buffer(4, WaitStrategy::Yield);
+        tx.send(value.clone()).unwrap();
+        tx.send(value.clone()).unwrap();
+        drop(rx.recv().unwrap());
+        drop(tx);
+        drop(rx);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    fn transfer(wait : WaitStrategy) {
+        let (tx, rx) = ring_buffer(1024, wait);
+        let producer = thread::spawn(move || {
+            for i in 0..100000u64 {
+                tx.send(i).unwrap();
+            }
+        });
+        let mut expected = 0;
+        while let Ok(i) = rx.recv() {
+            assert_eq!(i, expected);
+            expected += 1;
+        }
+        producer.join().unwrap();
+        assert_eq!(expected, 100000);
+    }
+
+    #[test]
+    fn test_busy_spin() {
+        transfer(WaitStrategy::BusySpin);
+    }
+
+    #[test]
+    fn test_yield() {
+        transfer(WaitStrategy::Yield);
+    }
+
+    #[test]
+    fn test_park() {
+        transfer(WaitStrategy::Park);
+    }
+}
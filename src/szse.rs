@@ -0,0 +1,1006 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::types::*;
+use crate::messages::{Inbound, NewOrder, CancelRequest};
+use crate::reports::{ExecutionReport, CancelReject, OutputMessage};
+use crate::fix::civil_from_days;
+use crate::report_log::StreamID;
+
+// 深交所 Binary 交易数据接口: 消息头 MsgType(u32) + BodyLength(u32), 消息体, 消息尾 Checksum(u32)
+// 整数均为网络字节序, 字符串左对齐右补空格, 与 PBUID / ClOrdID / SecurityID 的表示一致
+pub const K_MSG_TYPE_NEW_ORDER : u32 = 100101;
+pub const K_MSG_TYPE_CANCEL_REQUEST : u32 = 190007;
+pub const K_MSG_TYPE_EXECUTION_CONFIRM : u32 = 200102;
+pub const K_MSG_TYPE_EXECUTION_TRADE : u32 = 200115;
+pub const K_MSG_TYPE_CANCEL_REJECT : u32 = 290008;
+
+// 现货集中竞价
+pub const APPL_ID_SPOT : [u8;3] = *b"010";
+pub const SECURITY_ID_SOURCE_SZSE : [u8;4] = *b"102 ";
+
+const HEADER_LEN : usize = 8;
+const TAIL_LEN : usize = 4;
+const MAX_BODY_LEN : usize = 4096;
+// 价格为 N13(4), 数量为 N15(2)
+const PRICE_DECIMALS : u32 = 4;
+const QTY_FACTOR : i64 = 100;
+const K_SZSE_BUY : u8 = b'1';
+const K_SZSE_SELL : u8 = b'2';
+const K_ORD_TYPE_LIMIT : u8 = b'2';
+// LocalTimeStamp 为北京时间
+const LOCAL_OFFSET_SECS : u64 = 8 * 3600;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SzseError {
+    // 消息格式错误, BodyLength 或 Checksum 不符
+    Garbled(String),
+    UnknownMsgType(u32),
+    InvalidField(&'static str),
+    InvalidConfig(String),
+}
+
+impl fmt::Display for SzseError {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SzseError::Garbled(msg) => write!(f, "garbled message: {}", msg),
+            SzseError::UnknownMsgType(msg_type) => write!(f, "unknown message type {}", msg_type),
+            SzseError::InvalidField(name) => write!(f, "value is incorrect for {}", name),
+            SzseError::InvalidConfig(msg) => write!(f, "invalid SZSE config: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SzseError {}
+
+// 定长字段的编解码
+trait Field : Sized {
+    fn put(&self, buffer : &mut Vec<u8>);
+    fn get(body : &mut &[u8]) -> Result<Self, SzseError>;
+}
+
+fn take<'a>(body : &mut &'a [u8], len : usize) -> Result<&'a [u8], SzseError> {
+    if body.len() < len {
+        return Err(SzseError::Garbled("body too short".to_string()));
+    }
+    let (value, rest) = body.split_at(len);
+    *body = rest;
+    Ok(value)
+}
+
+impl<const N : usize> Field for [u8;N] {
+    fn put(&self, buffer : &mut Vec<u8>) {
+        buffer.extend_from_slice(self);
+    }
+    fn get(body : &mut &[u8]) -> Result<Self, SzseError> {
+        Ok(take(body, N)?.try_into().unwrap())
+    }
+}
+
+impl Field for u8 {
+    fn put(&self, buffer : &mut Vec<u8>) {
+        buffer.push(*self);
+    }
+    fn get(body : &mut &[u8]) -> Result<Self, SzseError> {
+        Ok(take(body, 1)?[0])
+    }
+}
+
+impl Field for u16 {
+    fn put(&self, buffer : &mut Vec<u8>) {
+        buffer.extend_from_slice(&self.to_be_bytes());
+    }
+    fn get(body : &mut &[u8]) -> Result<Self, SzseError> {
+        Ok(u16::from_be_bytes(Field::get(body)?))
+    }
+}
+
+impl Field for i32 {
+    fn put(&self, buffer : &mut Vec<u8>) {
+        buffer.extend_from_slice(&self.to_be_bytes());
+    }
+    fn get(body : &mut &[u8]) -> Result<Self, SzseError> {
+        Ok(i32::from_be_bytes(Field::get(body)?))
+    }
+}
+
+impl Field for i64 {
+    fn put(&self, buffer : &mut Vec<u8>) {
+        buffer.extend_from_slice(&self.to_be_bytes());
+    }
+    fn get(body : &mut &[u8]) -> Result<Self, SzseError> {
+        Ok(i64::from_be_bytes(Field::get(body)?))
+    }
+}
+
+pub trait SzseBody : Sized {
+    const MSG_TYPE : u32;
+    fn write_body(&self, buffer : &mut Vec<u8>);
+    fn read_body(body : &mut &[u8]) -> Result<Self, SzseError>;
+}
+
+// 新订单 100101, 扩展字段为现货集中竞价业务的部分
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SzseNewOrder {
+    pub appl_id : [u8;3],
+    pub submitting_pbu_id : PBUID,
+    pub security_id : SecurityID,
+    pub security_id_source : [u8;4],
+    pub owner_type : u16,
+    pub clearing_firm : [u8;2],
+    pub transact_time : i64,
+    pub user_info : [u8;8],
+    pub cl_ord_id : ClOrdID,
+    pub account_id : [u8;12],
+    pub branch_id : [u8;4],
+    pub order_restrictions : [u8;4],
+    pub side : u8,
+    pub ord_type : u8,
+    pub order_qty : i64,
+    pub price : i64,
+    pub stop_px : i64,
+    pub min_qty : i64,
+    pub max_price_levels : u16,
+    pub time_in_force : u8,
+}
+
+impl SzseBody for SzseNewOrder {
+    const MSG_TYPE : u32 = K_MSG_TYPE_NEW_ORDER;
+    fn write_body(&self, buffer : &mut Vec<u8>) {
+        self.appl_id.put(buffer);
+        self.submitting_pbu_id.put(buffer);
+        self.security_id.put(buffer);
+        self.security_id_source.put(buffer);
+        self.owner_type.put(buffer);
+        self.clearing_firm.put(buffer);
+        self.transact_time.put(buffer);
+        self.user_info.put(buffer);
+        self.cl_ord_id.put(buffer);
+        self.account_id.put(buffer);
+        self.branch_id.put(buffer);
+        self.order_restrictions.put(buffer);
+        self.side.put(buffer);
+        self.ord_type.put(buffer);
+        self.order_qty.put(buffer);
+        self.price.put(buffer);
+        self.stop_px.put(buffer);
+        self.min_qty.put(buffer);
+        self.max_price_levels.put(buffer);
+        self.time_in_force.put(buffer);
+    }
+    fn read_body(body : &mut &[u8]) -> Result<Self, SzseError> {
+        Ok(SzseNewOrder {
+            appl_id : Field::get(body)?,
+            submitting_pbu_id : Field::get(body)?,
+            security_id : Field::get(body)?,
+            security_id_source : Field::get(body)?,
+            owner_type : Field::get(body)?,
+            clearing_firm : Field::get(body)?,
+            transact_time : Field::get(body)?,
+            user_info : Field::get(body)?,
+            cl_ord_id : Field::get(body)?,
+            account_id : Field::get(body)?,
+            branch_id : Field::get(body)?,
+            order_restrictions : Field::get(body)?,
+            side : Field::get(body)?,
+            ord_type : Field::get(body)?,
+            order_qty : Field::get(body)?,
+            price : Field::get(body)?,
+            stop_px : Field::get(body)?,
+            min_qty : Field::get(body)?,
+            max_price_levels : Field::get(body)?,
+            time_in_force : Field::get(body)?,
+        })
+    }
+}
+
+// 撤单 190007
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SzseCancelRequest {
+    pub appl_id : [u8;3],
+    pub submitting_pbu_id : PBUID,
+    pub security_id : SecurityID,
+    pub security_id_source : [u8;4],
+    pub owner_type : u16,
+    pub clearing_firm : [u8;2],
+    pub transact_time : i64,
+    pub user_info : [u8;8],
+    pub cl_ord_id : ClOrdID,
+    pub orig_cl_ord_id : ClOrdID,
+    pub side : u8,
+    pub order_id : [u8;16],
+    pub order_qty : i64,
+}
+
+impl SzseBody for SzseCancelRequest {
+    const MSG_TYPE : u32 = K_MSG_TYPE_CANCEL_REQUEST;
+    fn write_body(&self, buffer : &mut Vec<u8>) {
+        self.appl_id.put(buffer);
+        self.submitting_pbu_id.put(buffer);
+        self.security_id.put(buffer);
+        self.security_id_source.put(buffer);
+        self.owner_type.put(buffer);
+        self.clearing_firm.put(buffer);
+        self.transact_time.put(buffer);
+        self.user_info.put(buffer);
+        self.cl_ord_id.put(buffer);
+        self.orig_cl_ord_id.put(buffer);
+        self.side.put(buffer);
+        self.order_id.put(buffer);
+        self.order_qty.put(buffer);
+    }
+    fn read_body(body : &mut &[u8]) -> Result<Self, SzseError> {
+        Ok(SzseCancelRequest {
+            appl_id : Field::get(body)?,
+            submitting_pbu_id : Field::get(body)?,
+            security_id : Field::get(body)?,
+            security_id_source : Field::get(body)?,
+            owner_type : Field::get(body)?,
+            clearing_firm : Field::get(body)?,
+            transact_time : Field::get(body)?,
+            user_info : Field::get(body)?,
+            cl_ord_id : Field::get(body)?,
+            orig_cl_ord_id : Field::get(body)?,
+            side : Field::get(body)?,
+            order_id : Field::get(body)?,
+            order_qty : Field::get(body)?,
+        })
+    }
+}
+
+// 执行报告 200102: 订单确认、订单拒绝和撤单成功
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SzseExecutionConfirm {
+    pub partition_no : i32,
+    pub report_index : i64,
+    pub appl_id : [u8;3],
+    pub reporting_pbu_id : PBUID,
+    pub submitting_pbu_id : PBUID,
+    pub security_id : SecurityID,
+    pub security_id_source : [u8;4],
+    pub owner_type : u16,
+    pub clearing_firm : [u8;2],
+    pub transact_time : i64,
+    pub user_info : [u8;8],
+    pub order_id : [u8;16],
+    pub cl_ord_id : ClOrdID,
+    pub orig_cl_ord_id : ClOrdID,
+    pub exec_id : [u8;16],
+    pub exec_type : u8,
+    pub ord_status : u8,
+    pub ord_rej_reason : u16,
+    pub leaves_qty : i64,
+    pub cum_qty : i64,
+    pub side : u8,
+    pub ord_type : u8,
+    pub order_qty : i64,
+    pub price : i64,
+    pub account_id : [u8;12],
+    pub branch_id : [u8;4],
+    pub order_restrictions : [u8;4],
+}
+
+impl SzseBody for SzseExecutionConfirm {
+    const MSG_TYPE : u32 = K_MSG_TYPE_EXECUTION_CONFIRM;
+    fn write_body(&self, buffer : &mut Vec<u8>) {
+        self.partition_no.put(buffer);
+        self.report_index.put(buffer);
+        self.appl_id.put(buffer);
+        self.reporting_pbu_id.put(buffer);
+        self.submitting_pbu_id.put(buffer);
+        self.security_id.put(buffer);
+        self.security_id_source.put(buffer);
+        self.owner_type.put(buffer);
+        self.clearing_firm.put(buffer);
+        self.transact_time.put(buffer);
+        self.user_info.put(buffer);
+        self.order_id.put(buffer);
+        self.cl_ord_id.put(buffer);
+        self.orig_cl_ord_id.put(buffer);
+        self.exec_id.put(buffer);
+        self.exec_type.put(buffer);
+        self.ord_status.put(buffer);
+        self.ord_rej_reason.put(buffer);
+        self.leaves_qty.put(buffer);
+        self.cum_qty.put(buffer);
+        self.side.put(buffer);
+        self.ord_type.put(buffer);
+        self.order_qty.put(buffer);
+        self.price.put(buffer);
+        self.account_id.put(buffer);
+        self.branch_id.put(buffer);
+        self.order_restrictions.put(buffer);
+    }
+    fn read_body(body : &mut &[u8]) -> Result<Self, SzseError> {
+        Ok(SzseExecutionConfirm {
+            partition_no : Field::get(body)?,
+            report_index : Field::get(body)?,
+            appl_id : Field::get(body)?,
+            reporting_pbu_id : Field::get(body)?,
+            submitting_pbu_id : Field::get(body)?,
+            security_id : Field::get(body)?,
+            security_id_source : Field::get(body)?,
+            owner_type : Field::get(body)?,
+            clearing_firm : Field::get(body)?,
+            transact_time : Field::get(body)?,
+            user_info : Field::get(body)?,
+            order_id : Field::get(body)?,
+            cl_ord_id : Field::get(body)?,
+            orig_cl_ord_id : Field::get(body)?,
+            exec_id : Field::get(body)?,
+            exec_type : Field::get(body)?,
+            ord_status : Field::get(body)?,
+            ord_rej_reason : Field::get(body)?,
+            leaves_qty : Field::get(body)?,
+            cum_qty : Field::get(body)?,
+            side : Field::get(body)?,
+            ord_type : Field::get(body)?,
+            order_qty : Field::get(body)?,
+            price : Field::get(body)?,
+            account_id : Field::get(body)?,
+            branch_id : Field::get(body)?,
+            order_restrictions : Field::get(body)?,
+        })
+    }
+}
+
+// 成交执行报告 200115
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SzseExecutionTrade {
+    pub partition_no : i32,
+    pub report_index : i64,
+    pub appl_id : [u8;3],
+    pub reporting_pbu_id : PBUID,
+    pub submitting_pbu_id : PBUID,
+    pub security_id : SecurityID,
+    pub security_id_source : [u8;4],
+    pub owner_type : u16,
+    pub clearing_firm : [u8;2],
+    pub transact_time : i64,
+    pub user_info : [u8;8],
+    pub order_id : [u8;16],
+    pub cl_ord_id : ClOrdID,
+    pub exec_id : [u8;16],
+    pub exec_type : u8,
+    pub ord_status : u8,
+    pub last_px : i64,
+    pub last_qty : i64,
+    pub leaves_qty : i64,
+    pub cum_qty : i64,
+    pub side : u8,
+    pub account_id : [u8;12],
+    pub branch_id : [u8;4],
+}
+
+impl SzseBody for SzseExecutionTrade {
+    const MSG_TYPE : u32 = K_MSG_TYPE_EXECUTION_TRADE;
+    fn write_body(&self, buffer : &mut Vec<u8>) {
+        self.partition_no.put(buffer);
+        self.report_index.put(buffer);
+        self.appl_id.put(buffer);
+        self.reporting_pbu_id.put(buffer);
+        self.submitting_pbu_id.put(buffer);
+        self.security_id.put(buffer);
+        self.security_id_source.put(buffer);
+        self.owner_type.put(buffer);
+        self.clearing_firm.put(buffer);
+        self.transact_time.put(buffer);
+        self.user_info.put(buffer);
+        self.order_id.put(buffer);
+        self.cl_ord_id.put(buffer);
+        self.exec_id.put(buffer);
+        self.exec_type.put(buffer);
+        self.ord_status.put(buffer);
+        self.last_px.put(buffer);
+        self.last_qty.put(buffer);
+        self.leaves_qty.put(buffer);
+        self.cum_qty.put(buffer);
+        self.side.put(buffer);
+        self.account_id.put(buffer);
+        self.branch_id.put(buffer);
+    }
+    fn read_body(body : &mut &[u8]) -> Result<Self, SzseError> {
+        Ok(SzseExecutionTrade {
+            partition_no : Field::get(body)?,
+            report_index : Field::get(body)?,
+            appl_id : Field::get(body)?,
+            reporting_pbu_id : Field::get(body)?,
+            submitting_pbu_id : Field::get(body)?,
+            security_id : Field::get(body)?,
+            security_id_source : Field::get(body)?,
+            owner_type : Field::get(body)?,
+            clearing_firm : Field::get(body)?,
+            transact_time : Field::get(body)?,
+            user_info : Field::get(body)?,
+            order_id : Field::get(body)?,
+            cl_ord_id : Field::get(body)?,
+            exec_id : Field::get(body)?,
+            exec_type : Field::get(body)?,
+            ord_status : Field::get(body)?,
+            last_px : Field::get(body)?,
+            last_qty : Field::get(body)?,
+            leaves_qty : Field::get(body)?,
+            cum_qty : Field::get(body)?,
+            side : Field::get(body)?,
+            account_id : Field::get(body)?,
+            branch_id : Field::get(body)?,
+        })
+    }
+}
+
+// 撤单失败 290008
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SzseCancelReject {
+    pub partition_no : i32,
+    pub report_index : i64,
+    pub appl_id : [u8;3],
+    pub reporting_pbu_id : PBUID,
+    pub submitting_pbu_id : PBUID,
+    pub security_id : SecurityID,
+    pub security_id_source : [u8;4],
+    pub owner_type : u16,
+    pub clearing_firm : [u8;2],
+    pub transact_time : i64,
+    pub user_info : [u8;8],
+    pub cl_ord_id : ClOrdID,
+    pub orig_cl_ord_id : ClOrdID,
+    pub side : u8,
+    pub ord_status : u8,
+    pub cxl_rej_reason : u16,
+    pub reject_text : [u8;16],
+    pub order_id : [u8;16],
+}
+
+impl SzseBody for SzseCancelReject {
+    const MSG_TYPE : u32 = K_MSG_TYPE_CANCEL_REJECT;
+    fn write_body(&self, buffer : &mut Vec<u8>) {
+        self.partition_no.put(buffer);
+        self.report_index.put(buffer);
+        self.appl_id.put(buffer);
+        self.reporting_pbu_id.put(buffer);
+        self.submitting_pbu_id.put(buffer);
+        self.security_id.put(buffer);
+        self.security_id_source.put(buffer);
+        self.owner_type.put(buffer);
+        self.clearing_firm.put(buffer);
+        self.transact_time.put(buffer);
+        self.user_info.put(buffer);
+        self.cl_ord_id.put(buffer);
+        self.orig_cl_ord_id.put(buffer);
+        self.side.put(buffer);
+        self.ord_status.put(buffer);
+        self.cxl_rej_reason.put(buffer);
+        self.reject_text.put(buffer);
+        self.order_id.put(buffer);
+    }
+    fn read_body(body : &mut &[u8]) -> Result<Self, SzseError> {
+        Ok(SzseCancelReject {
+            partition_no : Field::get(body)?,
+            report_index : Field::get(body)?,
+            appl_id : Field::get(body)?,
+            reporting_pbu_id : Field::get(body)?,
+            submitting_pbu_id : Field::get(body)?,
+            security_id : Field::get(body)?,
+            security_id_source : Field::get(body)?,
+            owner_type : Field::get(body)?,
+            clearing_firm : Field::get(body)?,
+            transact_time : Field::get(body)?,
+            user_info : Field::get(body)?,
+            cl_ord_id : Field::get(body)?,
+            orig_cl_ord_id : Field::get(body)?,
+            side : Field::get(body)?,
+            ord_status : Field::get(body)?,
+            cxl_rej_reason : Field::get(body)?,
+            reject_text : Field::get(body)?,
+            order_id : Field::get(body)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SzseMessage {
+    NewOrder(SzseNewOrder),
+    CancelRequest(SzseCancelRequest),
+    ExecutionConfirm(SzseExecutionConfirm),
+    ExecutionTrade(SzseExecutionTrade),
+    CancelReject(SzseCancelReject),
+}
+
+impl SzseMessage {
+    pub fn msg_type(&self) -> u32 {
+        match self {
+            SzseMessage::NewOrder(_) => K_MSG_TYPE_NEW_ORDER,
+            SzseMessage::CancelRequest(_) => K_MSG_TYPE_CANCEL_REQUEST,
+            SzseMessage::ExecutionConfirm(_) => K_MSG_TYPE_EXECUTION_CONFIRM,
+            SzseMessage::ExecutionTrade(_) => K_MSG_TYPE_EXECUTION_TRADE,
+            SzseMessage::CancelReject(_) => K_MSG_TYPE_CANCEL_REJECT,
+        }
+    }
+
+    pub fn encode_into(&self, buffer : &mut Vec<u8>) {
+        match self {
+            SzseMessage::NewOrder(message) => encode_into(buffer, message),
+            SzseMessage::CancelRequest(message) => encode_into(buffer, message),
+            SzseMessage::ExecutionConfirm(message) => encode_into(buffer, message),
+            SzseMessage::ExecutionTrade(message) => encode_into(buffer, message),
+            SzseMessage::CancelReject(message) => encode_into(buffer, message),
+        }
+    }
+}
+
+// 消息头和消息体所有字节之和对 256 取模
+pub fn checksum(bytes : &[u8]) -> u32 {
+    bytes.iter().fold(0u8, |sum, c| sum.wrapping_add(*c)) as u32
+}
+
+// 编码到 buffer 末尾
+pub fn encode_into<T : SzseBody>(buffer : &mut Vec<u8>, message : &T) {
+    let start = buffer.len();
+    buffer.extend_from_slice(&T::MSG_TYPE.to_be_bytes());
+    buffer.extend_from_slice(&[0u8;4]);
+    message.write_body(buffer);
+    let body_len = (buffer.len() - start - HEADER_LEN) as u32;
+    buffer[start + 4..start + HEADER_LEN].copy_from_slice(&body_len.to_be_bytes());
+    let checksum = checksum(&buffer[start..]);
+    buffer.extend_from_slice(&checksum.to_be_bytes());
+}
+
+pub fn encode<T : SzseBody>(message : &T) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(256);
+    encode_into(&mut buffer, message);
+    buffer
+}
+
+pub fn decode(bytes : &[u8]) -> Result<SzseMessage, SzseError> {
+    if bytes.len() < HEADER_LEN + TAIL_LEN {
+        return Err(SzseError::Garbled(format!("message of {} bytes", bytes.len())));
+    }
+    let msg_type = u32::from_be_bytes(bytes[..4].try_into().unwrap());
+    let body_len = u32::from_be_bytes(bytes[4..HEADER_LEN].try_into().unwrap()) as usize;
+    if HEADER_LEN + body_len + TAIL_LEN != bytes.len() {
+        return Err(SzseError::Garbled(format!("BodyLength {} of {} bytes", body_len, bytes.len())));
+    }
+    let (message, tail) = bytes.split_at(HEADER_LEN + body_len);
+    let expected = u32::from_be_bytes(tail.try_into().unwrap());
+    if checksum(message) != expected {
+        return Err(SzseError::Garbled(format!("Checksum {} expected {}", checksum(message), expected)));
+    }
+
+    let mut body = &message[HEADER_LEN..];
+    let message = match msg_type {
+        K_MSG_TYPE_NEW_ORDER => SzseMessage::NewOrder(SzseBody::read_body(&mut body)?),
+        K_MSG_TYPE_CANCEL_REQUEST => SzseMessage::CancelRequest(SzseBody::read_body(&mut body)?),
+        K_MSG_TYPE_EXECUTION_CONFIRM => SzseMessage::ExecutionConfirm(SzseBody::read_body(&mut body)?),
+        K_MSG_TYPE_EXECUTION_TRADE => SzseMessage::ExecutionTrade(SzseBody::read_body(&mut body)?),
+        K_MSG_TYPE_CANCEL_REJECT => SzseMessage::CancelReject(SzseBody::read_body(&mut body)?),
+        msg_type => return Err(SzseError::UnknownMsgType(msg_type)),
+    };
+    if !body.is_empty() {
+        return Err(SzseError::Garbled(format!("{} trailing bytes", body.len())));
+    }
+    Ok(message)
+}
+
+// 从字节流中按 BodyLength 切出完整消息, 不做校验
+pub struct SzseReader<R> {
+    reader : R,
+}
+
+impl<R : Read> SzseReader<R> {
+    pub fn new(reader : R) -> SzseReader<R> {
+        SzseReader { reader }
+    }
+
+    // 对端关闭连接时返回 None
+    pub fn read_message(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut header = [0u8;HEADER_LEN];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => {},
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let body_len = u32::from_be_bytes(header[4..].try_into().unwrap()) as usize;
+        if body_len > MAX_BODY_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("BodyLength {}", body_len)));
+        }
+        let mut message = vec![0u8; HEADER_LEN + body_len + TAIL_LEN];
+        message[..HEADER_LEN].copy_from_slice(&header);
+        self.reader.read_exact(&mut message[HEADER_LEN..])?;
+        Ok(Some(message))
+    }
+}
+
+// YYYYMMDDHHMMSSsss, 北京时间
+pub fn local_timestamp(time : SystemTime) -> i64 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() + LOCAL_OFFSET_SECS;
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs_of_day = (secs % 86400) as i64;
+    let date = year * 10000 + month as i64 * 100 + day as i64;
+    let time = secs_of_day / 3600 * 10000 + secs_of_day / 60 % 60 * 100 + secs_of_day % 60;
+    (date * 1000000 + time) * 1000 + since_epoch.subsec_millis() as i64
+}
+
+fn szse_side(side : Side) -> u8 {
+    match side {
+        K_BUY => K_SZSE_BUY,
+        K_SELL => K_SZSE_SELL,
+        // 被拒绝的委托原样带回
+        side => side as u8,
+    }
+}
+
+// OrderID / ExecID 为 char[16], 十进制表示
+fn id_field(id : u128) -> [u8;16] {
+    to_array(&id.to_string())
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SzseConfig {
+    // 各证券 Price 的小数位数, 不超过 4
+    pub price_scales : BTreeMap<SecurityID, u32>,
+    pub default_price_scale : u32,
+}
+
+impl SzseConfig {
+    // 价格在接口上为 N13(4), 小数位数超过 4 的价格无法表示
+    pub fn validate(&self) -> Result<(), SzseError> {
+        if self.default_price_scale > PRICE_DECIMALS {
+            return Err(SzseError::InvalidConfig(format!("default price scale {} exceeds {}", self.default_price_scale, PRICE_DECIMALS)));
+        }
+        for (security_id, scale) in self.price_scales.iter() {
+            if *scale > PRICE_DECIMALS {
+                return Err(SzseError::InvalidConfig(format!("price scale {} of {} exceeds {}",
+                    scale, String::from_utf8_lossy(security_id).trim_end(), PRICE_DECIMALS)));
+            }
+        }
+        Ok(())
+    }
+}
+
+// 深交所消息与 NewOrder / CancelRequest 及 exe 阶段报告之间的转换
+pub struct SzseCodec {
+    config : SzseConfig,
+}
+
+impl SzseCodec {
+    pub fn new(config : SzseConfig) -> Result<SzseCodec, SzseError> {
+        config.validate()?;
+        Ok(SzseCodec { config })
+    }
+
+    pub fn config(&self) -> &SzseConfig {
+        &self.config
+    }
+
+    pub fn price_scale(&self, security_id : &SecurityID) -> u32 {
+        self.config.price_scales.get(security_id).copied().unwrap_or(self.config.default_price_scale)
+    }
+
+    fn price_factor(&self, security_id : &SecurityID) -> i64 {
+        10i64.pow(PRICE_DECIMALS - self.price_scale(security_id))
+    }
+
+    fn wire_price(&self, security_id : &SecurityID, price : Price) -> i64 {
+        price * self.price_factor(security_id)
+    }
+
+    fn wire_qty(qty : Qty) -> i64 {
+        qty as i64 * QTY_FACTOR
+    }
+
+    // 收到的委托, order_id 由调用方分配
+    pub fn inbound(&self, message : &SzseMessage) -> Result<Option<Inbound>, SzseError> {
+        match message {
+            SzseMessage::NewOrder(order) => Ok(Some(Inbound::NewOrder(self.new_order(order)?))),
+            SzseMessage::CancelRequest(cancel) => Ok(Some(Inbound::CancelRequest(self.cancel_request(cancel)))),
+            _ => Ok(None),
+        }
+    }
+
+    // 只支持限价委托, 价格须为该证券价格精度的整数倍, 数量须为整数股
+    pub fn new_order(&self, order : &SzseNewOrder) -> Result<NewOrder, SzseError> {
+        let side = match order.side {
+            K_SZSE_BUY => K_BUY,
+            K_SZSE_SELL => K_SELL,
+            _ => return Err(SzseError::InvalidField("Side")),
+        };
+        if order.ord_type != K_ORD_TYPE_LIMIT {
+            return Err(SzseError::InvalidField("OrdType"));
+        }
+        let factor = self.price_factor(&order.security_id);
+        if order.price % factor != 0 {
+            return Err(SzseError::InvalidField("Price"));
+        }
+        if order.order_qty < 0 || order.order_qty % QTY_FACTOR != 0 {
+            return Err(SzseError::InvalidField("OrderQty"));
+        }
+        Ok(NewOrder {
+            order_id : 0,
+            seq_num : 0,
+            pbu_id : order.submitting_pbu_id,
+            cl_ord_id : order.cl_ord_id,
+            security_id : order.security_id,
+            side,
+            price : order.price / factor,
+            qty : (order.order_qty / QTY_FACTOR) as Qty,
+        })
+    }
+
+    pub fn cancel_request(&self, cancel : &SzseCancelRequest) -> CancelRequest {
+        CancelRequest {
+            order_id : 0,
+            seq_num : 0,
+            pbu_id : cancel.submitting_pbu_id,
+            cl_ord_id : cancel.cl_ord_id,
+            orig_cl_ord_id : cancel.orig_cl_ord_id,
+            security_id : cancel.security_id,
+        }
+    }
+
+    // 券商侧把 NewOrder 转为 100101
+    pub fn szse_new_order(&self, order : &NewOrder, transact_time : i64) -> SzseNewOrder {
+        SzseNewOrder {
+            appl_id : APPL_ID_SPOT,
+            submitting_pbu_id : order.pbu_id,
+            security_id : order.security_id,
+            security_id_source : SECURITY_ID_SOURCE_SZSE,
+            owner_type : 1,
+            clearing_firm : [b' ';2],
+            transact_time,
+            user_info : [b' ';8],
+            cl_ord_id : order.cl_ord_id,
+            account_id : [b' ';12],
+            branch_id : [b' ';4],
+            order_restrictions : [b' ';4],
+            side : szse_side(order.side),
+            ord_type : K_ORD_TYPE_LIMIT,
+            order_qty : SzseCodec::wire_qty(order.qty),
+            price : self.wire_price(&order.security_id, order.price),
+            stop_px : 0,
+            min_qty : 0,
+            max_price_levels : 0,
+            time_in_force : b'0',
+        }
+    }
+
+    pub fn szse_cancel_request(&self, cancel : &CancelRequest, transact_time : i64) -> SzseCancelRequest {
+        SzseCancelRequest {
+            appl_id : APPL_ID_SPOT,
+            submitting_pbu_id : cancel.pbu_id,
+            security_id : cancel.security_id,
+            security_id_source : SECURITY_ID_SOURCE_SZSE,
+            owner_type : 1,
+            clearing_firm : [b' ';2],
+            transact_time,
+            user_info : [b' ';8],
+            cl_ord_id : cancel.cl_ord_id,
+            orig_cl_ord_id : cancel.orig_cl_ord_id,
+            side : b' ',
+            order_id : [b' ';16],
+            order_qty : 0,
+        }
+    }
+
+    // 成交对应 200115, 其余执行报告对应 200102; TradeCaptureReport 没有对应的消息.
+    // PartitionNo 与 ReportIndex 取自报告在 ReportLog 中的流编号与流序号
+    pub fn report(&self, report : &OutputMessage, stream : StreamID, seq : u64, transact_time : i64) -> Option<SzseMessage> {
+        match report {
+            OutputMessage::ExecutionReport(report) => Some(self.execution_report(report, stream, seq, transact_time)),
+            OutputMessage::CancelReject(report) => Some(SzseMessage::CancelReject(self.cancel_reject(report, stream, seq, transact_time))),
+            OutputMessage::TradeCaptureReport(_) => None,
+        }
+    }
+
+    pub fn execution_report(&self, report : &ExecutionReport, stream : StreamID, seq : u64, transact_time : i64) -> SzseMessage {
+        if report.exec_type == K_EXEC_TYPE_TRADE {
+            return SzseMessage::ExecutionTrade(SzseExecutionTrade {
+                partition_no : stream as i32,
+                report_index : seq as i64,
+                appl_id : APPL_ID_SPOT,
+                reporting_pbu_id : report.pbu_id,
+                submitting_pbu_id : report.pbu_id,
+                security_id : report.security_id,
+                security_id_source : SECURITY_ID_SOURCE_SZSE,
+                owner_type : 1,
+                clearing_firm : [b' ';2],
+                transact_time,
+                user_info : [b' ';8],
+                order_id : id_field(report.order_id),
+                cl_ord_id : report.cl_ord_id,
+                exec_id : id_field(report.exec_id),
+                exec_type : report.exec_type as u8,
+                ord_status : report.ord_status as u8,
+                last_px : self.wire_price(&report.security_id, report.last_px),
+                last_qty : SzseCodec::wire_qty(report.last_qty),
+                leaves_qty : SzseCodec::wire_qty(report.leaves_qty),
+                cum_qty : SzseCodec::wire_qty(report.cum_qty),
+                side : szse_side(report.side),
+                account_id : [b' ';12],
+                branch_id : [b' ';4],
+            });
+        }
+        SzseMessage::ExecutionConfirm(SzseExecutionConfirm {
+            partition_no : stream as i32,
+            report_index : seq as i64,
+            appl_id : APPL_ID_SPOT,
+            reporting_pbu_id : report.pbu_id,
+            submitting_pbu_id : report.pbu_id,
+            security_id : report.security_id,
+            security_id_source : SECURITY_ID_SOURCE_SZSE,
+            owner_type : 1,
+            clearing_firm : [b' ';2],
+            transact_time,
+            user_info : [b' ';8],
+            order_id : id_field(report.order_id),
+            cl_ord_id : report.cl_ord_id,
+            orig_cl_ord_id : report.orig_cl_ord_id,
+            exec_id : id_field(report.exec_id),
+            exec_type : report.exec_type as u8,
+            ord_status : report.ord_status as u8,
+            ord_rej_reason : report.rejected_reason as u16,
+            leaves_qty : SzseCodec::wire_qty(report.leaves_qty),
+            cum_qty : SzseCodec::wire_qty(report.cum_qty),
+            side : szse_side(report.side),
+            ord_type : K_ORD_TYPE_LIMIT,
+            order_qty : SzseCodec::wire_qty(report.qty),
+            price : self.wire_price(&report.security_id, report.price),
+            account_id : [b' ';12],
+            branch_id : [b' ';4],
+            order_restrictions : [b' ';4],
+        })
+    }
+
+    pub fn cancel_reject(&self, report : &CancelReject, stream : StreamID, seq : u64, transact_time : i64) -> SzseCancelReject {
+        SzseCancelReject {
+            partition_no : stream as i32,
+            report_index : seq as i64,
+            appl_id : APPL_ID_SPOT,
+            reporting_pbu_id : report.pbu_id,
+            submitting_pbu_id : report.pbu_id,
+            security_id : report.security_id,
+            security_id_source : SECURITY_ID_SOURCE_SZSE,
+            owner_type : 1,
+            clearing_firm : [b' ';2],
+            transact_time,
+            user_info : [b' ';8],
+            cl_ord_id : report.cl_ord_id,
+            orig_cl_ord_id : report.orig_cl_ord_id,
+            side : b' ',
+            ord_status : K_ORD_STATUS_REJECT as u8,
+            cxl_rej_reason : report.rejected_reason as u16,
+            reject_text : [b' ';16],
+            order_id : [b' ';16],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn codec() -> SzseCodec {
+        let mut config = SzseConfig::default();
+        config.price_scales.insert(to_array("000001"), 2);
+        SzseCodec::new(config).unwrap()
+    }
+
+    fn order() -> NewOrder {
+        NewOrder {
+            order_id : 0,
+            seq_num : 0,
+            pbu_id : to_array("PBU001"),
+            cl_ord_id : to_array("C1"),
+            security_id : to_array("000001"),
+            side : K_SELL,
+            price : 1050,
+            qty : 300,
+        }
+    }
+
+    #[test]
+    fn new_order_layout() {
+        let codec = codec();
+        let message = codec.szse_new_order(&order(), 20240102093000123);
+        let bytes = encode(&message);
+        assert_eq!(&bytes[..4], &100101u32.to_be_bytes());
+        assert_eq!(u32::from_be_bytes(bytes[4..8].try_into().unwrap()) as usize, bytes.len() - 12);
+        assert_eq!(bytes.len(), 8 + 108 + 4);
+        assert_eq!(&bytes[8..11], b"010");
+        assert_eq!(&bytes[11..17], b"PBU001");
+        // 10.50 元按 N13(4) 编码, 300 股按 N15(2) 编码
+        assert_eq!(message.price, 105000);
+        assert_eq!(message.order_qty, 30000);
+        assert_eq!(message.side, b'2');
+
+        assert_eq!(decode(&bytes), Ok(SzseMessage::NewOrder(message.clone())));
+        let decoded = codec.new_order(&message).unwrap();
+        assert_eq!((decoded.pbu_id, decoded.cl_ord_id, decoded.security_id), (order().pbu_id, order().cl_ord_id, order().security_id));
+        assert_eq!((decoded.side, decoded.price, decoded.qty), (K_SELL, 1050, 300));
+
+        let mut corrupted = bytes.clone();
+        corrupted[20] ^= 1;
+        assert!(matches!(decode(&corrupted), Err(SzseError::Garbled(_))));
+        assert!(matches!(decode(&bytes[..bytes.len() - 1]), Err(SzseError::Garbled(_))));
+
+        let mut odd_price = message.clone();
+        odd_price.price += 1;
+        assert_eq!(codec.new_order(&odd_price).unwrap_err(), SzseError::InvalidField("Price"));
+        let mut market = message;
+        market.ord_type = b'1';
+        assert_eq!(codec.new_order(&market).unwrap_err(), SzseError::InvalidField("OrdType"));
+    }
+
+    #[test]
+    fn reports() {
+        let codec = codec();
+        let mut report = ExecutionReport::new(&order());
+        report.order_id = 7;
+        report.exec_id = 12;
+        report.exec_type = K_EXEC_TYPE_TRADE;
+        report.ord_status = K_ORD_STATUS_PARTIALLY_FILLED;
+        report.last_px = 1050;
+        report.last_qty = 100;
+        report.cum_qty = 100;
+        report.leaves_qty = 200;
+        let trade = match codec.execution_report(&report, 1, 5, 0) {
+            SzseMessage::ExecutionTrade(trade) => trade,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(&trade.order_id, b"7               ");
+        assert_eq!((trade.last_px, trade.last_qty, trade.leaves_qty), (105000, 10000, 20000));
+        assert_eq!((trade.partition_no, trade.report_index), (1, 5));
+        assert_eq!(decode(&encode(&trade)), Ok(SzseMessage::ExecutionTrade(trade)));
+
+        report.exec_type = K_EXEC_TYPE_CANCELLED;
+        let message = codec.execution_report(&report, 1, 6, 0);
+        assert_eq!(message.msg_type(), K_MSG_TYPE_EXECUTION_CONFIRM);
+        assert!(matches!(&message, SzseMessage::ExecutionConfirm(confirm) if (confirm.partition_no, confirm.report_index) == (1, 6)));
+        let mut bytes = Vec::new();
+        message.encode_into(&mut bytes);
+        assert_eq!(decode(&bytes), Ok(message));
+
+        let reject = CancelReject {
+            order_id : 8,
+            seq_num : 0,
+            pbu_id : to_array("PBU001"),
+            cl_ord_id : to_array("C2"),
+            orig_cl_ord_id : to_array("C1"),
+            security_id : to_array("000001"),
+            rejected_reason : CancelReasonCode::OrderNotExisted,
+        };
+        let message = codec.report(&OutputMessage::CancelReject(reject), 0, 7, 0).unwrap();
+        let bytes = [encode_message(&message), encode_message(&message)].concat();
+        let mut reader = SzseReader::new(&bytes[..]);
+        assert_eq!(decode(&reader.read_message().unwrap().unwrap()), Ok(message.clone()));
+        assert_eq!(decode(&reader.read_message().unwrap().unwrap()), Ok(message));
+        assert_eq!(reader.read_message().unwrap(), None);
+    }
+
+    fn encode_message(message : &SzseMessage) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        message.encode_into(&mut buffer);
+        buffer
+    }
+
+    #[test]
+    fn price_scale_limit() {
+        let mut config = SzseConfig::default();
+        config.price_scales.insert(to_array("000001"), 5);
+        assert!(matches!(SzseCodec::new(config.clone()), Err(SzseError::InvalidConfig(_))));
+        config.price_scales.insert(to_array("000001"), 4);
+        assert!(SzseCodec::new(config.clone()).is_ok());
+        config.default_price_scale = 6;
+        assert!(matches!(SzseCodec::new(config), Err(SzseError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn timestamp() {
+        // 2024-01-01 16:30:00.250 UTC
+        let time = UNIX_EPOCH + Duration::from_millis(1704126600250);
+        assert_eq!(local_timestamp(time), 20240102003000250);
+    }
+}
@@ -1,4 +1,4 @@
-use serde::Serialize;
+use serde::{Serialize, Deserialize};
 
 pub type Qty = u64;
 pub type Price = i64;
@@ -8,6 +8,8 @@ pub type PBUID = [u8;6];
 pub type SecurityID = [u8;8];
 pub type Side = char;
 pub type ExecID = u128;
+// 全局输入序号
+pub type SeqNum = u64;
 
 pub const K_BUY: char = 'B';
 pub const K_SELL: char = 'S';
@@ -25,13 +27,29 @@ pub const K_ORD_STATUS_REJECT: char = '8';
 
 
 
-#[derive(PartialEq, Eq, Serialize)]
-#[derive(Debug)]
+#[derive(PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy)]
 pub enum CancelReasonCode {
     Passed = 0,
     Duplicated = 1,
     InvalidSecurity = 2,
     OrderNotExisted = 3,
+    InvalidSide = 4,
+    InvalidPrice = 5,
+    InvalidQty = 6,
+    InvalidSecurityID = 7,
+    InvalidPBUID = 8,
+    InvalidClOrdID = 9,
+    PriceOutOfRange = 10,
+    InvalidPriceTick = 11,
+}
+
+// 证券的涨跌停价格与最小价位变动单位
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriceLimit {
+    pub min_price : Price,
+    pub max_price : Price,
+    pub tick : Price,
 }
 
 pub fn to_array<const N : usize>(s : &str) -> [u8;N] {
@@ -46,17 +64,3 @@ pub fn to_array<const N : usize>(s : &str) -> [u8;N] {
     }
     result
 }
-pub struct ExeSender {
-    pub count : u32,
-    pub bytes : usize,
-}
-
-impl ExeSender {
-    pub fn new() -> ExeSender {
-        ExeSender { count: 0, bytes : 0 }
-    }
-    pub fn send(&mut self, buffer : Vec<u8>) {
-        self.count += 1;
-        self.bytes += buffer.len();
-    }
-}
\ No newline at end of file
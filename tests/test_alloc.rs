@@ -0,0 +1,108 @@
+use trading::engin::{Engin, EnginConfig};
+use trading::messages::*;
+use trading::types::*;
+use trading::report_sink::CountingSink;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAlloc;
+
+static ALLOCATIONS : AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout : Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr : *mut u8, layout : Layout) {
+        System.dealloc(ptr, layout)
+    }
+    unsafe fn realloc(&self, ptr : *mut u8, layout : Layout, new_size : usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL : CountingAlloc = CountingAlloc;
+
+const ROUNDS : u64 = 2000;
+
+fn order(id : u64, side : Side, price : Price) -> NewOrder {
+    NewOrder {
+        order_id : From::from(id),
+        seq_num : 0,
+        pbu_id : to_array("PBU001"),
+        cl_ord_id : to_array(&id.to_string()),
+        security_id : to_array("SEC001"),
+        side,
+        price,
+        qty : 10,
+    }
+}
+
+fn cancel(id : u64, orig_id : u64) -> CancelRequest {
+    CancelRequest {
+        order_id : From::from(id),
+        seq_num : 0,
+        pbu_id : to_array("PBU001"),
+        cl_ord_id : to_array(&id.to_string()),
+        orig_cl_ord_id : to_array(&orig_id.to_string()),
+        security_id : to_array("SEC001"),
+    }
+}
+
+// 每轮: 一对完全成交的买卖委托, 再挂一笔买单并撤销, 共 4 条消息
+fn run(pooled : bool, rounds : u64) -> (usize, Engin) {
+    let messages : Vec<(Option<NewOrder>, Option<CancelRequest>)> = (0..rounds).flat_map(|i| {
+        let id = i * 4 + 1;
+        [
+            (Some(order(id, K_BUY, 100)), None),
+            (Some(order(id + 1, K_SELL, 100)), None),
+            (Some(order(id + 2, K_BUY, 90)), None),
+            (None, Some(cancel(id + 3, id + 2))),
+        ]
+    }).collect();
+
+    // 统计引擎的整个生命周期, 各阶段线程启动时的分配不会因调度早晚落到统计范围之外
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let config = EnginConfig { ingress_capacity : 64, queue_capacity : 64, ..EnginConfig::default() };
+    let mut engin = Engin::with_config(CountingSink::new(), config);
+    for message in messages {
+        let task = match message {
+            (Some(order), _) if pooled => PreProcessorTask::NewOrder(engin.alloc_new_order(order)),
+            (Some(order), _) => PreProcessorTask::NewOrder(Box::new(order)),
+            (None, Some(cancel_request)) if pooled => PreProcessorTask::CancelRequest(engin.alloc_cancel_request(cancel_request)),
+            (None, Some(cancel_request)) => PreProcessorTask::CancelRequest(Box::new(cancel_request)),
+            (None, None) => unreachable!(),
+        };
+        engin.process(task).unwrap();
+    }
+    let sender = engin.close().unwrap();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    assert_eq!(sender.count as u64, rounds * 7);
+    (allocations, engin)
+}
+
+#[test]
+fn test_pooled_allocations() {
+    // 创建与关闭引擎的分配: 两个池预先分配的对象、队列与线程; 线程启动时的分配与测试框架是否捕获输出有关, 不固定
+    let (empty, _) = run(true, 0);
+    let (boxed, _) = run(false, ROUNDS);
+    let (pooled, engin) = run(true, ROUNDS);
+
+    let messages = (ROUNDS * 4) as usize;
+    println!("allocations per message: boxed {:.2}, pooled {:.2}",
+        (boxed - empty) as f64 / messages as f64, (pooled - empty) as f64 / messages as f64);
+
+    assert_eq!(engin.new_order_pool_stats().allocated, 0);
+    assert_eq!(engin.new_order_pool_stats().reused, ROUNDS * 3);
+    assert_eq!(engin.cancel_request_pool_stats().allocated, 0);
+    assert_eq!(engin.cancel_request_pool_stats().reused, ROUNDS);
+    // 使用池时每条消息至少少分配一个 Box, 留一点余量给线程调度造成的差异
+    assert!(boxed + 16 >= pooled + messages);
+    // 撮合与挂单本身的分配 (Rc<NewOrderForBook>、新价位、成交结果的 Vec) 平均每条消息不超过 3 次, 报告编码不应再逐条分配
+    assert!(pooled - empty <= messages * 3);
+}
@@ -1,10 +1,24 @@
-use trading::engin::Engin;
+use trading::engin::{Engin, EnginConfig, BackpressurePolicy, EngineError, FaultKind, Stage, ReportFormat, Recovery, SnapshotStore};
+use trading::engin::{StateChecksum, first_divergence};
+use trading::market_data::{DepthBook, DepthSnapshot, MarketDataEvent, MarketDataMessage, MarketDataSink};
+use trading::fix::FixConfig;
 use trading::messages::CancelRequest;
 use trading::messages::NewOrder;
-use trading::messages::PreProcessorTask;
+use trading::messages::{PreProcessorTask, Inbound};
+use trading::recorder::{FileRecorder, InputReader, InputRecorder};
+use trading::journal::{Journal, JournalReader, SyncPolicy};
+use trading::replication::{ReplicationSender, Replica};
 use trading::types::*;
+use trading::report_sink::{ReportSink, CountingSink, MemorySink};
+use trading::reports::{decode, OutputMessage};
 
-use std::time::Instant;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 use rand::Rng;
 
 pub struct RandomOrderGen {
@@ -25,6 +39,7 @@ impl RandomOrderGen {
         self.cl_order_id += 1;
         Box::new(NewOrder { 
             order_id: self.order_id,
+            seq_num: 0,
             pbu_id: to_array(&rand_qty.to_string()),
             cl_ord_id: to_array(&format!("{:X}{:X}", self.cl_order_id, rand_price)),
             security_id: to_array("SEC001"), 
@@ -37,6 +52,7 @@ impl RandomOrderGen {
         self.order_id += 1;
         self.cl_order_id += 1;
         Box::new(CancelRequest { order_id: self.order_id, 
+            seq_num: 0,
             pbu_id: orig_order.pbu_id.clone(), 
             cl_ord_id: to_array(&self.order_id.to_string()),
             orig_cl_ord_id: orig_order.cl_ord_id.clone(), 
@@ -48,7 +64,7 @@ impl RandomOrderGen {
 #[test]
 fn test_order_process() {
     let mut gen = trading::messages::OrderGen::new();
-    let mut sender = ExeSender::new();
+    let mut sender = CountingSink::new();
 
     let mut engin = Engin::new(sender);
 
@@ -121,6 +137,684 @@ fn test_order_process() {
 }
 */
 
+#[test]
+fn test_invalid_input() {
+    let mut gen = RandomOrderGen::new();
+    let mut engin = Engin::new(CountingSink::new());
+
+    let mut order = gen.gen_order();
+    order.side = 'X';
+    engin.process(PreProcessorTask::NewOrder(order)).unwrap();
+    let mut order = gen.gen_order();
+    order.qty = 0;
+    engin.process(PreProcessorTask::NewOrder(order)).unwrap();
+    let mut order = gen.gen_order();
+    order.price = -1;
+    engin.process(PreProcessorTask::NewOrder(order)).unwrap();
+    let mut order = gen.gen_order();
+    order.security_id = to_array("");
+    let mut cancel_request = gen.get_cancel_request(&order);
+    engin.process(PreProcessorTask::NewOrder(order)).unwrap();
+    cancel_request.security_id = to_array("");
+    engin.process(PreProcessorTask::CancelRequest(cancel_request)).unwrap();
+
+    let order = gen.gen_order();
+    engin.process(PreProcessorTask::NewOrder(order)).unwrap();
+
+    let sender = engin.close().unwrap();
+    assert_eq!(sender.count, 6);
+}
+
+#[test]
+fn test_backpressure() {
+    let mut gen = RandomOrderGen::new();
+    let config = EnginConfig {
+        ingress_capacity : 4,
+        queue_capacity : 8,
+        backpressure : BackpressurePolicy::Reject,
+        ..EnginConfig::default()
+    };
+    let mut engin = Engin::with_config(CountingSink::new(), config);
+
+    let (mut accepted, mut rejected) = (0, 0);
+    for _ in 0..20000 {
+        let mut order = gen.gen_order();
+        order.side = K_BUY;
+        match engin.process(PreProcessorTask::NewOrder(order)) {
+            Ok(()) => accepted += 1,
+            Err(e) => {
+                assert_eq!(e, EngineError::QueueFull);
+                rejected += 1;
+            },
+        }
+    }
+    // 入口队列只有 4 个位置, 连续提交时应当有委托因队列满被拒绝
+    assert!(rejected > 0);
+
+    let stats = engin.queue_stats();
+    assert_eq!(stats.len(), 5);
+    assert_eq!((stats[0].stage, stats[0].capacity), (Stage::Seq, 4));
+    stats.iter().for_each(|s| {
+        assert!(s.high_watermark <= s.capacity);
+        assert!(s.depth <= s.capacity);
+    });
+
+    let sender = engin.close().unwrap();
+    assert_eq!(sender.count, accepted);
+}
+
+#[test]
+fn test_price_ladder() {
+    let mut gen = RandomOrderGen::new();
+    let mut config = EnginConfig { use_price_ladder : true, ..EnginConfig::default() };
+    config.price_limits.insert(to_array("SEC001"), PriceLimit { min_price : 1, max_price : 150, tick : 1 });
+    let mut engin = Engin::with_config(CountingSink::new(), config);
+
+    // 价格在 1..=200 之间随机, 超出涨跌停的委托被拒绝, 其余委托进入数组价位簿撮合
+    for _ in 0..20000 {
+        let order = gen.gen_order();
+        let cancel_request = gen.get_cancel_request(&order);
+        engin.process(PreProcessorTask::NewOrder(order)).unwrap();
+        engin.process(PreProcessorTask::CancelRequest(cancel_request)).unwrap();
+    }
+    let sender = engin.close().unwrap();
+    assert!(sender.count >= 40000);
+}
+
+#[test]
+fn test_invalid_price_limit() {
+    for limit in [PriceLimit { min_price : 1, max_price : 150, tick : 0 }, PriceLimit { min_price : 150, max_price : 1, tick : 1 }] {
+        let mut config = EnginConfig { use_price_ladder : true, ..EnginConfig::default() };
+        config.price_limits.insert(to_array("SEC001"), limit);
+        assert!(matches!(config.validate(), Err(EngineError::InvalidConfig(_))));
+        assert!(matches!(Engin::try_with_config(CountingSink::new(), config), Err(EngineError::InvalidConfig(_))));
+    }
+}
+
+struct BrokenSink;
+
+impl ReportSink for BrokenSink {
+    fn send(&mut self, _report : &[u8]) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::BrokenPipe, "peer closed"))
+    }
+}
+
+#[test]
+fn test_report_sink() {
+    let mut gen = RandomOrderGen::new();
+    let mut engin = Engin::new(MemorySink::new());
+    for _ in 0..100 {
+        let mut order = gen.gen_order();
+        order.side = K_BUY;
+        engin.process(PreProcessorTask::NewOrder(order)).unwrap();
+    }
+    let sink = engin.close().unwrap();
+    assert_eq!(sink.reports.len(), 100);
+    sink.reports.iter().for_each(|report| match decode(report).unwrap() {
+        OutputMessage::ExecutionReport(report) => assert_eq!(report.exec_type, K_EXEC_TYPE_NEW),
+        message => panic!("unexpected message {:?}", message),
+    });
+
+    // 输出失败时 exe 阶段登记故障
+    let mut engin = Engin::new(BrokenSink);
+    engin.process(PreProcessorTask::NewOrder(gen.gen_order())).unwrap();
+    match engin.close() {
+        Err(EngineError::StageFailed(fault)) => {
+            assert_eq!(fault.stage, Stage::Exe);
+            assert!(matches!(fault.kind, FaultKind::Io(_)));
+        },
+        _ => panic!("sink error should fail the exe stage"),
+    }
+}
+
+#[test]
+fn test_sequencer() {
+    let mut gen = RandomOrderGen::new();
+    let mut engin = Engin::new(MemorySink::new());
+    // 调用方填写的 order_id 被定序阶段覆盖
+    let mut buy = gen.gen_order();
+    buy.order_id = 99;
+    buy.side = K_BUY;
+    buy.price = 100;
+    buy.qty = 10;
+    let cancel = gen.get_cancel_request(&buy);
+    let sell = Box::new(NewOrder { order_id : 99, cl_ord_id : to_array("S1"), side : K_SELL, qty : 4, ..*buy });
+    engin.process(PreProcessorTask::NewOrder(buy)).unwrap();
+    engin.process(PreProcessorTask::NewOrder(sell)).unwrap();
+    engin.process(PreProcessorTask::CancelRequest(cancel)).unwrap();
+    let sink = engin.close().unwrap();
+
+    let reports : Vec<(OrderID, SeqNum, char)> = sink.reports.iter().map(|report| match decode(report).unwrap() {
+        OutputMessage::ExecutionReport(report) => (report.order_id, report.seq_num, report.exec_type),
+        OutputMessage::TradeCaptureReport(report) => (report.order_id, report.seq_num, ' '),
+        message => panic!("unexpected message {:?}", message),
+    }).collect();
+    assert_eq!(reports, vec![
+        (1, 1, K_EXEC_TYPE_NEW),
+        (2, 2, K_EXEC_TYPE_NEW),
+        (2, 2, K_EXEC_TYPE_TRADE),
+        (1, 2, K_EXEC_TYPE_TRADE),
+        (2, 2, ' '),
+        (1, 3, K_EXEC_TYPE_CANCELLED),
+    ]);
+}
+
+#[test]
+fn test_recorded_replay() {
+    let path = std::env::temp_dir().join(format!("test_recorded_replay_{}.bin", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let config = EnginConfig { recorder : Some(Box::new(FileRecorder::open(&path).unwrap())), ..EnginConfig::default() };
+    let mut engin = Engin::with_config(MemorySink::new(), config);
+
+    // 多个线程同时送入, 到达顺序不确定
+    thread::scope(|scope| {
+        for thread_id in 0..4 {
+            let ingress = engin.ingress();
+            scope.spawn(move || {
+                let mut gen = RandomOrderGen::new();
+                for i in 0..200 {
+                    let mut order = gen.gen_order();
+                    order.cl_ord_id = to_array(&format!("T{}-{}", thread_id, i));
+                    ingress.submit(Inbound::NewOrder(*order)).unwrap();
+                }
+            });
+        }
+    });
+    let recorded = engin.close().unwrap();
+
+    let inputs : Vec<Inbound> = InputReader::new(std::fs::File::open(&path).unwrap()).map(|input| input.unwrap()).collect();
+    std::fs::remove_file(&path).unwrap();
+    let seq_nums : Vec<SeqNum> = inputs.iter().map(|input| match input {
+        Inbound::NewOrder(order) => order.seq_num,
+        Inbound::CancelRequest(cancel_request) => cancel_request.seq_num,
+    }).collect();
+    assert_eq!(seq_nums, (1..=800).collect::<Vec<SeqNum>>());
+
+    // 按记录的顺序重放, 报告逐字节相同
+    let mut engin = Engin::new(MemorySink::new());
+    inputs.into_iter().for_each(|input| engin.submit(input).unwrap());
+    let replayed = engin.close().unwrap();
+    assert_eq!(replayed.reports, recorded.reports);
+}
+
+#[test]
+fn test_journal() {
+    let path = std::env::temp_dir().join(format!("test_journal_{}.bin", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let config = EnginConfig { recorder : Some(Box::new(Journal::open(&path, SyncPolicy::PerBatch).unwrap())), ..EnginConfig::default() };
+    let mut engin = Engin::with_config(MemorySink::new(), config);
+    let mut gen = RandomOrderGen::new();
+    for _ in 0..100 {
+        engin.submit(Inbound::NewOrder(*gen.gen_order())).unwrap();
+    }
+    engin.close().unwrap();
+
+    let seq_nums : Vec<SeqNum> = JournalReader::new(std::fs::File::open(&path).unwrap()).map(|input| match input.unwrap() {
+        Inbound::NewOrder(order) => order.seq_num,
+        Inbound::CancelRequest(cancel_request) => cancel_request.seq_num,
+    }).collect();
+    assert_eq!(seq_nums, (1..=100).collect::<Vec<SeqNum>>());
+    assert_eq!(Journal::open(&path, SyncPolicy::PerMessage).unwrap().last_seq_num(), 100);
+    std::fs::remove_file(&path).unwrap();
+}
+
+// flush 之后才把已记录的最后序号公开, 模拟 fsync 之后输入才算落盘; flush 较慢, 期间入口队列会积压
+struct DurableRecorder {
+    recorded : SeqNum,
+    durable : Arc<AtomicU64>,
+}
+
+impl InputRecorder for DurableRecorder {
+    fn record(&mut self, input : &Inbound) -> io::Result<()> {
+        self.recorded = input.seq_num();
+        Ok(())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        thread::sleep(Duration::from_millis(1));
+        self.durable.store(self.recorded, Ordering::Release);
+        Ok(())
+    }
+}
+
+// 记录报告对应的输入在输出时是否已落盘
+struct DurableCheckSink {
+    durable : Arc<AtomicU64>,
+    reports : usize,
+    not_durable : usize,
+}
+
+impl ReportSink for DurableCheckSink {
+    fn send(&mut self, report : &[u8]) -> io::Result<()> {
+        self.reports += 1;
+        if report_seq_num(report) > self.durable.load(Ordering::Acquire) {
+            self.not_durable += 1;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_group_commit() {
+    let durable = Arc::new(AtomicU64::new(0));
+    let config = EnginConfig { recorder : Some(Box::new(DurableRecorder { recorded : 0, durable : durable.clone() })), ..EnginConfig::default() };
+    let mut engin = Engin::with_config(DurableCheckSink { durable, reports : 0, not_durable : 0 }, config);
+
+    // 多个线程同时送入, 入口队列不会每条都处理空
+    thread::scope(|scope| {
+        for thread_id in 0..4 {
+            let ingress = engin.ingress();
+            scope.spawn(move || {
+                let mut gen = RandomOrderGen::new();
+                for i in 0..500 {
+                    let mut order = gen.gen_order();
+                    order.cl_ord_id = to_array(&format!("T{}-{}", thread_id, i));
+                    ingress.submit(Inbound::NewOrder(*order)).unwrap();
+                }
+            });
+        }
+    });
+    let sink = engin.close().unwrap();
+    assert!(sink.reports >= 2000);
+    assert_eq!(sink.not_durable, 0);
+}
+
+fn report_seq_num(report : &[u8]) -> SeqNum {
+    match decode(report).unwrap() {
+        OutputMessage::ExecutionReport(report) => report.seq_num,
+        OutputMessage::TradeCaptureReport(report) => report.seq_num,
+        OutputMessage::CancelReject(report) => report.seq_num,
+    }
+}
+
+// 委托与撤单混合, 最后重复第一笔委托
+fn gen_inputs() -> Vec<Inbound> {
+    let mut gen = RandomOrderGen::new();
+    let mut inputs = Vec::new();
+    for i in 0..300 {
+        let order = gen.gen_order();
+        inputs.push(Inbound::NewOrder(*order));
+        if i % 5 == 0 {
+            inputs.push(Inbound::CancelRequest(*gen.get_cancel_request(&order)));
+        }
+    }
+    inputs.push(inputs[0]);
+    inputs
+}
+
+fn process_all(inputs : &[Inbound]) -> Vec<Vec<u8>> {
+    let mut engin = Engin::new(MemorySink::new());
+    inputs.iter().for_each(|input| engin.submit(*input).unwrap());
+    engin.close().unwrap().reports
+}
+
+#[test]
+fn test_recovery() {
+    // 重启后重复的委托仍被拒绝
+    let inputs = gen_inputs();
+    let crash_at = inputs.len() / 2;
+    let expected = process_all(&inputs);
+
+    let path = std::env::temp_dir().join(format!("test_recovery_{}.bin", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let config = EnginConfig { recorder : Some(Box::new(Journal::open(&path, SyncPolicy::PerMessage).unwrap())), ..EnginConfig::default() };
+    let mut engin = Engin::with_config(MemorySink::new(), config);
+    inputs[..crash_at].iter().for_each(|input| engin.submit(*input).unwrap());
+    let mut reports = engin.close().unwrap().reports;
+
+    // 从日志恢复后接着处理余下的输入, 已送达的报告不再输出
+    let journal = Journal::open(&path, SyncPolicy::PerMessage).unwrap();
+    let config = EnginConfig { recorder : Some(Box::new(journal)), recovery : Some(Recovery::from_journal(&path, crash_at as SeqNum).unwrap()), ..EnginConfig::default() };
+    let mut engin = Engin::with_config(MemorySink::new(), config);
+    inputs[crash_at..].iter().for_each(|input| engin.submit(*input).unwrap());
+    reports.extend(engin.close().unwrap().reports);
+    assert_eq!(reports, expected);
+
+    // 只有部分报告送达时, 其余的报告在重放时重新输出
+    let recovery = Recovery::from_journal(&path, 100).unwrap();
+    assert_eq!(recovery.inputs.len(), inputs.len());
+    let mut engin = Engin::with_config(MemorySink::new(), EnginConfig { recovery : Some(recovery), ..EnginConfig::default() });
+    let reports = engin.close().unwrap().reports;
+    let expected : Vec<Vec<u8>> = expected.into_iter().filter(|report| report_seq_num(report) > 100).collect();
+    assert_eq!(reports, expected);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_snapshot() {
+    let inputs = gen_inputs();
+    let (snapshot_at, crash_at) = (inputs.len() / 3, inputs.len() * 2 / 3);
+    let expected = process_all(&inputs);
+
+    let path = std::env::temp_dir().join(format!("test_snapshot_{}.bin", std::process::id()));
+    let dir = std::env::temp_dir().join(format!("test_snapshot_{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = EnginConfig {
+        recorder : Some(Box::new(Journal::open(&path, SyncPolicy::PerBatch).unwrap())),
+        snapshots : Some(SnapshotStore::open(&dir).unwrap()),
+        ..EnginConfig::default()
+    };
+    let mut engin = Engin::with_config(MemorySink::new(), config);
+    inputs[..snapshot_at].iter().for_each(|input| engin.submit(*input).unwrap());
+    engin.snapshot().unwrap();
+    inputs[snapshot_at..crash_at].iter().for_each(|input| engin.submit(*input).unwrap());
+    let mut reports = engin.close().unwrap().reports;
+
+    // 快照之前的输入不再重放
+    let snapshots = SnapshotStore::open(&dir).unwrap();
+    assert_eq!(snapshots.seq_nums().unwrap(), vec![snapshot_at as SeqNum]);
+    let recovery = Recovery::from_snapshot(&snapshots, &path, crash_at as SeqNum).unwrap();
+    assert_eq!(recovery.snapshot.as_ref().map(|snapshot| snapshot.seq_num), Some(snapshot_at as SeqNum));
+    assert_eq!(recovery.inputs.len(), crash_at - snapshot_at);
+    // 快照之前的报告未全部送达时不能从这个快照恢复
+    let earlier = Recovery::from_snapshot(&snapshots, &path, snapshot_at as SeqNum - 1).unwrap();
+    assert!(earlier.snapshot.is_none());
+    assert_eq!(earlier.inputs.len(), crash_at);
+
+    let config = EnginConfig {
+        recorder : Some(Box::new(Journal::open(&path, SyncPolicy::PerBatch).unwrap())),
+        recovery : Some(recovery),
+        snapshots : Some(snapshots),
+        ..EnginConfig::default()
+    };
+    let mut engin = Engin::with_config(MemorySink::new(), config);
+    inputs[crash_at..].iter().for_each(|input| engin.submit(*input).unwrap());
+    engin.snapshot().unwrap();
+    reports.extend(engin.close().unwrap().reports);
+    assert_eq!(reports, expected);
+    assert_eq!(SnapshotStore::open(&dir).unwrap().seq_nums().unwrap(), vec![snapshot_at as SeqNum, inputs.len() as SeqNum]);
+
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn run_with_checksums(inputs : &[Inbound], interval : SeqNum) -> Vec<StateChecksum> {
+    let (tx, rx) = mpsc::channel();
+    let config = EnginConfig { checksum_interval : interval, checksum_log : Some(Box::new(tx)), ..EnginConfig::default() };
+    let mut engin = Engin::with_config(MemorySink::new(), config);
+    inputs.iter().for_each(|input| engin.submit(*input).unwrap());
+    engin.close().unwrap();
+    rx.try_iter().collect()
+}
+
+#[test]
+fn test_checksum() {
+    let mut inputs = gen_inputs();
+    let checksums = run_with_checksums(&inputs, 50);
+    let seq_nums : Vec<SeqNum> = checksums.iter().map(|checksum| checksum.seq_num).collect();
+    assert_eq!(seq_nums, (1..=inputs.len() as SeqNum / 50).map(|i| i * 50).collect::<Vec<_>>());
+    assert_eq!(first_divergence(&checksums, &run_with_checksums(&inputs, 50)), None);
+
+    // 改动一笔委托后, 第一个不一致的摘要在它之后最近的检查点上
+    let at = (120..inputs.len()).find(|i| matches!(inputs[*i], Inbound::NewOrder(_))).unwrap();
+    if let Inbound::NewOrder(order) = &mut inputs[at] {
+        order.cl_ord_id = to_array("X");
+    }
+    let (x, y) = first_divergence(&checksums, &run_with_checksums(&inputs, 50)).unwrap();
+    assert_eq!(x.seq_num, (at as SeqNum / 50 + 1) * 50);
+    assert!(x.diverged_stages(&y).contains(&"pre"));
+}
+
+#[test]
+fn test_market_data() {
+    let inputs = gen_inputs();
+    let (tx, rx) = mpsc::channel();
+    let mut engin = Engin::with_config(MemorySink::new(), EnginConfig { market_data : Some(Box::new(tx)), ..EnginConfig::default() });
+    assert!(engin.queue_stats().iter().any(|stats| stats.stage == Stage::Md));
+    inputs.iter().for_each(|input| engin.submit(*input).unwrap());
+    let reports = engin.close().unwrap().reports;
+    let messages : Vec<MarketDataMessage> = rx.try_iter().collect();
+
+    // 按顺序应用价位变化不会出现不一致, 最终各证券的簿不交叉
+    let mut book = DepthBook::new();
+    let mut traded = 0;
+    for (i, message) in messages.iter().enumerate() {
+        assert_eq!(message.md_seq_num, i as u64 + 1);
+        match message.event {
+            MarketDataEvent::Level(update) => assert!(book.apply(&update), "{:?}", message),
+            MarketDataEvent::Trade(trade) => traded += trade.qty,
+        }
+    }
+    assert!(messages.windows(2).all(|pair| pair[0].seq_num <= pair[1].seq_num));
+    for security_id in book.securities() {
+        if let (Some(bid), Some(ask)) = (book.depth(&security_id, K_BUY).first(), book.depth(&security_id, K_SELL).first()) {
+            assert!(bid.0 < ask.0);
+        }
+    }
+    let expected : Qty = reports.iter().map(|report| match decode(report).unwrap() {
+        OutputMessage::TradeCaptureReport(report) => report.last_qty,
+        _ => 0,
+    }).sum();
+    assert!(traded > 0);
+    assert_eq!(traded, expected);
+}
+
+#[test]
+fn test_market_data_recovery() {
+    let inputs = gen_inputs();
+    let (snapshot_at, crash_at) = (inputs.len() / 3, inputs.len() * 2 / 3);
+    let (tx, rx) = mpsc::channel();
+    let mut engin = Engin::with_config(MemorySink::new(), EnginConfig { market_data : Some(Box::new(tx)), ..EnginConfig::default() });
+    inputs.iter().for_each(|input| engin.submit(*input).unwrap());
+    engin.close().unwrap();
+    let expected : Vec<MarketDataMessage> = rx.try_iter().collect();
+
+    let path = std::env::temp_dir().join(format!("test_md_recovery_{}.bin", std::process::id()));
+    let dir = std::env::temp_dir().join(format!("test_md_recovery_{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_dir_all(&dir);
+    let (tx, rx) = mpsc::channel();
+    let config = EnginConfig {
+        recorder : Some(Box::new(Journal::open(&path, SyncPolicy::PerBatch).unwrap())),
+        snapshots : Some(SnapshotStore::open(&dir).unwrap()),
+        market_data : Some(Box::new(tx)),
+        ..EnginConfig::default()
+    };
+    let mut engin = Engin::with_config(MemorySink::new(), config);
+    inputs[..snapshot_at].iter().for_each(|input| engin.submit(*input).unwrap());
+    engin.snapshot().unwrap();
+    inputs[snapshot_at..crash_at].iter().for_each(|input| engin.submit(*input).unwrap());
+    engin.close().unwrap();
+    let mut messages : Vec<MarketDataMessage> = rx.try_iter().collect();
+
+    // 从快照恢复后行情接着崩溃前的编号, 重放的输入不再输出行情
+    let (tx, rx) = mpsc::channel();
+    let config = EnginConfig {
+        recovery : Some(Recovery::from_snapshot(&SnapshotStore::open(&dir).unwrap(), &path, crash_at as SeqNum).unwrap()),
+        market_data : Some(Box::new(tx)),
+        ..EnginConfig::default()
+    };
+    let mut engin = Engin::with_config(MemorySink::new(), config);
+    inputs[crash_at..].iter().for_each(|input| engin.submit(*input).unwrap());
+    engin.close().unwrap();
+    let resumed : Vec<MarketDataMessage> = rx.try_iter().collect();
+    assert_eq!(resumed.first().map(|message| message.md_seq_num), messages.last().map(|message| message.md_seq_num + 1));
+    messages.extend(resumed);
+    assert_eq!(messages, expected);
+
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// 增量与快照按行情阶段交出的顺序放在同一个通道中
+enum Feed {
+    Message(MarketDataMessage),
+    Snapshot(DepthSnapshot),
+}
+
+struct FeedSink(mpsc::Sender<Feed>);
+
+impl MarketDataSink for FeedSink {
+    fn publish(&mut self, message : &MarketDataMessage) -> io::Result<()> {
+        self.0.send(Feed::Message(*message)).map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+    fn publish_snapshot(&mut self, snapshot : &DepthSnapshot) -> io::Result<()> {
+        self.0.send(Feed::Snapshot(snapshot.clone())).map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+#[test]
+fn test_market_data_snapshot() {
+    let inputs = gen_inputs();
+    let (tx, rx) = mpsc::channel();
+    let mut engin = Engin::with_config(MemorySink::new(), EnginConfig { market_data : Some(Box::new(FeedSink(tx))), ..EnginConfig::default() });
+    let half = inputs.len() / 2;
+    inputs[..half].iter().for_each(|input| engin.submit(*input).unwrap());
+    engin.market_data_snapshot().unwrap();
+    inputs[half..].iter().for_each(|input| engin.submit(*input).unwrap());
+    engin.market_data_snapshot().unwrap();
+    let depth_snapshots = engin.depth_snapshots();
+    engin.close().unwrap();
+
+    let mut messages = Vec::new();
+    let mut snapshots : Vec<Vec<DepthSnapshot>> = Vec::new();
+    for feed in rx.try_iter() {
+        match feed {
+            Feed::Message(message) => messages.push(message),
+            Feed::Snapshot(snapshot) => match snapshots.last_mut() {
+                Some(last) if last[0].md_seq_num == snapshot.md_seq_num => last.push(snapshot),
+                _ => snapshots.push(vec![snapshot]),
+            },
+        }
+    }
+    assert_eq!(snapshots.len(), 2);
+    let (middle, last) = (&snapshots[0], &snapshots[1]);
+    assert_eq!(middle[0].seq_num, half as SeqNum);
+    assert_eq!(last[0].md_seq_num, messages.len() as u64);
+
+    let mut book = DepthBook::new();
+    for message in &messages {
+        if let MarketDataEvent::Level(update) = message.event {
+            assert!(book.apply(&update));
+        }
+    }
+    // 晚加入的订阅方载入中途的快照, 再应用此后的增量
+    let mut late = DepthBook::new();
+    middle.iter().for_each(|snapshot| late.load(snapshot));
+    for message in messages.iter().filter(|message| message.md_seq_num > middle[0].md_seq_num) {
+        if let MarketDataEvent::Level(update) = message.event {
+            assert!(late.apply(&update), "{:?}", message);
+        }
+    }
+    assert_eq!(late, book);
+
+    let mut loaded = DepthBook::new();
+    last.iter().for_each(|snapshot| loaded.load(snapshot));
+    assert_eq!(loaded, book);
+    assert_eq!(&depth_snapshots.all(), last);
+    for snapshot in last {
+        assert_eq!(snapshot.bids, book.depth(&snapshot.security_id, K_BUY));
+        assert_eq!(snapshot.asks, book.depth(&snapshot.security_id, K_SELL));
+    }
+}
+
+#[test]
+fn test_market_data_snapshot_timer() {
+    let (tx, _rx) = mpsc::channel::<MarketDataMessage>();
+    let mut engin = Engin::with_config(MemorySink::new(), EnginConfig {
+        market_data : Some(Box::new(tx)),
+        market_data_snapshot_interval : Some(Duration::from_millis(10)),
+        ..EnginConfig::default()
+    });
+    gen_inputs().iter().take(100).for_each(|input| engin.submit(*input).unwrap());
+    let depth_snapshots = engin.depth_snapshots();
+    let start = Instant::now();
+    while depth_snapshots.all().iter().all(|snapshot| snapshot.seq_num < 100) {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(10));
+    }
+    engin.close().unwrap();
+}
+
+#[test]
+fn test_replication() {
+    let inputs = gen_inputs();
+    let fail_at = inputs.len() / 2;
+    let expected = process_all(&inputs);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let sender = ReplicationSender::new(TcpStream::connect(listener.local_addr().unwrap()).unwrap(), Duration::from_millis(10)).unwrap();
+    let monitor = sender.monitor();
+    let (connection, _) = listener.accept().unwrap();
+    let (secondary_tx, secondary_checksums) = mpsc::channel();
+    let config = EnginConfig { standby : true, checksum_interval : 50, checksum_log : Some(Box::new(secondary_tx)), ..EnginConfig::default() };
+    let mut secondary = Engin::with_config(MemorySink::new(), config);
+    let replica = Replica::start(connection, secondary.ingress(), 0, Duration::from_millis(200)).unwrap();
+    assert_eq!(secondary.submit(inputs[0]), Err(EngineError::Standby));
+
+    let (primary_tx, primary_checksums) = mpsc::channel();
+    let config = EnginConfig { recorder : Some(Box::new(sender)), checksum_interval : 50, checksum_log : Some(Box::new(primary_tx)), ..EnginConfig::default() };
+    let mut primary = Engin::with_config(MemorySink::new(), config);
+    inputs[..fail_at].iter().for_each(|input| primary.submit(*input).unwrap());
+    // 复制是异步的, 等备用引擎确认收到全部输入后再让主引擎退出
+    let start = Instant::now();
+    while monitor.acknowledged_seq_num() < fail_at as SeqNum {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(10));
+    }
+    let mut reports = primary.close().unwrap().reports;
+
+    // 主引擎退出后连接断开, 备用引擎接管并接着处理余下的输入
+    let start = Instant::now();
+    while !replica.primary_failed() {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(replica.promote(), Ok(fail_at as SeqNum));
+    inputs[fail_at..].iter().for_each(|input| secondary.submit(*input).unwrap());
+    reports.extend(secondary.close().unwrap().reports);
+    assert_eq!(reports, expected);
+
+    // 主备引擎在相同的序号上状态一致, 备用引擎接管后接着输出摘要
+    let primary_checksums : Vec<StateChecksum> = primary_checksums.try_iter().collect();
+    let secondary_checksums : Vec<StateChecksum> = secondary_checksums.try_iter().collect();
+    assert_eq!(primary_checksums.len(), fail_at / 50);
+    assert_eq!(secondary_checksums.len(), inputs.len() / 50);
+    assert_eq!(first_divergence(&primary_checksums, &secondary_checksums), None);
+}
+
+#[test]
+fn test_ingress_pool() {
+    let inputs = gen_inputs();
+    let mut engin = Engin::new(CountingSink::new());
+    // 其它线程的入口与 Engin 共用对象池
+    let (first, second) = inputs.split_at(inputs.len() / 2);
+    thread::scope(|scope| {
+        for part in [first, second] {
+            let ingress = engin.ingress();
+            scope.spawn(move || part.iter().for_each(|input| ingress.submit(*input).unwrap()));
+        }
+    });
+    let sink = engin.close().unwrap();
+    assert!(sink.count > 0);
+    let (orders, cancels) = (engin.new_order_pool_stats(), engin.cancel_request_pool_stats());
+    assert_eq!((orders.allocated, cancels.allocated), (0, 0));
+    assert_eq!(orders.reused + cancels.reused, inputs.len() as u64);
+}
+
+#[test]
+fn test_fix_reports() {
+    let mut gen = RandomOrderGen::new();
+    let config = EnginConfig { report_format : ReportFormat::Fix(FixConfig::default()), ..EnginConfig::default() };
+    let mut engin = Engin::with_config(MemorySink::new(), config);
+    let order = gen.gen_order();
+    let cancel_request = gen.get_cancel_request(&order);
+    engin.process(PreProcessorTask::NewOrder(order)).unwrap();
+    engin.process(PreProcessorTask::CancelRequest(cancel_request)).unwrap();
+
+    let sink = engin.close().unwrap();
+    assert_eq!(sink.reports.len(), 2);
+    sink.reports.iter().for_each(|report| {
+        assert!(report.starts_with(b"8=FIX.4.4\x019="));
+        assert!(report.windows(6).any(|w| w == b"\x0135=8\x01"));
+    });
+
+    // 价格小数位数超出范围时不启动引擎
+    let fix_config = FixConfig { default_price_scale : 20, ..FixConfig::default() };
+    let config = EnginConfig { report_format : ReportFormat::Fix(fix_config), ..EnginConfig::default() };
+    assert!(matches!(Engin::try_with_config(MemorySink::new(), config), Err(EngineError::InvalidConfig(_))));
+}
+
 #[test]
 fn test_engin() {
     let mut gen = RandomOrderGen::new();
@@ -142,7 +836,7 @@ fn test_engin() {
 
     }
 
-    let mut sender = ExeSender::new();
+    let mut sender = CountingSink::new();
 
     let mut engin = Engin::new(sender);
 
@@ -150,11 +844,10 @@ fn test_engin() {
 
     orders.into_iter().for_each(|task| {
         //println!("price {}, qty {}", order.price, order.qty);
-        engin.engin_tx.send(Some(task)).unwrap();
+        engin.process(task).unwrap();
     });
-    engin.engin_tx.send(None).unwrap();
 
-    sender = engin.close();
+    sender = engin.close().unwrap();
 
     let elapsed_time = now.elapsed();
 
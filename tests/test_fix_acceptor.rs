@@ -0,0 +1,136 @@
+use trading::engin::Engin;
+use trading::fix::*;
+use trading::fix::acceptor::FixAcceptor;
+use trading::fix::message::{FixMessage, FixReader};
+use trading::types::*;
+
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+struct Client {
+    encoder : FixEncoder,
+    seq_num : u64,
+    stream : TcpStream,
+    reader : FixReader<TcpStream>,
+}
+
+impl Client {
+    fn connect(addr : SocketAddr, pbu_id : &str, seq_num : u64) -> Client {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let reader = FixReader::new(stream.try_clone().unwrap());
+        let encoder = FixEncoder::new(FixConfig { sender_comp_id : pbu_id.to_string(), ..FixConfig::default() }).unwrap();
+        Client { encoder, seq_num, stream, reader }
+    }
+
+    fn send(&mut self, msg_type : &str, body : &str) {
+        use std::io::Write;
+        self.seq_num += 1;
+        let sending_time = format_sending_time(SystemTime::now());
+        let header = FixHeader { msg_type, target : b"ENGINE", seq_num : self.seq_num, sending_time : &sending_time, orig_sending_time : None };
+        let mut buffer = Vec::new();
+        self.encoder.write_message(&mut buffer, &header, body.as_bytes());
+        self.stream.write_all(&buffer).unwrap();
+    }
+
+    fn recv(&mut self) -> FixMessage {
+        let bytes = self.reader.read_message().unwrap().unwrap();
+        FixMessage::parse(&bytes).unwrap()
+    }
+
+    // 读到指定类型的消息为止, 返回途中收到的所有消息
+    fn recv_until(&mut self, msg_type : &[u8]) -> Vec<FixMessage> {
+        let mut messages = Vec::new();
+        loop {
+            let message = self.recv();
+            let done = message.msg_type() == msg_type;
+            messages.push(message);
+            if done {
+                return messages;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_fix_session() {
+    let mut config = FixConfig::default();
+    config.price_scales.insert(to_array("SEC001"), 2);
+    let acceptor = FixAcceptor::bind("127.0.0.1:0", config).unwrap();
+    let addr = acceptor.local_addr().unwrap();
+    let engin = Engin::new(acceptor.sink());
+
+    let acceptor = &acceptor;
+    thread::scope(|scope| {
+        let running = scope.spawn(move || {
+            let result = acceptor.run(&engin);
+            (result, engin)
+        });
+
+        let mut buyer = Client::connect(addr, "PBU001", 0);
+        buyer.send("A", "98=0\x01108=30\x01141=Y\x01");
+        assert_eq!(buyer.recv().msg_type(), b"A");
+        let mut seller = Client::connect(addr, "PBU002", 0);
+        seller.send("A", "98=0\x01108=30\x01141=Y\x01");
+        assert_eq!(seller.recv().msg_type(), b"A");
+
+        // 同一交易单元的第二个连接被断开
+        let mut duplicate = Client::connect(addr, "PBU001", 0);
+        duplicate.send("A", "98=0\x01108=30\x01");
+        assert!(duplicate.reader.read_message().map(|m| m.is_none()).unwrap_or(true));
+
+        buyer.send("D", "11=B1\x0155=SEC001\x0154=1\x0138=10\x0140=2\x0144=100.50\x01");
+        let reports = buyer.recv_until(b"8");
+        assert_eq!(reports.last().unwrap().get(150), Some(&b"0"[..]));
+        assert_eq!(reports.last().unwrap().get(44), Some(&b"100.50"[..]));
+
+        seller.send("D", "11=S1\x0155=SEC001\x0154=2\x0138=4\x0140=2\x0144=100.5\x01");
+        for client in [&mut buyer, &mut seller] {
+            let trade = loop {
+                let report = client.recv();
+                if report.msg_type() == b"8" && report.get(150) == Some(&b"F"[..]) {
+                    break report;
+                }
+            };
+            assert_eq!(trade.get(31), Some(&b"100.50"[..]));
+            assert_eq!(trade.get_u64(32), Some(4));
+        }
+
+        buyer.send("F", "11=B2\x0141=XX\x0155=SEC001\x01");
+        let reject = buyer.recv_until(b"9").pop().unwrap();
+        assert_eq!(reject.get(41), Some(&b"XX"[..]));
+
+        buyer.send("1", "112=PING\x01");
+        let heartbeat = buyer.recv_until(b"0").pop().unwrap();
+        assert_eq!(heartbeat.get(112), Some(&b"PING"[..]));
+        let last_seq = heartbeat.seq_num().unwrap();
+
+        buyer.send("5", "");
+        assert_eq!(buyer.recv_until(b"5").len(), 1);
+
+        // 重新登录后补发之前的消息
+        let seq_num = buyer.seq_num;
+        let mut buyer = Client::connect(addr, "PBU001", seq_num);
+        buyer.send("A", "98=0\x01108=30\x01");
+        let logon = buyer.recv();
+        assert_eq!(logon.msg_type(), b"A");
+        assert_eq!(logon.seq_num(), Ok(last_seq + 2));
+        buyer.send("2", "7=1\x0116=0\x01");
+        let mut expected = 1;
+        while expected <= last_seq + 2 {
+            let message = buyer.recv();
+            assert_eq!(message.seq_num(), Ok(expected));
+            assert!(message.get_flag(43));
+            expected = match message.msg_type() {
+                b"4" => message.get_u64(36).unwrap(),
+                _ => expected + 1,
+            };
+        }
+
+        acceptor.stop();
+        let (result, mut engin) = running.join().unwrap();
+        result.unwrap();
+        engin.close().unwrap();
+    });
+}
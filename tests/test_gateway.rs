@@ -0,0 +1,237 @@
+use trading::engin::Engin;
+use trading::gateway::*;
+use trading::report_sink::{read_frame, write_frame};
+use trading::report_log::{ReportLog, StreamID};
+use trading::journal::SyncPolicy;
+use trading::reports::{decode, OutputMessage};
+use trading::types::*;
+
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+struct Client {
+    stream : TcpStream,
+    // 每条流最后收到的报告序号
+    seqs : HashMap<StreamID, u64>,
+}
+
+impl Client {
+    fn connect(addr : SocketAddr) -> Client {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        Client { stream, seqs : HashMap::new() }
+    }
+
+    fn logon(addr : SocketAddr, pbu_id : &str, password : &str) -> (Client, Response) {
+        Client::logon_from(addr, pbu_id, password, Vec::new())
+    }
+
+    fn logon_from(addr : SocketAddr, pbu_id : &str, password : &str, next_seqs : Vec<(StreamID, u64)>) -> (Client, Response) {
+        let mut client = Client::connect(addr);
+        client.send(&Request::Logon(Logon { pbu_id : to_array(pbu_id), password : password.to_string(), next_seqs }));
+        let response = client.recv().unwrap();
+        (client, response)
+    }
+
+    fn send(&mut self, request : &Request) {
+        write_frame(&mut self.stream, &encode_request(request)).unwrap();
+    }
+
+    // 每条流的报告序号连续
+    fn recv(&mut self) -> Option<Response> {
+        let response = read_frame(&mut self.stream).unwrap().map(|frame| decode_response(&frame).unwrap());
+        if let Some(Response::Report { stream, seq, .. }) = &response {
+            let last = self.seqs.insert(*stream, *seq);
+            assert!(last.is_none() || last == Some(seq - 1), "stream {} seq {} after {:?}", stream, seq, last);
+        }
+        response
+    }
+
+    fn recv_report(&mut self) -> OutputMessage {
+        match self.recv() {
+            Some(Response::Report { report, .. }) => report,
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    // 重连时从每条流最后收到的报告之后开始重传
+    fn next_seqs(&self) -> Vec<(StreamID, u64)> {
+        let mut next_seqs : Vec<(StreamID, u64)> = self.seqs.iter().map(|(stream, seq)| (*stream, seq + 1)).collect();
+        next_seqs.sort();
+        next_seqs
+    }
+
+    fn order(&mut self, cl_ord_id : &str, side : Side, price : Price, qty : Qty) {
+        self.order_in("SEC001", cl_ord_id, side, price, qty);
+    }
+
+    fn order_in(&mut self, security_id : &str, cl_ord_id : &str, side : Side, price : Price, qty : Qty) {
+        self.send(&Request::NewOrder(NewOrderRequest {
+            cl_ord_id : to_array(cl_ord_id),
+            security_id : to_array(security_id),
+            side,
+            price,
+            qty,
+        }));
+    }
+}
+
+#[test]
+fn test_gateway() {
+    let mut credentials = HashMap::new();
+    credentials.insert(to_array("PBU001"), "buyer".to_string());
+    credentials.insert(to_array("PBU002"), "seller".to_string());
+    let gateway = Gateway::bind("127.0.0.1:0", GatewayConfig { credentials, ..GatewayConfig::default() }).unwrap();
+    let addr = gateway.local_addr().unwrap();
+    let engin = Engin::new(gateway.sink());
+
+    let gateway = &gateway;
+    thread::scope(|scope| {
+        let running = scope.spawn(move || {
+            let result = gateway.run(&engin);
+            (result, engin)
+        });
+
+        let (mut client, response) = Client::logon(addr, "PBU001", "wrong");
+        assert_eq!(response, Response::Reject(RejectReason::AuthenticationFailed));
+        assert!(client.recv().is_none());
+
+        // 未登录时发送委托
+        let mut client = Client::connect(addr);
+        client.order("X1", K_BUY, 100, 10);
+        assert_eq!(client.recv(), Some(Response::Reject(RejectReason::NotLoggedOn)));
+        assert!(client.recv().is_none());
+
+        let (mut buyer, response) = Client::logon(addr, "PBU001", "buyer");
+        assert_eq!(response, Response::LogonAccepted(to_array("PBU001")));
+        let (_, response) = Client::logon(addr, "PBU001", "buyer");
+        assert_eq!(response, Response::Reject(RejectReason::AlreadyLoggedOn));
+        let (mut seller, response) = Client::logon(addr, "PBU002", "seller");
+        assert_eq!(response, Response::LogonAccepted(to_array("PBU002")));
+
+        buyer.order("B1", K_BUY, 100, 10);
+        let accepted = match buyer.recv_report() {
+            OutputMessage::ExecutionReport(report) => report,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!((accepted.cl_ord_id, accepted.exec_type), (to_array("B1"), K_EXEC_TYPE_NEW));
+
+        seller.order("S1", K_SELL, 100, 4);
+        for (client, cl_ord_id) in [(&mut seller, "S1"), (&mut buyer, "B1")] {
+            let trade = loop {
+                match client.recv_report() {
+                    OutputMessage::ExecutionReport(report) if report.exec_type == K_EXEC_TYPE_TRADE => break report,
+                    _ => continue,
+                }
+            };
+            assert_eq!(trade.cl_ord_id, to_array(cl_ord_id));
+            assert_eq!((trade.last_px, trade.last_qty), (100, 4));
+        }
+
+        // 报告只发给所属交易单元
+        buyer.send(&Request::CancelRequest(CancelOrderRequest {
+            cl_ord_id : to_array("B2"),
+            orig_cl_ord_id : to_array("B1"),
+            security_id : to_array("SEC001"),
+        }));
+        let canceled = loop {
+            match buyer.recv_report() {
+                OutputMessage::ExecutionReport(report) if report.exec_type == K_EXEC_TYPE_CANCELLED => break report,
+                OutputMessage::ExecutionReport(report) => assert_eq!(report.pbu_id, to_array("PBU001")),
+                OutputMessage::TradeCaptureReport(report) => assert_eq!(report.pbu_id, to_array("PBU001")),
+                other => panic!("unexpected {:?}", other),
+            }
+        };
+        assert_eq!(canceled.orig_cl_ord_id, to_array("B1"));
+        assert_eq!(canceled.leaves_qty, 0);
+
+        buyer.send(&Request::Logout);
+        assert!(buyer.recv().is_none());
+
+        // stop 断开所有连接, 未读的报告读完后即结束
+        gateway.stop();
+        while let Some(response) = seller.recv() {
+            assert!(matches!(response, Response::Report { .. }));
+        }
+        let (result, mut engin) = running.join().unwrap();
+        result.unwrap();
+        engin.close().unwrap();
+    });
+}
+
+#[test]
+fn test_resend_after_reconnect() {
+    let path = std::env::temp_dir().join(format!("test_resend_{}.bin", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let report_log = ReportLog::open(&path, SyncPolicy::PerBatch).unwrap();
+    let mut credentials = HashMap::new();
+    credentials.insert(to_array("PBU001"), "buyer".to_string());
+    credentials.insert(to_array("PBU002"), "seller".to_string());
+    // SEC002 的报告单独编号
+    let mut streams = HashMap::new();
+    streams.insert(to_array("SEC002"), 1);
+    let gateway = Gateway::bind("127.0.0.1:0", GatewayConfig { credentials, report_log : Some(report_log.clone()), streams }).unwrap();
+    let addr = gateway.local_addr().unwrap();
+    let engin = Engin::new(gateway.sink());
+
+    let gateway = &gateway;
+    thread::scope(|scope| {
+        let running = scope.spawn(move || {
+            let result = gateway.run(&engin);
+            (result, engin)
+        });
+
+        let (mut buyer, response) = Client::logon_from(addr, "PBU001", "buyer", vec![(0, 1), (1, 1)]);
+        assert_eq!(response, Response::LogonAccepted(to_array("PBU001")));
+        buyer.order("B1", K_BUY, 100, 10);
+        assert!(matches!(buyer.recv_report(), OutputMessage::ExecutionReport(report) if report.exec_type == K_EXEC_TYPE_NEW));
+        buyer.order_in("SEC002", "B3", K_BUY, 50, 10);
+        assert!(matches!(buyer.recv_report(), OutputMessage::ExecutionReport(report) if report.exec_type == K_EXEC_TYPE_NEW));
+        assert_eq!(buyer.next_seqs(), vec![(0, 2), (1, 2)]);
+        buyer.send(&Request::Logout);
+        assert!(buyer.recv().is_none());
+
+        // 买方断线期间两只证券都有成交
+        let (mut seller, _) = Client::logon(addr, "PBU002", "seller");
+        seller.order("S1", K_SELL, 100, 4);
+        seller.order_in("SEC002", "S2", K_SELL, 50, 3);
+        let mut trades = 0;
+        while trades < 2 {
+            if let OutputMessage::ExecutionReport(report) = seller.recv_report() {
+                trades += (report.exec_type == K_EXEC_TYPE_TRADE) as u32;
+            }
+        }
+
+        // 重连后每条流从客户端最后收到的序号之后重传, 之后的报告照常送达
+        let next_seqs = buyer.next_seqs();
+        let (mut reconnected, response) = Client::logon_from(addr, "PBU001", "buyer", next_seqs);
+        reconnected.seqs = buyer.seqs;
+        let mut buyer = reconnected;
+        assert_eq!(response, Response::LogonAccepted(to_array("PBU001")));
+        for (cl_ord_id, last_qty) in [("B1", 4), ("B3", 3)] {
+            match buyer.recv_report() {
+                OutputMessage::ExecutionReport(report) => assert_eq!((report.cl_ord_id, report.exec_type, report.last_qty), (to_array(cl_ord_id), K_EXEC_TYPE_TRADE, last_qty)),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        buyer.order("B2", K_BUY, 99, 1);
+        assert!(matches!(buyer.recv_report(), OutputMessage::ExecutionReport(report) if report.cl_ord_id == to_array("B2")));
+        assert_eq!(buyer.next_seqs(), vec![(0, 4), (1, 3)]);
+
+        gateway.stop();
+        let (result, mut engin) = running.join().unwrap();
+        result.unwrap();
+        engin.close().unwrap();
+    });
+
+    let pbu_id = to_array("PBU001");
+    assert_eq!((report_log.last_seq(&pbu_id, 0), report_log.last_seq(&pbu_id, 1)), (3, 2));
+    let exec_types : Vec<char> = report_log.range(&pbu_id, 0, 1, 3).unwrap().iter().map(|report| match decode(report).unwrap() {
+        OutputMessage::ExecutionReport(report) => report.exec_type,
+        other => panic!("unexpected {:?}", other),
+    }).collect();
+    assert_eq!(exec_types, vec![K_EXEC_TYPE_NEW, K_EXEC_TYPE_TRADE, K_EXEC_TYPE_NEW]);
+    std::fs::remove_file(&path).unwrap();
+}
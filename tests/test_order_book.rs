@@ -0,0 +1,130 @@
+use trading::order_book::{PriceOrderBook, BookBackend};
+use trading::order_book::auction_order::AuctionOrder;
+use trading::types::*;
+
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use rand::Rng;
+
+struct Order {
+    order_id : OrderID,
+    price : Price,
+    qty : Qty,
+}
+
+impl AuctionOrder for Order {
+    fn qty(&self) -> Qty {
+        self.qty
+    }
+    fn price(&self) -> Price {
+        self.price
+    }
+    fn order_id(&self) -> OrderID {
+        self.order_id
+    }
+}
+
+enum Op {
+    Insert(Rc<Order>),
+    Remove(Price, OrderID),
+    Consume(Qty, Price),
+}
+
+const MIN_PRICE : Price = 9000;
+const MAX_PRICE : Price = 11000;
+
+fn gen_ops(count : usize) -> Vec<Op> {
+    let mut rng = rand::thread_rng();
+    let mut ops = Vec::new();
+    let mut resting : Vec<(Price, OrderID)> = Vec::new();
+    for order_id in 0..count {
+        let rand_num = rng.gen_range(0..100);
+        if rand_num < 50 || resting.is_empty() {
+            let price = rng.gen_range(MIN_PRICE..=MAX_PRICE);
+            resting.push((price, order_id as OrderID));
+            ops.push(Op::Insert(Rc::new(Order { order_id : order_id as OrderID, price, qty : rng.gen_range(1..=1000) })));
+        }
+        else if rand_num < 80 {
+            let (price, order_id) = resting.swap_remove(rng.gen_range(0..resting.len()));
+            ops.push(Op::Remove(price, order_id));
+        }
+        else {
+            ops.push(Op::Consume(rng.gen_range(1..=3000), rng.gen_range(MIN_PRICE..=MAX_PRICE)));
+        }
+    }
+    ops
+}
+
+type Outcome = Vec<(OrderID, Qty, Qty)>;
+
+fn apply(book : &mut PriceOrderBook<Order>, op : &Op) -> (Qty, Outcome) {
+    match op {
+        Op::Insert(order) => {
+            book.insert_order(order.clone());
+            (0, Vec::new())
+        },
+        Op::Remove(price, order_id) => match book.remove_order(*price, *order_id) {
+            Some(order) => (0, vec![(order.orig_order().order_id(), order.consumed_qty(), order.leaves_qty())]),
+            None => (0, Vec::new()),
+        },
+        Op::Consume(qty, price) => {
+            let (leaves_qty, consumed) = book.consume_order(*qty, *price);
+            (leaves_qty, consumed.iter().map(|c| (c.orig_order().order_id(), c.consumed_qty(), c.leaves_qty())).collect())
+        }
+    }
+}
+
+fn levels(book : &PriceOrderBook<Order>) -> Vec<(i64, u64)> {
+    let mut result = Vec::new();
+    let mut iter = book.price_iter();
+    while let Some(level) = iter.next() {
+        result.push(level);
+    }
+    result
+}
+
+fn books(high_priority : bool) -> (PriceOrderBook<Order>, PriceOrderBook<Order>) {
+    let ladder = BookBackend::Ladder { min_price : MIN_PRICE, max_price : MAX_PRICE, tick : 1 };
+    if high_priority {
+        (PriceOrderBook::create_high_price_priority_order_book(),
+         PriceOrderBook::create_high_price_priority_order_book_with_backend(ladder))
+    }
+    else {
+        (PriceOrderBook::create_low_price_priority_order_book(),
+         PriceOrderBook::create_low_price_priority_order_book_with_backend(ladder))
+    }
+}
+
+#[test]
+fn test_ladder_same_as_tree() {
+    for high_priority in [true, false] {
+        let ops = gen_ops(20000);
+        let (mut tree, mut ladder) = books(high_priority);
+        for op in ops.iter() {
+            assert_eq!(apply(&mut tree, op), apply(&mut ladder, op));
+        }
+        assert_eq!(levels(&tree), levels(&ladder));
+        let tree_orders : Vec<(OrderID, Qty)> = tree.order_iter().map(|o| (o.orig_order().order_id(), o.leaves_qty())).collect();
+        let ladder_orders : Vec<(OrderID, Qty)> = ladder.order_iter().map(|o| (o.orig_order().order_id(), o.leaves_qty())).collect();
+        assert_eq!(tree_orders, ladder_orders);
+    }
+}
+
+fn time(book : &mut PriceOrderBook<Order>, ops : &[Op]) -> Duration {
+    let now = Instant::now();
+    for op in ops {
+        apply(book, op);
+    }
+    now.elapsed()
+}
+
+// 只输出两种簿的耗时, 结果受机器负载影响, 不参与常规测试; 用 cargo test -- --ignored 手动运行
+#[test]
+#[ignore]
+fn test_ladder_performance() {
+    let ops = gen_ops(1000000);
+    let (mut tree, mut ladder) = books(false);
+    let tree_time = time(&mut tree, &ops);
+    let ladder_time = time(&mut ladder, &ops);
+    println!("tree backend {} ms, ladder backend {} ms", tree_time.as_millis(), ladder_time.as_millis());
+}